@@ -0,0 +1 @@
+require('./components/button')
@@ -0,0 +1,8 @@
+for (const lang of ['en', 'fr']) {
+  const messages = require('./locales/' + lang + '.json')
+  if (messages.lang !== lang) throw new Error(`wrong messages for ${lang}`)
+}
+const page = name => require(`./pages/${name}`)
+for (const name of ['home', 'about', 'home.js', 'about/index']) {
+  if (page(name).name !== name.split(/[./]/)[0]) throw new Error(`wrong page for ${name}`)
+}
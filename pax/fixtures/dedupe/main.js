@@ -0,0 +1,3 @@
+require('a')
+require('b')
+require('c')
@@ -0,0 +1,6 @@
+import vendor from 'vendor'
+import format from './lib/format.js'
+
+import('./settings.mjs').then(settings => {
+  console.log(format(vendor.name, 'admin', settings.value))
+})
@@ -0,0 +1,6 @@
+const vendor = require('vendor')
+const format = require('./lib/format')
+const util = require('./lib/util')
+const greeting = require('./greeting')
+
+console.log(format(vendor.name, greeting, util.twice(1)))
@@ -0,0 +1 @@
+module.exports = (...parts) => parts.join(' ')
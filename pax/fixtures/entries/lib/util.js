@@ -0,0 +1 @@
+exports.twice = x => x * 2
@@ -0,0 +1,3 @@
+const b = require('./b')
+
+console.log('a sees', b.name)
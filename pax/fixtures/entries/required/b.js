@@ -0,0 +1,3 @@
+exports.name = 'b'
+
+console.log('b runs')
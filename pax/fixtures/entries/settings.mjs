@@ -0,0 +1,3 @@
+import util from './lib/util.js'
+
+export const value = util.twice(2)
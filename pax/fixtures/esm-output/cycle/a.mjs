@@ -0,0 +1,5 @@
+import {b} from './b.mjs'
+
+export const name = 'a'
+
+b()
@@ -0,0 +1,5 @@
+import {name} from './a.mjs'
+
+export function b() {
+  console.log('b sees', name)
+}
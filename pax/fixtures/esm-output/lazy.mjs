@@ -0,0 +1,3 @@
+import path from 'path'
+
+export const value = path.basename('/lazy/value')
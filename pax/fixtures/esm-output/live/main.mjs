@@ -0,0 +1,4 @@
+import {count, increment} from './counter.mjs'
+
+increment()
+console.log('count', count)
@@ -0,0 +1,12 @@
+import {readFileSync} from 'fs'
+import {double} from './lib.mjs'
+export * from './reexport.mjs'
+
+export const answer = double(21)
+export const canRead = typeof readFileSync === 'function'
+export function loadLazy() {
+  return import('./lazy.mjs')
+}
+export default function greet(name) {
+  return `hi ${name}`
+}
@@ -0,0 +1,12 @@
+const routes = require.context('./routes', true, /\.js$/)
+const keys = routes.keys().join()
+if (keys !== './_draft.js,./about.js,./admin/users.js,./home.js') throw new Error(`keys: ${keys}`)
+if (routes('./admin/users.js').name !== 'users') throw new Error('wrong module')
+
+const top = require.context(
+  './routes/',
+  false,
+  /^\.\/[A-Z]+\.js$/i
+)
+if (top.keys().join() !== './about.js,./home.js') throw new Error(`top-level keys: ${top.keys()}`)
+if (top.resolve('./home.js') !== require.resolve('./routes/home.js')) throw new Error('wrong filename')
@@ -0,0 +1,14 @@
+const lazy = import.meta.glob('./routes/*.js')
+const eager = import.meta.glob([
+  './routes/**/*.js',
+  '!./routes/_*.js',
+], { eager: true })
+
+const lazyKeys = Object.keys(lazy).join()
+if (lazyKeys !== './routes/_draft.js,./routes/about.js,./routes/home.js') throw new Error(`lazy keys: ${lazyKeys}`)
+const eagerKeys = Object.keys(eager).join()
+if (eagerKeys !== './routes/about.js,./routes/admin/users.js,./routes/home.js') throw new Error(`eager keys: ${eagerKeys}`)
+if (eager['./routes/admin/users.js'].default.name !== 'users') throw new Error('wrong eager module')
+lazy['./routes/about.js']().then(about => {
+  if (about.default.name !== 'about') throw new Error('wrong lazy module')
+})
@@ -0,0 +1,3 @@
+require('./c.mjs')
+
+module.exports = 'legacy'
@@ -0,0 +1,6 @@
+import {count, inc} from './c.mjs'
+import legacy from './legacy.js'
+import {next} from './next.mjs'
+
+inc()
+console.log(legacy, 'count', count, 'next', next())
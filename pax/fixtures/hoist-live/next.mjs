@@ -0,0 +1,6 @@
+import {count} from './c.mjs'
+
+// declares `count` as well, so it reads the import when it starts
+export function next() {
+  return [count].map(count => count + 1)[0]
+}
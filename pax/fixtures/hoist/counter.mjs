@@ -0,0 +1,10 @@
+import {label} from './shapes.mjs'
+
+const value = 'counter'
+export let count = 0
+export function increment() {
+  count += 1
+}
+export default function greet(name) {
+  return `${label} ${value} ${name}`
+}
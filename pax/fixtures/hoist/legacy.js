@@ -0,0 +1,3 @@
+const {shared} = require('./shared.mjs')
+
+module.exports = `legacy ${shared}`
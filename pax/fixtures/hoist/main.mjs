@@ -0,0 +1,13 @@
+import greet, {count, increment} from './counter.mjs'
+import * as shapes from './shapes.mjs'
+import legacy from './legacy.js'
+import {shared} from './shared.mjs'
+export {area as squareArea} from './shapes.mjs'
+
+const value = 'main'
+increment()
+increment()
+const result = {value, count, shapes: Object.keys(shapes).join(',')}
+
+console.log(greet(value), result.count, result.shapes, shapes.area(3), legacy, shared)
+export {value}
@@ -0,0 +1,5 @@
+const value = 'shapes'
+export const label = value
+export function area(side) {
+  return side * side
+}
@@ -0,0 +1,2 @@
+const value = 'shared'
+export const shared = value
@@ -0,0 +1 @@
+module.exports = 'lib/local/index.js'
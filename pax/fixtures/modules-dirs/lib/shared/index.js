@@ -0,0 +1 @@
+module.exports = 'lib/shared/index.js'
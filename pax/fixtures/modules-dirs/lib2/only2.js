@@ -0,0 +1 @@
+module.exports = 'lib2/only2.js'
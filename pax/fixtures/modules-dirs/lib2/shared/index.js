@@ -0,0 +1 @@
+module.exports = 'lib2/shared/index.js'
@@ -0,0 +1,10 @@
+#!/usr/bin/env node
+/* eslint-disable */
+"use strict";
+
+const RAW_RUNTIME_STATE =
+'{"enableTopLevelFallback": false, "fallbackExclusionList": [], "fallbackPool": [], "ignorePatternData": null, "packageRegistryData": [[null, [[null, {"packageLocation": "./", "packageDependencies": [["util-pkg", "npm:1.0.0"]], "linkType": "SOFT"}]]], ["util-pkg", [["npm:1.0.0", {"packageLocation": "./.yarn/unplugged/util-npm-1.0.0-5e6f7a/node_modules/util-pkg/", "packageDependencies": [["util-pkg", "npm:1.0.0"]], "linkType": "HARD"}]]]]}';
+
+function $$SETUP_STATE(hydrateRuntimeState, basePath) {
+  return hydrateRuntimeState(JSON.parse(RAW_RUNTIME_STATE), {basePath: basePath || __dirname});
+}
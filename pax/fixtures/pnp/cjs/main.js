@@ -0,0 +1 @@
+require('util-pkg')
@@ -0,0 +1 @@
+require('left-pad')
@@ -0,0 +1 @@
+module.exports = {filename: __filename}
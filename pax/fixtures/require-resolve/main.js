@@ -0,0 +1,2 @@
+const path = require.resolve('./dep')
+if (path !== require('./dep').filename) throw new Error(`require.resolve('./dep') returned ${path}`)
@@ -0,0 +1,3 @@
+import dep from './dep'
+const path = require.resolve('./dep.js')
+if (path !== dep.filename) throw new Error(`require.resolve('./dep.js') returned ${path}`)
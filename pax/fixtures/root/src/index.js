@@ -0,0 +1,5 @@
+const util = require('./lib/util')
+const dep = require('dep')
+console.log(__filename, __dirname, require.resolve('./lib/util'))
+console.log(util.filename, util.dirname)
+console.log(dep)
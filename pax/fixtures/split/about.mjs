@@ -0,0 +1,6 @@
+import { counter } from './shared.mjs'
+import commonObject from './common.mjs'
+import format from './format.js'
+
+export const title = format('About', counter)
+export const common = commonObject
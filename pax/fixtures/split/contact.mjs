@@ -0,0 +1,4 @@
+import commonObject from './common.mjs'
+
+export const title = 'Contact'
+export const common = commonObject
@@ -0,0 +1 @@
+module.exports = (title, n) => `${title} ${n}`
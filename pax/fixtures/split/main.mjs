@@ -0,0 +1,10 @@
+import { counter, increment } from './shared.mjs'
+
+increment()
+Promise.all([import('./about.mjs'), import('./contact.mjs'), import('./shared.mjs')]).then(([about, contact, shared]) => {
+  if (about.title !== 'About 1') throw new Error(`wrong title: ${about.title}`)
+  if (contact.title !== 'Contact') throw new Error(`wrong title: ${contact.title}`)
+  if (about.common !== contact.common) throw new Error('common.mjs ran twice')
+  if (shared.counter !== counter) throw new Error('shared.mjs ran twice')
+  console.log('ok')
+})
@@ -0,0 +1,6 @@
+import greeting from './greeting.js'
+
+export const name = 'widget'
+export default function render() {
+  return greeting('widget')
+}
@@ -0,0 +1,2 @@
+~function(register) {
+  register(Pax => {
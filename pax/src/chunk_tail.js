@@ -0,0 +1,2 @@
+  })
+}(typeof document !== "undefined" && document.currentScript && document.currentScript.pax ? f => f(document.currentScript.pax) : f => { module.exports = f })
@@ -0,0 +1,4 @@
+  })
+}(f => { __pax_chunk = f })
+var __pax_chunk
+export default __pax_chunk
@@ -0,0 +1,541 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+use esparse::lex::{self, Tt};
+use regex::{self, Regex};
+
+/// The argument of a `require()` call, when it's a string or is built from one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request<'s> {
+    /// A string literal or a template literal without substitutions.
+    Static(Cow<'s, str>),
+    /// A string with a static prefix and dynamic parts.
+    Context(ContextRequest),
+}
+
+/// A partially dynamic module name, like `'./locales/' + lang + '.json'` or `` `./pages/${name}` ``, which refers to every file that matches its static parts (webpack calls the module that maps those files a context module).
+///
+/// The static parts are in `parts`, with a dynamic part between each pair; the first part is the prefix, and the last is the suffix (possibly empty).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ContextRequest {
+    pub parts: Vec<String>,
+}
+
+impl ContextRequest {
+    /// The directory that the request refers to files in, i.e., the prefix up to its last '/', or `None` if the prefix isn't an explicitly relative path.
+    pub fn dir(&self) -> Option<&str> {
+        let prefix = &self.parts[0];
+        if !(prefix.starts_with("./") || prefix.starts_with("../")) {
+            return None
+        }
+        prefix.rfind('/').map(|i| &prefix[..i + 1])
+    }
+
+    /// Whether `name` could be the value of the request, i.e., whether it contains each static part in order, starting with the prefix and ending with the suffix.
+    pub fn matches(&self, name: &str) -> bool {
+        let (first, rest) = self.parts.split_first().unwrap();
+        let (last, middle) = rest.split_last().unwrap();
+        if !name.starts_with(first.as_str()) {
+            return false
+        }
+        let mut name = &name[first.len()..];
+        for part in middle {
+            match name.find(part.as_str()) {
+                Some(i) => name = &name[i + part.len()..],
+                None => return false,
+            }
+        }
+        name.ends_with(last.as_str())
+    }
+}
+
+impl fmt::Display for ContextRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.parts.join("*"))
+    }
+}
+
+/// A call to `import.meta.glob()` or `require.context()`, which refers to every file that matches a pattern and evaluates to their modules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Glob {
+    /// `import.meta.glob(patterns, { eager })`, like Vite's: each pattern is relative to the module and is excluded if it starts with '!'.
+    ///
+    /// https://vitejs.dev/guide/features.html#glob-import
+    ImportMeta { patterns: Vec<String>, eager: bool },
+    /// `require.context(dir, recursive, regexp)`, like webpack's: `regexp` is matched against paths relative to `dir`, like './a.js'.
+    ///
+    /// https://webpack.js.org/guides/dependency-management/#requirecontext
+    RequireContext { dir: String, recursive: bool, regexp: String, flags: String },
+}
+
+/// A `Glob` and the range of source code that calls it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobCall {
+    pub range: Range<usize>,
+    pub glob: Glob,
+}
+
+impl Glob {
+    /// Converts the `RegExp` of a `require.context()` call to a `Regex`.
+    pub fn regex(regexp: &str, flags: &str) -> Result<Regex, regex::Error> {
+        let mut source = String::new();
+        for flag in flags.chars() {
+            if matches!(flag, 'i' | 'm' | 's') {
+                source.push_str("(?");
+                source.push(flag);
+                source.push(')');
+            }
+        }
+        source.push_str(regexp);
+        Regex::new(&source)
+    }
+
+    /// Converts a glob pattern to a `Regex` that matches the whole pattern: `*` and `?` match any characters but '/', `**/` matches any number of directories, and `{a,b}` matches either alternative.
+    pub fn glob_regex(pattern: &str) -> Result<Regex, regex::Error> {
+        let mut source = "^".to_owned();
+        let mut braces = 0;
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        source.push_str("(?:.*/)?");
+                    } else {
+                        source.push_str(".*");
+                    }
+                }
+                '*' => source.push_str("[^/]*"),
+                '?' => source.push_str("[^/]"),
+                '{' => {
+                    braces += 1;
+                    source.push_str("(?:");
+                }
+                ',' if braces > 0 => source.push('|'),
+                '}' if braces > 0 => {
+                    braces -= 1;
+                    source.push(')');
+                }
+                '[' => {
+                    source.push('[');
+                    if chars.peek() == Some(&'!') {
+                        chars.next();
+                        source.push('^');
+                    }
+                    for c in chars.by_ref() {
+                        if c == ']' { break }
+                        if c == '\\' || c == '[' { source.push('\\') }
+                        source.push(c);
+                    }
+                    source.push(']');
+                }
+                _ => source.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        source.push('$');
+        Regex::new(&source)
+    }
+
+    /// The directory that a glob pattern refers to files in, i.e., the pattern up to the last '/' before its first special character, or `None` if the pattern isn't an explicitly relative path.
+    pub fn glob_dir(pattern: &str) -> Option<&str> {
+        if !(pattern.starts_with("./") || pattern.starts_with("../")) {
+            return None
+        }
+        let end = pattern.find(|c| matches!(c, '*' | '?' | '[' | '{')).unwrap_or(pattern.len());
+        pattern[..end].rfind('/').map(|i| &pattern[..i + 1])
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Glob::ImportMeta { ref patterns, .. } => {
+                write!(f, "import.meta.glob('{}')", patterns.join("', '"))
+            }
+            Glob::RequireContext { ref dir, recursive, ref regexp, ref flags } => {
+                write!(f, "require.context('{}', {}, /{}/{})", dir, recursive, regexp, flags)
+            }
+        }
+    }
+}
+
+/// Parses the arguments of an `import.meta.glob()` call and the closing parenthesis, after the opening parenthesis: a string or an array of strings, then optionally an object with an `eager` property. Returns `None` and stops at the first token it doesn't understand if the arguments aren't like that.
+pub fn parse_import_meta_glob<'f, 's>(lex: &mut lex::Lexer<'f, 's>) -> Result<Option<Glob>, lex::ParseStrLitError> {
+    let mut patterns = Vec::new();
+    match lex.here().tt {
+        Tt::Lbracket => {
+            lex.advance();
+            loop {
+                match lex.here().tt {
+                    Tt::Rbracket => break,
+                    _ => match parse_str(lex)? {
+                        Some(pattern) => patterns.push(pattern),
+                        None => return Ok(None),
+                    },
+                }
+                match lex.here().tt {
+                    Tt::Comma => {
+                        lex.advance();
+                    }
+                    Tt::Rbracket => break,
+                    _ => return Ok(None),
+                }
+            }
+            lex.advance();
+        }
+        _ => match parse_str(lex)? {
+            Some(pattern) => patterns.push(pattern),
+            None => return Ok(None),
+        },
+    }
+
+    let mut eager = false;
+    if let Tt::Comma = lex.here().tt {
+        lex.advance();
+        if let Tt::Lbrace = lex.here().tt {
+            lex.advance();
+            loop {
+                match lex.here().tt {
+                    Tt::Rbrace => break,
+                    Tt::Id("eager") => {
+                        lex.advance();
+                    }
+                    Tt::StrLitSgl(s) |
+                    Tt::StrLitDbl(s) if lex::str_lit_value(s)? == "eager" => {
+                        lex.advance();
+                    }
+                    _ => return Ok(None),
+                }
+                if let Tt::Colon = lex.here().tt {
+                    lex.advance();
+                } else {
+                    return Ok(None)
+                }
+                eager = match lex.advance().tt {
+                    Tt::True => true,
+                    Tt::False => false,
+                    _ => return Ok(None),
+                };
+                match lex.here().tt {
+                    Tt::Comma => {
+                        lex.advance();
+                    }
+                    Tt::Rbrace => break,
+                    _ => return Ok(None),
+                }
+            }
+            lex.advance();
+        }
+    }
+    if let Tt::Rparen = lex.here().tt {
+        lex.advance();
+        Ok(Some(Glob::ImportMeta { patterns, eager }))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parses the arguments of a `require.context()` call and the closing parenthesis, after the opening parenthesis: a directory and, optionally, whether to include subdirectories (default true), a regular expression literal that paths must match (default `/^\.\/.*$/`), and the mode 'sync'. Returns `None` and stops at the first token it doesn't understand if the arguments aren't like that.
+pub fn parse_require_context<'f, 's>(lex: &mut lex::Lexer<'f, 's>) -> Result<Option<Glob>, lex::ParseStrLitError> {
+    let dir = match parse_str(lex)? {
+        Some(dir) => dir,
+        None => return Ok(None),
+    };
+    let mut recursive = true;
+    let mut regexp = r"^\./.*$".to_owned();
+    let mut flags = String::new();
+    for i in 0..3 {
+        match lex.here().tt {
+            Tt::Rparen => break,
+            Tt::Comma => {
+                lex.advance();
+            }
+            _ => return Ok(None),
+        }
+        match (i, lex.here().tt) {
+            (_, Tt::Rparen) => break,
+            (0, Tt::True) => recursive = true,
+            (0, Tt::False) => recursive = false,
+            (1, Tt::RegExpLit(source, source_flags)) => {
+                regexp = source[1..source.len() - source_flags.len() - 1].to_owned();
+                flags = source_flags.to_owned();
+            }
+            (2, Tt::StrLitSgl(s)) |
+            (2, Tt::StrLitDbl(s)) if lex::str_lit_value(s)? == "sync" => {}
+            _ => return Ok(None),
+        }
+        lex.advance();
+    }
+    if let Tt::Rparen = lex.here().tt {
+        lex.advance();
+        Ok(Some(Glob::RequireContext { dir, recursive, regexp, flags }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_str<'f, 's>(lex: &mut lex::Lexer<'f, 's>) -> Result<Option<String>, lex::ParseStrLitError> {
+    match lex.here().tt {
+        Tt::StrLitSgl(s) |
+        Tt::StrLitDbl(s) |
+        Tt::TemplateNoSub(s) => {
+            lex.advance();
+            Ok(Some(lex::str_lit_value(s)?.into_owned()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Parses the argument of a `require()` call and the closing parenthesis, after the opening parenthesis. Returns `None` and stops at the first token it doesn't understand if the argument isn't a string or built from one by concatenation or template substitution.
+pub fn parse_request<'f, 's>(lex: &mut lex::Lexer<'f, 's>) -> Result<Option<Request<'s>>, lex::ParseStrLitError> {
+    let mut parts = vec![String::new()];
+    match lex.here().tt {
+        Tt::StrLitSgl(s) |
+        Tt::StrLitDbl(s) |
+        Tt::TemplateNoSub(s) => {
+            lex.advance();
+            if let Tt::Rparen = lex.here().tt {
+                lex.advance();
+                return Ok(Some(Request::Static(lex::str_lit_value(s)?)))
+            }
+            parts[0].push_str(&lex::str_lit_value(s)?);
+        }
+        Tt::TemplateStart(_) => {
+            if !parse_template(lex, &mut parts)? {
+                return Ok(None)
+            }
+        }
+        _ => return Ok(None),
+    }
+
+    loop {
+        match lex.here().tt {
+            Tt::Rparen => {
+                lex.advance();
+                break
+            }
+            Tt::Plus => {
+                lex.advance();
+            }
+            _ => return Ok(None),
+        }
+        match lex.here().tt {
+            Tt::StrLitSgl(s) |
+            Tt::StrLitDbl(s) |
+            Tt::TemplateNoSub(s) => {
+                lex.advance();
+                parts.last_mut().unwrap().push_str(&lex::str_lit_value(s)?);
+            }
+            Tt::TemplateStart(_) => {
+                if !parse_template(lex, &mut parts)? {
+                    return Ok(None)
+                }
+            }
+            _ => {
+                if !skip_operand(lex) {
+                    return Ok(None)
+                }
+                push_dynamic(&mut parts);
+            }
+        }
+    }
+
+    if parts.len() == 1 {
+        Ok(Some(Request::Static(Cow::Owned(parts.pop().unwrap()))))
+    } else {
+        Ok(Some(Request::Context(ContextRequest { parts })))
+    }
+}
+
+fn push_dynamic(parts: &mut Vec<String>) {
+    // consecutive dynamic parts match the same names as one
+    if parts.len() == 1 || !parts.last().unwrap().is_empty() {
+        parts.push(String::new());
+    }
+}
+
+/// Parses a template literal with substitutions, appending its static parts to `parts`. Returns false if the template is unterminated.
+fn parse_template<'f, 's>(lex: &mut lex::Lexer<'f, 's>, parts: &mut Vec<String>) -> Result<bool, lex::ParseStrLitError> {
+    let start = match lex.advance().tt {
+        Tt::TemplateStart(s) => s,
+        _ => unreachable!(),
+    };
+    // strip `${` so that only the backtick is left as a delimiter
+    parts.last_mut().unwrap().push_str(&lex::str_lit_value(&start[..start.len() - 1])?);
+    let mut depth = 0usize;
+    loop {
+        match lex.advance().tt {
+            Tt::TemplateStart(_) => depth += 1,
+            Tt::TemplateMiddle(s) if depth == 0 => {
+                push_dynamic(parts);
+                parts.last_mut().unwrap().push_str(&lex::str_lit_value(&s[..s.len() - 1])?);
+            }
+            Tt::TemplateEnd(s) if depth == 0 => {
+                push_dynamic(parts);
+                parts.last_mut().unwrap().push_str(&lex::str_lit_value(s)?);
+                return Ok(true)
+            }
+            Tt::TemplateEnd(_) => depth -= 1,
+            Tt::Eof => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+/// Skips an identifier or a number, followed by any property accesses and calls, like `lang`, `page.name`, or `names[i]()`. Returns false if there's no such operand.
+fn skip_operand<'f, 's>(lex: &mut lex::Lexer<'f, 's>) -> bool {
+    match lex.here().tt {
+        Tt::Id(_) |
+        Tt::NumLitBin(_) |
+        Tt::NumLitOct(_) |
+        Tt::NumLitDec(_) |
+        Tt::NumLitHex(_) => {
+            lex.advance();
+        }
+        Tt::Lparen => {
+            if !skip_group(lex) { return false }
+        }
+        _ => return false,
+    }
+    loop {
+        match lex.here().tt {
+            Tt::Dot => {
+                lex.advance();
+                match lex.here().tt {
+                    Tt::Id(_) => {
+                        lex.advance();
+                    }
+                    _ => return false,
+                }
+            }
+            Tt::Lparen |
+            Tt::Lbracket => {
+                if !skip_group(lex) { return false }
+            }
+            _ => return true,
+        }
+    }
+}
+
+/// Skips a parenthesized or bracketed group and everything in it.
+fn skip_group<'f, 's>(lex: &mut lex::Lexer<'f, 's>) -> bool {
+    let mut depth = 0usize;
+    loop {
+        match lex.advance().tt {
+            Tt::Lparen |
+            Tt::Lbracket |
+            Tt::Lbrace => depth += 1,
+            Tt::Rparen |
+            Tt::Rbracket |
+            Tt::Rbrace => {
+                depth -= 1;
+                if depth == 0 { return true }
+            }
+            Tt::Eof => return false,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(source: &str) -> Option<Request<'_>> {
+        let mut lex = lex::Lexer::new_unnamed(source);
+        parse_request(&mut lex).unwrap()
+    }
+    fn context(parts: &[&str]) -> Option<Request<'static>> {
+        Some(Request::Context(ContextRequest {
+            parts: parts.iter().map(|&s| s.to_owned()).collect(),
+        }))
+    }
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(parse("'./a')"), Some(Request::Static(Cow::Borrowed("./a"))));
+        assert_eq!(parse("`./a`)"), Some(Request::Static(Cow::Borrowed("./a"))));
+        assert_eq!(parse("'./a' + 'b')"), Some(Request::Static(Cow::Borrowed("./ab"))));
+        assert_eq!(parse("'./locales/' + lang + '.json')"), context(&["./locales/", ".json"]));
+        assert_eq!(parse("'./locales/' + lang)"), context(&["./locales/", ""]));
+        assert_eq!(parse("'./' + a.b[c](d) + x + '-' + (y) + '.js')"), context(&["./", "-", ".js"]));
+        assert_eq!(parse("`./pages/${name}`)"), context(&["./pages/", ""]));
+        assert_eq!(parse("`./pages/${a}/${`${b}`}.js`)"), context(&["./pages/", "/", ".js"]));
+        assert_eq!(parse("`./pages/${name}` + '.js')"), context(&["./pages/", ".js"]));
+
+        assert_eq!(parse("'./a', b)"), None);
+        assert_eq!(parse("'./a' + b * 2)"), None);
+        assert_eq!(parse("'./a' + b ? 'c' : 'd')"), None);
+        assert_eq!(parse("a + './b')"), None);
+        assert_eq!(parse("`./a/${b}"), None);
+    }
+
+    #[test]
+    fn test_parse_globs() {
+        let glob = |source: &str| parse_import_meta_glob(&mut lex::Lexer::new_unnamed(source)).unwrap();
+        let patterns = |patterns: &[&str], eager| Some(Glob::ImportMeta {
+            patterns: patterns.iter().map(|&s| s.to_owned()).collect(),
+            eager,
+        });
+        assert_eq!(glob("'./a/*.js')"), patterns(&["./a/*.js"], false));
+        assert_eq!(glob("['./a/*.js', '!./a/b.js',], {eager: true})"), patterns(&["./a/*.js", "!./a/b.js"], true));
+        assert_eq!(glob("`./a/*.js`, {'eager': false})"), patterns(&["./a/*.js"], false));
+        assert_eq!(glob("'./a/*.js', {import: 'default'})"), None);
+        assert_eq!(glob("pattern)"), None);
+
+        let context = |source: &str| parse_require_context(&mut lex::Lexer::new_unnamed(source)).unwrap();
+        let dir = |dir: &str, recursive, regexp: &str, flags: &str| Some(Glob::RequireContext {
+            dir: dir.to_owned(),
+            recursive,
+            regexp: regexp.to_owned(),
+            flags: flags.to_owned(),
+        });
+        assert_eq!(context("'./a')"), dir("./a", true, r"^\./.*$", ""));
+        assert_eq!(context("'./a', false)"), dir("./a", false, r"^\./.*$", ""));
+        assert_eq!(context(r"'./a', true, /\.vue$/i, 'sync')"), dir("./a", true, r"\.vue$", "i"));
+        assert_eq!(context("'./a', true, /x/, 'lazy')"), None);
+        assert_eq!(context("dir, true)"), None);
+    }
+
+    #[test]
+    fn test_glob_regex() {
+        let matches = |pattern: &str, name: &str| Glob::glob_regex(pattern).unwrap().is_match(name);
+        assert!(matches("./a/*.js", "./a/b.js"));
+        assert!(!matches("./a/*.js", "./a/b/c.js"));
+        assert!(!matches("./a/*.js", "./a/b.jsx"));
+        assert!(matches("./a/**/*.js", "./a/b.js"));
+        assert!(matches("./a/**/*.js", "./a/b/c/d.js"));
+        assert!(matches("./a/?.{js,ts}", "./a/b.ts"));
+        assert!(!matches("./a/?.{js,ts}", "./a/bc.ts"));
+        assert!(matches("./a/[!_]*.js", "./a/b.js"));
+        assert!(!matches("./a/[!_]*.js", "./a/_b.js"));
+
+        assert_eq!(Glob::glob_dir("./a/b/*.js"), Some("./a/b/"));
+        assert_eq!(Glob::glob_dir("../a/**/*.js"), Some("../a/"));
+        assert_eq!(Glob::glob_dir("./{a,b}/*.js"), Some("./"));
+        assert_eq!(Glob::glob_dir("/a/*.js"), None);
+
+        assert!(Glob::regex(r"^\./[a-z]+\.js$", "i").unwrap().is_match("./ABC.js"));
+        assert!(Glob::regex(r"\/b\.js$", "").unwrap().is_match("./a/b.js"));
+    }
+
+    #[test]
+    fn test_context_request() {
+        let request = ContextRequest { parts: vec!["./locales/".to_owned(), ".json".to_owned()] };
+        assert_eq!(request.dir(), Some("./locales/"));
+        assert!(request.matches("./locales/en.json"));
+        assert!(request.matches("./locales/en/US.json"));
+        assert!(request.matches("./locales/.json"));
+        assert!(!request.matches("./locales/en.js"));
+        assert!(!request.matches("./en.json"));
+
+        let request = ContextRequest { parts: vec!["../pages/page-".to_owned(), "/".to_owned(), "".to_owned()] };
+        assert_eq!(request.dir(), Some("../pages/"));
+        assert!(request.matches("../pages/page-a/b"));
+        assert!(!request.matches("../pages/page-a"));
+        assert!(!request.matches("../pages/a/b"));
+
+        assert_eq!(ContextRequest { parts: vec!["lodash/".to_owned(), "".to_owned()] }.dir(), None);
+    }
+}
@@ -1,12 +1,15 @@
 use std::fmt;
 use std::fmt::Write;
 use std::borrow::Cow;
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
 
 use esparse;
 use esparse::lex::{self, Tt};
 use esparse::skip::{self, Prec};
 
+use context::{self, ContextRequest, GlobCall};
+use idents;
+
 macro_rules! expected {
     ($lex:expr, $msg:expr) => {{
         return Err(Error {
@@ -49,6 +52,8 @@ impl<'s> ExportSpec<'s> {
 pub enum ParsedImport<'s> {
     Import(Import<'s>),
     ImportMeta,
+    /// `import("module")`
+    Dynamic(Cow<'s, str>),
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -105,6 +110,56 @@ pub struct CjsModule<'s> {
     pub source: String,
     pub source_suffix: String,
     pub deps: FnvHashSet<Cow<'s, str>>,
+    /// The modules that are only imported with `import()`, which aren't in `deps`.
+    pub dynamic_deps: FnvHashSet<Cow<'s, str>>,
+    pub contexts: FnvHashSet<ContextRequest>,
+    /// The calls to `import.meta.glob()` and `require.context()` in `source`.
+    pub globs: Vec<GlobCall>,
+    /// What the module exports, if it is an ES module.
+    pub exports: Option<ExportNames>,
+    /// How the module's imports and exports refer to other modules, if it is an ES module.
+    pub linkage: Option<Linkage>,
+    /// The expressions that read the imported bindings which references to them in `source` should be replaced with, if the imports aren't bound with `with`.
+    pub import_refs: FnvHashMap<String, String>,
+}
+
+/// The names that an ES module exports, as far as its own source says.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportNames {
+    pub names: Vec<String>,
+    /// The modules whose exports are re-exported with `export * from`.
+    pub all_from: Vec<String>,
+}
+
+/// How an ES module's bindings refer to the modules it imports from, which scope hoisting turns into direct references.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Linkage {
+    /// The modules that `import` and `export ... from` name, in the order they appear, which is the order they run in.
+    pub requests: Vec<String>,
+    pub imports: Vec<ImportBinding>,
+    /// The names that the module exports from its own bindings, with those bindings.
+    pub exports: Vec<(String, String)>,
+    /// The names that `export {...} from` re-exports.
+    pub reexports: Vec<ReExport>,
+}
+
+/// A binding that an `import` declaration makes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportBinding {
+    pub module: String,
+    /// The name that the binding imports, or `None` for `import * as`.
+    pub name: Option<String>,
+    pub bind: String,
+}
+
+/// A name that `export {...} from` takes from another module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReExport {
+    pub module: String,
+    /// The name in `module`.
+    pub name: String,
+    /// The name it is exported as.
+    pub export: String,
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -155,40 +210,112 @@ impl fmt::Display for ErrorKind {
     }
 }
 
-pub fn module_to_cjs<'f, 's>(lex: &mut lex::Lexer<'f, 's>, allow_require: bool) -> Result<CjsModule<'s>> {
+/// The error for the invalid string literal in the token at `span`.
+fn str_lit_error<'f, 's>(lex: &lex::Lexer<'f, 's>, span: esparse::ast::Span<'f, usize>, error: lex::ParseStrLitError) -> Error {
+    Error {
+        kind: ErrorKind::ParseStrLitError(error),
+        span: lex.recover_span(span).with_owned(),
+    }
+}
+
+/// The value of the string literal `source`, which is the token at `span`.
+fn str_lit_value<'f, 's>(lex: &lex::Lexer<'f, 's>, source: &'s str, span: esparse::ast::Span<'f, usize>) -> Result<Cow<'s, str>> {
+    lex::str_lit_value(source).map_err(|error| str_lit_error(lex, span, error))
+}
+
+/// Translates an ES module to a function body that CommonJS code can require. Imports are bound with `with`, unless `strict`: strict mode code, like an ES module bundle, can't use `with`, so references to them are left for `import_refs` to replace instead. If the module declares an imported name somewhere else too, or uses `eval`, that binding is a `const`, which is read when the module starts rather than whenever it's used.
+pub fn module_to_cjs<'f, 's>(lex: &mut lex::Lexer<'f, 's>, allow_require: bool, strict: bool) -> Result<CjsModule<'s>> {
     let mut source = String::new();
     let mut deps = FnvHashSet::default();
+    let mut dynamic_deps = FnvHashSet::default();
+    let mut contexts = FnvHashSet::default();
+    let mut globs = Vec::new();
     let mut imports = Vec::new();
     let mut exports = Vec::new();
+    let mut requests = Vec::new();
     // TODO source map lines won't match up when module string literal contains newlines
     loop {
         eat!(lex => tok { source.push_str(tok.ws_before) },
             Tt::Export => {
                 let export = parse_export(lex, &mut source)?;
+                match export {
+                    Export::AllFrom(_, ref module) |
+                    Export::NamedFrom(_, _, ref module) => requests.push(module.clone().into_owned()),
+                    _ => {}
+                }
                 exports.push(export);
             },
             Tt::Import => {
+                let start = source.len();
                 match parse_import(lex, &mut source)? {
                     ParsedImport::Import(import) => {
+                        requests.push(import.module.clone().into_owned());
                         imports.push(import);
                     }
-                    ParsedImport::ImportMeta => {}
+                    ParsedImport::Dynamic(module) => {
+                        dynamic_deps.insert(module);
+                    }
+                    ParsedImport::ImportMeta => {
+                        let here = lex.here();
+                        let meta_end = here.span.start - here.ws_before.len();
+                        let mut glob = None;
+                        eat!(lex,
+                            Tt::Dot => eat!(lex,
+                                Tt::Id("glob") => eat!(lex,
+                                    Tt::Lparen => {
+                                        glob = context::parse_import_meta_glob(lex).map_err(|error| str_lit_error(lex, tok.span, error))?;
+                                    },
+                                    _ => {},
+                                ),
+                                _ => {},
+                            ),
+                            _ => {},
+                        );
+
+                        let here = lex.here();
+                        let end_pos = here.span.start - here.ws_before.len();
+                        source.push_str(&lex.input()[meta_end..end_pos]);
+                        if let Some(glob) = glob {
+                            globs.push(GlobCall {
+                                range: start..source.len(),
+                                glob,
+                            });
+                        }
+                    }
                 }
             },
-            Tt::Id("require") if allow_require => {
+            // require.resolve() is bundled even in pure ES modules, which may get `require` from createRequire()
+            Tt::Id("require") => {
                 let start_pos = tok.span.start;
+                let mut glob = None;
                 eat!(lex,
-                    Tt::Lparen => eat!(lex,
-                        Tt::StrLitSgl(dep_source) |
-                        Tt::StrLitDbl(dep_source) => eat!(lex,
-                            Tt::Rparen => {
-                                deps.insert(match lex::str_lit_value(dep_source) {
-                                    Ok(dep) => dep,
-                                    Err(error) => return Err(Error {
-                                        kind: ErrorKind::ParseStrLitError(error),
-                                        span: lex.recover_span(tok.span).with_owned(),
-                                    }),
-                                });
+                    Tt::Lparen if allow_require => match context::parse_request(lex) {
+                        Ok(Some(context::Request::Static(dep))) => {
+                            deps.insert(dep);
+                        }
+                        Ok(Some(context::Request::Context(request))) => {
+                            contexts.insert(request);
+                        }
+                        Ok(None) => {}
+                        Err(error) => return Err(str_lit_error(lex, tok.span, error)),
+                    },
+                    Tt::Dot => eat!(lex,
+                        Tt::Id("resolve") => eat!(lex,
+                            Tt::Lparen => eat!(lex,
+                                Tt::StrLitSgl(dep_source) |
+                                Tt::StrLitDbl(dep_source) => eat!(lex,
+                                    Tt::Rparen => {
+                                        deps.insert(str_lit_value(lex, dep_source, tok.span)?);
+                                    },
+                                    _ => {},
+                                ),
+                                _ => {},
+                            ),
+                            _ => {},
+                        ),
+                        Tt::Id("context") if allow_require => eat!(lex,
+                            Tt::Lparen => {
+                                glob = context::parse_require_context(lex).map_err(|error| str_lit_error(lex, tok.span, error))?;
                             },
                             _ => {},
                         ),
@@ -199,7 +326,14 @@ pub fn module_to_cjs<'f, 's>(lex: &mut lex::Lexer<'f, 's>, allow_require: bool)
 
                 let here = lex.here();
                 let end_pos = here.span.start - here.ws_before.len();
+                let start = source.len();
                 source.push_str(&lex.input()[start_pos..end_pos]);
+                if let Some(glob) = glob {
+                    globs.push(GlobCall {
+                        range: start..source.len(),
+                        glob,
+                    });
+                }
             },
             Tt::Eof => break,
             _ => {
@@ -216,7 +350,7 @@ pub fn module_to_cjs<'f, 's>(lex: &mut lex::Lexer<'f, 's>, allow_require: bool)
         write!(source_prefix, "Object.defineProperty(exports, '__esModule', {{value: true}})\n").unwrap();
     }
 
-    if !imports.is_empty() {
+    if !imports.is_empty() && !strict {
         write!(source_prefix, "with (function() {{").unwrap();
         for (i, import) in imports.iter().enumerate() {
             write!(source_prefix, "\n  const __module{} = require._esModule({})", i, import.module_source).unwrap();
@@ -263,6 +397,34 @@ pub fn module_to_cjs<'f, 's>(lex: &mut lex::Lexer<'f, 's>, allow_require: bool)
         write!(source_prefix, "\n'use strict';\n").unwrap();
     }
 
+    let mut import_refs = FnvHashMap::default();
+    if !imports.is_empty() && strict {
+        let declared = idents::declared(&source);
+        for (i, import) in imports.iter().enumerate() {
+            write!(source_prefix, "const __module{} = require._esModule({})", i, import.module_source).unwrap();
+            let mut bind = |bind: &str, value: String| match declared {
+                Some(ref declared) if !declared.contains(bind) => {
+                    import_refs.insert(bind.to_owned(), value);
+                }
+                _ => write!(source_prefix, ", {} = {}", bind, value).unwrap(),
+            };
+            if let Some(name) = import.default_bind {
+                bind(name, format!("__module{}.default", i));
+            }
+            match import.binds {
+                Bindings::None => {}
+                Bindings::NameSpace(name) => bind(name, format!("__module{}", i)),
+                Bindings::Named(ref specs) => {
+                    for spec in specs {
+                        bind(spec.bind, format!("__module{}.{}", i, spec.name));
+                    }
+                }
+            }
+            source_prefix.push('\n');
+        }
+    }
+    let import_ref = |bind: &'s str| import_refs.get(bind).map_or(bind, String::as_str);
+
     if !exports.is_empty() {
         let mut inner = String::new();
         let mut had_binds = false;
@@ -274,7 +436,7 @@ pub fn module_to_cjs<'f, 's>(lex: &mut lex::Lexer<'f, 's>, allow_require: bool)
                     write!(
                         inner,
                         "\n  default: {{get() {{return {}}}, enumerable: true}},",
-                        bind,
+                        import_ref(bind),
                     ).unwrap();
                 }
                 Export::Named(ref specs) => {
@@ -283,7 +445,7 @@ pub fn module_to_cjs<'f, 's>(lex: &mut lex::Lexer<'f, 's>, allow_require: bool)
                             inner,
                             "\n  {}: {{get() {{return {}}}, enumerable: true}},",
                             spec.name,
-                            spec.bind,
+                            import_ref(spec.bind),
                         ).unwrap();
                     }
                 }
@@ -319,24 +481,76 @@ pub fn module_to_cjs<'f, 's>(lex: &mut lex::Lexer<'f, 's>, allow_require: bool)
         }
     }
 
+    let mut export_names = ExportNames::default();
+    let mut linkage = Linkage {
+        requests,
+        ..Linkage::default()
+    };
     for import in imports {
+        let module = import.module.clone().into_owned();
+        if let Some(bind) = import.default_bind {
+            linkage.imports.push(ImportBinding {
+                module: module.clone(),
+                name: Some("default".to_owned()),
+                bind: bind.to_owned(),
+            });
+        }
+        match import.binds {
+            Bindings::None => {}
+            Bindings::NameSpace(bind) => {
+                linkage.imports.push(ImportBinding {
+                    module: module.clone(),
+                    name: None,
+                    bind: bind.to_owned(),
+                });
+            }
+            Bindings::Named(ref specs) => {
+                linkage.imports.extend(specs.iter().map(|spec| ImportBinding {
+                    module: module.clone(),
+                    name: Some(spec.name.to_owned()),
+                    bind: spec.bind.to_owned(),
+                }));
+            }
+        }
         deps.insert(import.module);
     }
     for export in exports {
         match export {
-            Export::Default(_) => {}
-            Export::Named(_) => {}
-            Export::AllFrom(_, name) |
-            Export::NamedFrom(_, _, name) => {
+            Export::Default(bind) => {
+                export_names.names.push("default".to_owned());
+                linkage.exports.push(("default".to_owned(), bind.to_owned()));
+            }
+            Export::Named(specs) => {
+                export_names.names.extend(specs.iter().map(|spec| spec.name.to_owned()));
+                linkage.exports.extend(specs.iter().map(|spec| (spec.name.to_owned(), spec.bind.to_owned())));
+            }
+            Export::AllFrom(_, name) => {
+                export_names.all_from.push(name.clone().into_owned());
+                deps.insert(name);
+            }
+            Export::NamedFrom(specs, _, name) => {
+                export_names.names.extend(specs.iter().map(|spec| spec.name.to_owned()));
+                linkage.reexports.extend(specs.iter().map(|spec| ReExport {
+                    module: name.clone().into_owned(),
+                    name: spec.bind.to_owned(),
+                    export: spec.name.to_owned(),
+                }));
                 deps.insert(name);
             }
         }
     }
+    dynamic_deps.retain(|dep| !deps.contains(dep));
     Ok(CjsModule {
         source_prefix,
         source,
         source_suffix: "}()".to_owned(),
         deps,
+        dynamic_deps,
+        contexts,
+        globs,
+        exports: if is_module { Some(export_names) } else { None },
+        linkage: if is_module { Some(linkage) } else { None },
+        import_refs,
     })
 }
 
@@ -399,13 +613,7 @@ fn parse_export<'f, 's>(lex: &mut lex::Lexer<'f, 's>, source: &mut String) -> Re
             Tt::Id("from") => eat!(lex => tok { source.push_str(tok.ws_before) },
                 Tt::StrLitSgl(module_source) |
                 Tt::StrLitDbl(module_source) => {
-                    Ok(Export::AllFrom(module_source, match lex::str_lit_value(module_source) {
-                        Ok(module) => module,
-                        Err(error) => return Err(Error {
-                            kind: ErrorKind::ParseStrLitError(error),
-                            span: lex.recover_span(tok.span).with_owned(),
-                        }),
-                    }))
+                    Ok(Export::AllFrom(module_source, str_lit_value(lex, module_source, tok.span)?))
                 },
                 _ => expected!(lex, "module name (string literal)"),
             ),
@@ -450,13 +658,7 @@ fn parse_export<'f, 's>(lex: &mut lex::Lexer<'f, 's>, source: &mut String) -> Re
                 Tt::Id("from") => eat!(lex => tok { source.push_str(tok.ws_before) },
                     Tt::StrLitSgl(module_source) |
                     Tt::StrLitDbl(module_source) => {
-                        Ok(Export::NamedFrom(exports, module_source, match lex::str_lit_value(module_source) {
-                            Ok(module) => module,
-                            Err(error) => return Err(Error {
-                                kind: ErrorKind::ParseStrLitError(error),
-                                span: lex.recover_span(tok.span).with_owned(),
-                            }),
-                        }))
+                        Ok(Export::NamedFrom(exports, module_source, str_lit_value(lex, module_source, tok.span)?))
                     },
                     _ => expected!(lex, "module name (string literal)"),
                 ),
@@ -645,13 +847,7 @@ fn parse_import<'f, 's>(lex: &mut lex::Lexer<'f, 's>, source: &mut String) -> Re
     eat!(lex => tok { source.push_str(tok.ws_before) },
         Tt::StrLitSgl(module_source) |
         Tt::StrLitDbl(module_source) => {
-            return Ok(ParsedImport::Import(Import::new(module_source, match lex::str_lit_value(module_source) {
-                Ok(module) => module,
-                Err(error) => return Err(Error {
-                    kind: ErrorKind::ParseStrLitError(error),
-                    span: lex.recover_span(tok.span).with_owned(),
-                }),
-            })))
+            return Ok(ParsedImport::Import(Import::new(module_source, str_lit_value(lex, module_source, tok.span)?)))
         },
         // import foo
         Tt::Id(default) => {
@@ -679,24 +875,12 @@ fn parse_import<'f, 's>(lex: &mut lex::Lexer<'f, 's>, source: &mut String) -> Re
             Tt::StrLitDbl(module_source) => eat!(lex,
                 Tt::Rparen => {
                     let start_pos = tok.span.start;
-                    let module = match lex::str_lit_value(module_source) {
-                            Ok(module) => module,
-                            Err(error) => return Err(Error {
-                                kind: ErrorKind::ParseStrLitError(error),
-                                span: lex.recover_span(tok.span).with_owned(),
-                            }),
-                        };
+                    let module = str_lit_value(lex, module_source, tok.span)?;
                     let here = lex.here();
                     let end_pos = here.span.start - here.ws_before.len();
-                    source.push_str("Promise.resolve(require");
+                    source.push_str("require._import");
                     source.push_str(&lex.input()[start_pos..end_pos]);
-                    source.push_str(")");
-                    return Ok(ParsedImport::Import(Import {
-                        module_source: &module_source,
-                        module,
-                        default_bind: None,
-                        binds: Bindings::None,
-                    }))
+                    return Ok(ParsedImport::Dynamic(module))
                 },
                 _ => {},
             ),
@@ -713,13 +897,7 @@ fn parse_import<'f, 's>(lex: &mut lex::Lexer<'f, 's>, source: &mut String) -> Re
         Tt::StrLitDbl(module_source) => {
             Ok(ParsedImport::Import(Import {
                 module_source,
-                module: match lex::str_lit_value(module_source) {
-                    Ok(module) => module,
-                    Err(error) => return Err(Error {
-                        kind: ErrorKind::ParseStrLitError(error),
-                        span: lex.recover_span(tok.span).with_owned(),
-                    }),
-                },
+                module: str_lit_value(lex, module_source, tok.span)?,
                 default_bind,
                 binds,
             }))
@@ -955,6 +1133,20 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_import_dynamic() {
+        assert_import_form!(
+            "import('a_module') _next",
+            ParsedImport::Dynamic(Cow::Borrowed("a_module")),
+            "require._import('a_module')",
+        );
+        assert_import_form!(
+            "import (\"a_module\") _next",
+            ParsedImport::Dynamic(Cow::Borrowed("a_module")),
+            " require._import(\"a_module\")",
+        );
+    }
+
     #[test]
     fn test_import_default() {
         assert_import_form!(
@@ -1153,4 +1345,72 @@ mod test {
             "       ",
         );
     }
+
+    #[test]
+    fn test_module_exports() {
+        let mut lexer = lex::Lexer::new_unnamed("export default 1; export {a, b as c}; export * from './d'; export {e} from './e'");
+        assert_eq!(module_to_cjs(&mut lexer, true, false).unwrap().exports, Some(ExportNames {
+            names: vec!["default".to_owned(), "a".to_owned(), "c".to_owned(), "e".to_owned()],
+            all_from: vec!["./d".to_owned()],
+        }));
+
+        let mut lexer = lex::Lexer::new_unnamed("import './a'");
+        assert_eq!(module_to_cjs(&mut lexer, true, false).unwrap().exports, Some(ExportNames::default()));
+
+        let mut lexer = lex::Lexer::new_unnamed("module.exports = require('./a')");
+        assert_eq!(module_to_cjs(&mut lexer, true, false).unwrap().exports, None);
+    }
+
+    #[test]
+    fn test_module_linkage() {
+        let mut lexer = lex::Lexer::new_unnamed("export {e as f} from './e'; import a, * as b from './a'; import {c as d} from './c'\nexport default d; export {a as g}; export * from './h'");
+        let import = |module: &str, name: Option<&str>, bind: &str| ImportBinding {
+            module: module.to_owned(),
+            name: name.map(str::to_owned),
+            bind: bind.to_owned(),
+        };
+        assert_eq!(module_to_cjs(&mut lexer, true, false).unwrap().linkage, Some(Linkage {
+            requests: vec!["./e".to_owned(), "./a".to_owned(), "./c".to_owned(), "./h".to_owned()],
+            imports: vec![
+                import("./a", Some("default"), "a"),
+                import("./a", None, "b"),
+                import("./c", Some("c"), "d"),
+            ],
+            exports: vec![
+                ("default".to_owned(), "__default".to_owned()),
+                ("g".to_owned(), "a".to_owned()),
+            ],
+            reexports: vec![ReExport {
+                module: "./e".to_owned(),
+                name: "e".to_owned(),
+                export: "f".to_owned(),
+            }],
+        }));
+
+        let mut lexer = lex::Lexer::new_unnamed("module.exports = require('./a')");
+        assert_eq!(module_to_cjs(&mut lexer, true, false).unwrap().linkage, None);
+    }
+
+    #[test]
+    fn test_module_strict() {
+        let mut lexer = lex::Lexer::new_unnamed("import a, {b, c as d} from './a'; import * as e from 'e'\nexport default a\nexport {d}");
+        let module = module_to_cjs(&mut lexer, false, true).unwrap();
+        assert!(!module.source_prefix.contains("with"));
+        assert!(module.source_prefix.contains("\nconst __module0 = require._esModule('./a')\nconst __module1 = require._esModule('e')\n"));
+        assert!(module.source_prefix.contains("d: {get() {return __module0.c}"));
+        let mut import_refs = module.import_refs.into_iter().collect::<Vec<_>>();
+        import_refs.sort();
+        assert_eq!(import_refs, [
+            ("a".to_owned(), "__module0.default".to_owned()),
+            ("b".to_owned(), "__module0.b".to_owned()),
+            ("d".to_owned(), "__module0.c".to_owned()),
+            ("e".to_owned(), "__module1".to_owned()),
+        ]);
+
+        // a parameter with an imported name can't be replaced
+        let mut lexer = lex::Lexer::new_unnamed("import {a, b} from './a'\nexport const f = a => a + b");
+        let module = module_to_cjs(&mut lexer, false, true).unwrap();
+        assert!(module.source_prefix.contains("\nconst __module0 = require._esModule('./a'), a = __module0.a\n"));
+        assert_eq!(module.import_refs.into_iter().collect::<Vec<_>>(), [("b".to_owned(), "__module0.b".to_owned())]);
+    }
 }
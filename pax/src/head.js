@@ -3,21 +3,52 @@
   Pax.baseRequire = typeof require !== "undefined" ? require : n => {
     throw new Error(`Could not resolve module name: ${n}`)
   }
+  // the namespaces of the external modules that an ES module bundle imports
+  Pax.externals = {}
   Pax.ignored = () => {}
   Pax.ignored.deps = {}
   Pax.ignored.filename = ''
   Pax.modules = {}
   Pax.files = {}
   Pax.mains = {}
+  Pax.chunks = {}
+  Pax.loadedChunks = {}
+  Pax.baseURL = typeof document !== "undefined" && document.currentScript ? document.currentScript.src : ''
+  Pax.loadChunk = name => {
+    if (Pax.loadedChunks[name]) return Pax.loadedChunks[name]
+    if (typeof document === "undefined") {
+      // chunks load synchronously in node
+      Pax.baseRequire('./' + name)(Pax)
+      return Pax.loadedChunks[name] = Promise.resolve()
+    }
+    return Pax.loadedChunks[name] = new Promise((resolve, reject) => {
+      const script = document.createElement('script')
+      script.src = new URL(name, Pax.baseURL || document.baseURI).href
+      script.pax = Pax
+      script.onload = () => resolve()
+      script.onerror = () => {
+        delete Pax.loadedChunks[name]
+        reject(new Error(`Could not load chunk: ${name}`))
+      }
+      document.head.appendChild(script)
+    })
+  }
+  Pax.start = (chunks, then = () => {}) => {
+    const loaded = Promise.all(chunks.map(Pax.loadChunk))
+    if (typeof document === "undefined") then(Pax.makeRequire(null)())
+    else loaded.then(() => then(Pax.makeRequire(null)()))
+  }
   Pax.resolve = (base, then) => {
-    base = base.split('/')
-    base.shift()
-    for (const p of then.split('/')) {
-      if (p === '..') base.pop()
-      else if (p !== '.') base.push(p)
+    // filenames are relative to the project root, so they may start with '..'
+    const parts = []
+    for (const p of (base + '/' + then).split('/')) {
+      if (p === '..' && parts.length && parts[parts.length - 1] !== '..') parts.pop()
+      else if (p !== '.' && p !== '') parts.push(p)
     }
-    return '/' + base.join('/')
+    const path = parts.join('/')
+    return base.charAt(0) === '/' ? '/' + path : path || '.'
   }
+  Pax.dirname = filename => filename.split('/').slice(0, -1).join('/') || '.'
   Pax.Module = function Module(filename, parent) {
     this.filename = filename
     this.id = filename
@@ -26,6 +57,23 @@
     this.children = []
     this.exports = {}
   }
+  Pax.glob = (require, names, eager) => {
+    const modules = {}
+    for (const name of names) {
+      modules[name] = eager ? require._esModule(name) : () => Promise.resolve().then(() => require._esModule(name))
+    }
+    return modules
+  }
+  Pax.context = (require, names) => {
+    const name = key => {
+      if (!Object.prototype.hasOwnProperty.call(names, key)) throw new Error(`Cannot find module '${key}'`)
+      return names[key]
+    }
+    const context = key => require(name(key))
+    context.keys = () => Object.keys(names)
+    context.resolve = key => require.resolve(name(key))
+    return context
+  }
   Pax.makeRequire = self => {
     const require = m => require._module(m).exports
     require._deps = {}
@@ -33,14 +81,33 @@
 
     require._esModule = m => {
       const mod = require._module(m)
+      if (mod.namespace) return mod.namespace
       return mod.exports.__esModule ? mod.exports : {
         get default() {return mod.exports},
       }
     }
+    require._import = m => {
+      const fn = require._deps[m]
+      const chunks = fn == null ? [] : Pax.chunks[typeof fn === 'string' ? fn : fn.filename] || []
+      return Promise.resolve().then(() => Promise.all(chunks.map(Pax.loadChunk))).then(() => require._esModule(m))
+    }
+    require.resolve = m => {
+      const fn = require._deps[m]
+      if (typeof fn === 'string') return fn
+      if (fn == null || fn.filename == null) {
+        if (Pax.baseRequire.resolve) return Pax.baseRequire.resolve(m)
+        throw new Error(`Could not resolve module name: ${m}`)
+      }
+      return fn.filename
+    }
     require._module = m => {
       let fn = self ? require._deps[m] : Pax.main
+      // modules in chunks are registered when the chunk is loaded
+      if (typeof fn === 'string') fn = Pax.files[fn]
+      if (!self) Pax.main = fn
       if (fn == null) {
-        const module = {exports: Pax.baseRequire(m)}
+        const namespace = Object.prototype.hasOwnProperty.call(Pax.externals, m) ? Pax.externals[m] : null
+        const module = namespace ? {exports: 'default' in namespace ? namespace.default : namespace, namespace} : {exports: Pax.baseRequire(m)}
         require._deps[m] = {module: module}
         return module
       }
@@ -51,7 +118,7 @@
       module.require._deps = fn.deps
       module.require.main = self ? self.require.main : module
       if (self) self.children.push(module)
-      fn(module, module.exports, module.require, fn.filename, fn.filename.split('/').slice(0, -1).join('/'), {url: 'file://' + (fn.filename.charAt(0) === '/' ? '' : '/') + fn.filename})
+      fn(module, module.exports, module.require, fn.filename, Pax.dirname(fn.filename), {url: 'file://' + (fn.filename.charAt(0) === '/' ? '' : '/') + fn.filename})
       module.loaded = true
       return module
     }
@@ -0,0 +1,48 @@
+use fnv::FnvHashSet;
+
+use idents::{self, Ident};
+
+/// What scope hoisting needs to know about the source of a module.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scope {
+    /// Every identifier in the source, property names included, which a new name for a binding mustn't be.
+    pub names: FnvHashSet<String>,
+    /// The bindings that the source declares at the top level.
+    pub bindings: FnvHashSet<String>,
+    /// The names that the source declares anywhere, as `idents::walk` says.
+    pub declared: FnvHashSet<String>,
+}
+
+/// Finds the identifiers in `source`, the body of an ES module from `es6::module_to_cjs`, and the bindings it declares at the top level. Returns `None` if `idents::walk` can't tell what the identifiers are.
+pub fn scope(source: &str) -> Option<Scope> {
+    let mut scope = Scope::default();
+    scope.declared = idents::walk(source, |name, ident| {
+        scope.names.insert(name.to_owned());
+        if let Ident { binding: true, .. } = ident {
+            scope.bindings.insert(name.to_owned());
+        }
+    })?;
+    Some(scope)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bindings(source: &str) -> Vec<String> {
+        let mut bindings = scope(source).unwrap().bindings.into_iter().collect::<Vec<_>>();
+        bindings.sort();
+        bindings
+    }
+
+    #[test]
+    fn test_bindings() {
+        assert_eq!(bindings("const a = 1, b = f(x, y), {c, d: [e, ...f], g = h} = i; let j\nk = 2"), ["a", "b", "c", "e", "f", "g", "j"]);
+        assert_eq!(bindings("function a(b) { var c; let d }\nasync function e() {}\nclass F extends G { h() { var i } }"), ["F", "a", "e"]);
+        assert_eq!(bindings("const a = function b() {}, c = class D {}; x = function e() {}"), ["a", "c"]);
+        // var is function-scoped, let and const are block-scoped
+        assert_eq!(bindings("if (x) { var a; let b } for (var c = 0; c < 1; c++) {} for (const d of e) {} { const f = 1 }"), ["a", "c"]);
+        assert_eq!(bindings("const a = x ? {b: 1} : [c]; const d = () => { var e }"), ["a", "d"]);
+        assert_eq!(bindings("const __default = {a, b: c}"), ["__default"]);
+    }
+}
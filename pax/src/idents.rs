@@ -0,0 +1,623 @@
+use std::fmt::Write;
+use fnv::{FnvHashMap, FnvHashSet};
+
+use esparse::lex::{self, Tt};
+
+/// Calls `f` with each identifier in `source`, the body of an ES module from `es6::module_to_cjs`, property names included, and says what it is. Returns the names that the source declares anywhere, parameters included, or uses as labels, which it can't refer to with anything but an identifier. Returns `None` if the source doesn't lex, uses `eval`, which may refer to any binding, or declares a top-level binding whose name is also a contextual keyword, which can't be told apart from the keyword.
+pub fn walk<F>(source: &str, mut f: F) -> Option<FnvHashSet<String>>
+where F: FnMut(&str, Ident) {
+    let mut walker = Walker::new(source);
+    loop {
+        let (tok, ident) = walker.next();
+        match tok.tt {
+            Tt::Eof => break,
+            Tt::Err => return None,
+            Tt::Id(name) => {
+                if name == "eval" {
+                    return None
+                }
+                if let Some(ident) = ident {
+                    if ident.binding && (name == "async" || name == "of") {
+                        return None
+                    }
+                    f(name, ident);
+                }
+            }
+            _ => {}
+        }
+    }
+    if walker.lex.take_error().is_some() {
+        return None
+    }
+    Some(walker.declared.into_iter().map(str::to_owned).collect())
+}
+
+/// The names that `source` declares, as `walk` says.
+pub fn declared(source: &str) -> Option<FnvHashSet<String>> {
+    walk(source, |_, _| {})
+}
+
+/// Renames the identifiers in `source` that `renames` has new names for, except in property names. Shorthand properties keep their names: `{a}` becomes `{a: b}`. Line breaks stay where they are.
+pub fn rename(source: &str, renames: &FnvHashMap<String, String>) -> String {
+    let mut result = String::with_capacity(source.len());
+    let mut walker = Walker::new(source);
+    loop {
+        let (tok, ident) = walker.next();
+        result.push_str(tok.ws_before);
+        if let Tt::Eof | Tt::Err = tok.tt {
+            break
+        }
+        let text = &source[tok.span.start..tok.span.end];
+        match (ident, renames.get(text)) {
+            (Some(Ident { kind: IdentKind::Ref, .. }), Some(new_name)) => {
+                result.push_str(new_name);
+            }
+            (Some(Ident { kind: IdentKind::Shorthand, .. }), Some(new_name)) => {
+                write!(result, "{}: {}", text, new_name).unwrap();
+            }
+            _ => result.push_str(text),
+        }
+    }
+    result
+}
+
+/// What an identifier is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ident {
+    pub kind: IdentKind,
+    /// Whether the identifier is the name of a top-level binding where it's declared.
+    pub binding: bool,
+    /// Whether the identifier is the name of a binding where it's declared, at any level.
+    pub declaration: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentKind {
+    /// A binding or a reference to one.
+    Ref,
+    /// A shorthand property, which is both a property name and a reference.
+    Shorthand,
+    /// A property name, a label, or a contextual keyword.
+    Key,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    /// A block, or the top level if it's the first level; `function` if it's the body of a function.
+    Block { function: bool },
+    /// Parentheses; `control` if they follow `if`, `for`, `while`, `switch`, `catch`, or `with`, rather than being a parameter list or an expression.
+    Paren { control: bool },
+    Bracket,
+    Object,
+    Class,
+    Template,
+}
+
+#[derive(Debug)]
+struct Level<'s> {
+    context: Context,
+    /// The number of `?` operators whose `:` hasn't come yet.
+    ternary: usize,
+    /// In an object literal or class body, whether the next token starts a property.
+    key: bool,
+    /// In a class body, whether this is a field initializer.
+    init: bool,
+    /// Whether this is a destructuring pattern in a top-level declaration.
+    pattern: bool,
+    /// In a pattern, whether an identifier here would be a binding, rather than part of a default value.
+    binding: bool,
+    /// Whether the bindings in this pattern are top-level.
+    top_level: bool,
+    /// In parentheses, whether they may be a parameter list.
+    params: bool,
+    /// In parentheses, the identifiers in them, which are parameters if they turn out to be a parameter list.
+    names: Vec<&'s str>,
+}
+
+impl<'s> Level<'s> {
+    fn new(context: Context) -> Self {
+        Level {
+            context,
+            ternary: 0,
+            key: matches!(context, Context::Object | Context::Class),
+            init: false,
+            pattern: false,
+            binding: false,
+            top_level: false,
+            params: false,
+            names: Vec::new(),
+        }
+    }
+}
+
+/// A `var`, `let`, or `const` declaration.
+#[derive(Debug, Clone, Copy)]
+struct Declaration {
+    depth: usize,
+    /// Whether an identifier here would be a binding, rather than part of an initializer.
+    binding: bool,
+    /// Whether the bindings are top-level.
+    top_level: bool,
+}
+
+/// Goes through the tokens of a module, keeping just enough track of the syntax to tell references from property names and to find the top-level bindings.
+struct Walker<'s> {
+    lex: lex::Lexer<'static, 's>,
+    levels: Vec<Level<'s>>,
+    prev: Option<Tt<'s>>,
+    /// Whether the last `)` closed a `control` paren.
+    paren_control: bool,
+    /// Whether the last `:` ended a conditional expression.
+    colon_ternary: bool,
+    /// Whether the last `?` started a `??` operator.
+    nullish: bool,
+    /// The depths of the `class` keywords whose bodies haven't started.
+    classes: Vec<usize>,
+    declaration: Option<Declaration>,
+    /// Whether the next identifier is the name of a top-level function or class declaration.
+    declare_next: bool,
+    /// Whether the last token was `async` in `async function` at the start of a statement.
+    async_statement: bool,
+    /// Whether the next identifier is the name of a function or class, at any level.
+    name_next: bool,
+    /// The names declared anywhere, as `walk` returns them.
+    declared: FnvHashSet<&'s str>,
+}
+
+impl<'s> Walker<'s> {
+    fn new(source: &'s str) -> Self {
+        Walker {
+            lex: lex::Lexer::new_unnamed(source),
+            levels: vec![Level::new(Context::Block { function: false })],
+            prev: None,
+            paren_control: false,
+            colon_ternary: false,
+            nullish: false,
+            classes: Vec::new(),
+            declaration: None,
+            declare_next: false,
+            async_statement: false,
+            name_next: false,
+            declared: FnvHashSet::default(),
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    fn top(&mut self) -> &mut Level<'s> {
+        self.levels.last_mut().unwrap()
+    }
+
+    /// Whether a token that follows `self.prev` and is preceded by a line break if `nl_before` starts a statement.
+    fn starts_statement(&self, nl_before: bool) -> bool {
+        match self.prev {
+            None | Some(Tt::Semi) | Some(Tt::Lbrace) | Some(Tt::Rbrace) => true,
+            Some(prev) => nl_before && ends_expr(prev),
+        }
+    }
+
+    /// Whether a binding declared here with `var` would be top-level, i.e., whether this isn't in a function or class body.
+    fn var_is_top_level(&self) -> bool {
+        self.levels.iter().all(|level| match level.context {
+            Context::Block { function } => !function,
+            Context::Class => false,
+            _ => true,
+        })
+    }
+
+    /// Whether an identifier or pattern here would declare a binding, and whether it would be top-level.
+    fn binding_here(&self) -> Option<bool> {
+        let depth = self.depth();
+        let level = &self.levels[depth];
+        match self.declaration {
+            Some(declaration) if declaration.depth == depth => Some(declaration.top_level).filter(|_| declaration.binding),
+            _ => Some(level.top_level).filter(|_| level.pattern && level.binding),
+        }
+    }
+
+    fn push(&mut self, context: Context) {
+        let pattern = self.binding_here();
+        let mut level = Level::new(context);
+        if let Some(top_level) = pattern {
+            level.pattern = true;
+            level.binding = context == Context::Bracket;
+            level.top_level = top_level;
+        }
+        self.levels.push(level);
+    }
+
+    fn pop(&mut self) -> Option<Level<'s>> {
+        if self.levels.len() == 1 {
+            return None
+        }
+        let level = self.levels.pop().unwrap();
+        if let Some(declaration) = self.declaration {
+            if declaration.depth > self.depth() {
+                self.declaration = None;
+            }
+        }
+        let parent = self.top();
+        match (parent.context, level.context) {
+            // the end of a method or static block
+            (Context::Class, Context::Block { .. }) => parent.key = true,
+            // a computed property name
+            (Context::Class, Context::Bracket) |
+            (Context::Object, Context::Bracket) => parent.key = false,
+            _ => {}
+        }
+        Some(level)
+    }
+
+    /// The context of a `{` that doesn't start a class body.
+    fn brace_context(&self) -> Context {
+        let level = &self.levels[self.depth()];
+        match level.context {
+            Context::Class if !level.init => return Context::Block { function: true },
+            _ => {}
+        }
+        if self.binding_here().is_some() {
+            return Context::Object
+        }
+        match self.prev {
+            None |
+            Some(Tt::Semi) |
+            Some(Tt::Lbrace) |
+            Some(Tt::Rbrace) |
+            Some(Tt::Else) |
+            Some(Tt::Try) |
+            Some(Tt::Finally) |
+            Some(Tt::Do) => Context::Block { function: false },
+            Some(Tt::Rparen) => Context::Block { function: !self.paren_control },
+            Some(Tt::EqGt) => Context::Block { function: true },
+            // a label or a case clause, rather than a conditional expression
+            Some(Tt::Colon) if !self.colon_ternary && matches!(level.context, Context::Block { .. }) => Context::Block { function: false },
+            Some(Tt::Id("of")) => Context::Object,
+            // an object literal can't follow an expression, so this is a block after an automatically inserted semicolon
+            Some(prev) if ends_expr(prev) => Context::Block { function: false },
+            _ => Context::Object,
+        }
+    }
+
+    /// Consumes the next token, and classifies it if it's an identifier.
+    fn next(&mut self) -> (lex::Tok<'static, 's>, Option<Ident>) {
+        let tok = self.lex.advance();
+        let next = self.lex.here();
+        let depth = self.depth();
+
+        // automatic semicolon insertion
+        if tok.nl_before && self.prev.is_some_and(ends_expr) {
+            if let Some(declaration) = self.declaration {
+                if declaration.depth == depth && !declaration.binding && starts_statement(tok.tt) {
+                    self.declaration = None;
+                }
+            }
+            let level = self.top();
+            if level.context == Context::Class && matches!(tok.tt, Tt::Id(_) | Tt::StrLitSgl(_) | Tt::StrLitDbl(_) | Tt::NumLitBin(_) | Tt::NumLitOct(_) | Tt::NumLitDec(_) | Tt::NumLitHex(_)) {
+                level.init = false;
+                level.key = true;
+            }
+        }
+
+        let mut ident = None;
+        match tok.tt {
+            Tt::Id("let") if self.prev != Some(Tt::Dot) && matches!(next.tt, Tt::Id(_) | Tt::Lbrace | Tt::Lbracket) => {
+                self.declare(depth == 0);
+            }
+            Tt::Id(name) => ident = Some(self.ident(name, next.tt)),
+            Tt::Var => {
+                let top_level = self.var_is_top_level();
+                self.declare(top_level);
+            }
+            Tt::Const => self.declare(depth == 0),
+            Tt::Function => {
+                if depth == 0 && (self.async_statement || self.starts_statement(tok.nl_before)) {
+                    self.declare_next = true;
+                }
+                self.name_next = true;
+            }
+            Tt::Class => {
+                if depth == 0 && self.starts_statement(tok.nl_before) {
+                    self.declare_next = true;
+                }
+                self.name_next = true;
+                self.classes.push(depth);
+            }
+            Tt::Lbrace => {
+                let context = if self.classes.last() == Some(&depth) {
+                    self.classes.pop();
+                    Context::Class
+                } else {
+                    self.brace_context()
+                };
+                self.push(context);
+            }
+            Tt::Lparen => {
+                let control = matches!(self.prev, Some(Tt::If) | Some(Tt::For) | Some(Tt::While) | Some(Tt::Switch) | Some(Tt::Catch) | Some(Tt::With));
+                let params = !control || self.prev == Some(Tt::Catch);
+                let level = self.top();
+                if level.context == Context::Class {
+                    level.key = false;
+                }
+                self.push(Context::Paren { control });
+                self.top().params = params;
+            }
+            Tt::Lbracket => {
+                let level = self.top();
+                if level.context == Context::Class || level.context == Context::Object {
+                    // a computed property name isn't a pattern
+                    if level.key {
+                        self.levels.push(Level::new(Context::Bracket));
+                        return self.finish(tok, ident)
+                    }
+                }
+                self.push(Context::Bracket);
+            }
+            Tt::TemplateStart(_) => self.push(Context::Template),
+            Tt::Rparen => {
+                if let Some(level) = self.pop() {
+                    if let Context::Paren { control } = level.context {
+                        self.paren_control = control;
+                        // a function's parameters, or a caught exception
+                        if level.params && matches!(next.tt, Tt::EqGt | Tt::Lbrace) {
+                            self.declared.extend(level.names);
+                        } else if let Some(parent) = self.levels.iter_mut().rev().find(|level| matches!(level.context, Context::Paren { .. })) {
+                            parent.names.extend(level.names);
+                        }
+                    }
+                }
+            }
+            Tt::Rbracket |
+            Tt::Rbrace |
+            Tt::TemplateEnd(_) => {
+                self.pop();
+            }
+            Tt::Question => {
+                if self.nullish {
+                    self.nullish = false;
+                } else if next.span.start == tok.span.end && next.tt == Tt::Question {
+                    self.nullish = true;
+                } else if !(next.span.start == tok.span.end && next.tt == Tt::Dot) {
+                    self.top().ternary += 1;
+                }
+            }
+            Tt::Colon => {
+                self.colon_ternary = self.top().ternary > 0;
+                let level = self.top();
+                if level.ternary > 0 {
+                    level.ternary -= 1;
+                } else if level.context == Context::Object {
+                    level.key = false;
+                    level.binding = level.pattern;
+                }
+            }
+            Tt::Comma => {
+                if let Some(ref mut declaration) = self.declaration {
+                    if declaration.depth == depth {
+                        declaration.binding = true;
+                    }
+                }
+                let level = self.top();
+                match level.context {
+                    Context::Object => {
+                        level.key = true;
+                        level.binding = false;
+                    }
+                    Context::Bracket => level.binding = level.pattern,
+                    _ => {}
+                }
+            }
+            Tt::Eq => {
+                if let Some(ref mut declaration) = self.declaration {
+                    if declaration.depth == depth {
+                        declaration.binding = false;
+                    }
+                }
+                let level = self.top();
+                match level.context {
+                    Context::Class => {
+                        level.key = false;
+                        level.init = true;
+                    }
+                    _ => level.binding = false,
+                }
+            }
+            Tt::Semi => {
+                if let Some(declaration) = self.declaration {
+                    if declaration.depth == depth {
+                        self.declaration = None;
+                    }
+                }
+                let level = self.top();
+                if level.context == Context::Class {
+                    level.key = true;
+                    level.init = false;
+                }
+            }
+            Tt::In => {
+                if let Some(declaration) = self.declaration {
+                    if declaration.depth == depth {
+                        self.declaration = None;
+                    }
+                }
+                self.leave_key();
+            }
+            Tt::DotDotDot => {
+                let level = self.top();
+                if level.context == Context::Object {
+                    level.key = false;
+                    level.binding = level.pattern;
+                }
+            }
+            // generator methods
+            Tt::Star => {}
+            Tt::TemplateMiddle(_) |
+            Tt::Eof |
+            Tt::Err => {}
+            _ => self.leave_key(),
+        }
+        self.finish(tok, ident)
+    }
+
+    fn finish(&mut self, tok: lex::Tok<'static, 's>, ident: Option<Ident>) -> (lex::Tok<'static, 's>, Option<Ident>) {
+        if !matches!(tok.tt, Tt::Function | Tt::Class | Tt::Star) {
+            self.name_next = false;
+        }
+        self.async_statement = false;
+        if let Tt::Id("async") = tok.tt {
+            self.async_statement = self.lex.here().tt == Tt::Function && !self.lex.here().nl_before && self.depth() == 0 && self.starts_statement(tok.nl_before);
+        }
+        if tok.tt != Tt::Colon {
+            self.colon_ternary = false;
+        }
+        self.prev = Some(tok.tt);
+        (tok, ident)
+    }
+
+    /// Notes a token in a property name, like a string literal or a keyword, which ends the place where a property starts.
+    fn leave_key(&mut self) {
+        let level = self.top();
+        if let Context::Object | Context::Class = level.context {
+            level.key = false;
+        }
+    }
+
+    /// Starts a `var`, `let`, or `const` declaration, whose bindings are `top_level` or not.
+    fn declare(&mut self, top_level: bool) {
+        self.declaration = Some(Declaration {
+            depth: self.depth(),
+            binding: true,
+            top_level,
+        });
+    }
+
+    fn ident(&mut self, name: &'s str, next: Tt) -> Ident {
+        let ident = self.classify(name, next);
+        if ident.kind != IdentKind::Key {
+            let label = next == Tt::Colon && self.prev != Some(Tt::Case) && matches!(self.levels[self.depth()], Level { context: Context::Block { .. }, ternary: 0, .. });
+            // a parameter of an arrow function without parentheses
+            if ident.declaration || self.name_next || label || next == Tt::EqGt {
+                self.declared.insert(name);
+            }
+            if let Some(level) = self.levels.iter_mut().rev().find(|level| matches!(level.context, Context::Paren { .. })) {
+                level.names.push(name);
+            }
+        }
+        ident
+    }
+
+    fn classify(&mut self, name: &str, next: Tt) -> Ident {
+        let key = Ident { kind: IdentKind::Key, binding: false, declaration: false };
+        if self.prev == Some(Tt::Dot) {
+            return key
+        }
+        if self.declare_next {
+            self.declare_next = false;
+            return Ident { kind: IdentKind::Ref, binding: true, declaration: true }
+        }
+        if let Some(declaration) = self.declaration {
+            if declaration.depth == self.depth() {
+                if name == "of" && !declaration.binding {
+                    // for (const x of xs)
+                    self.declaration = None;
+                    return key
+                }
+                // what follows a binding is an initializer, a comma, or the end of the declaration
+                self.declaration = Some(Declaration { binding: false, ..declaration });
+                return Ident { kind: IdentKind::Ref, binding: declaration.binding && declaration.top_level, declaration: declaration.binding }
+            }
+        }
+
+        let level = self.top();
+        let modifier = matches!(name, "get" | "set" | "async" | "static") && matches!(next,
+            Tt::Id(_) | Tt::StrLitSgl(_) | Tt::StrLitDbl(_) |
+            Tt::NumLitBin(_) | Tt::NumLitOct(_) | Tt::NumLitDec(_) | Tt::NumLitHex(_) |
+            Tt::Lbracket | Tt::Star);
+        match level.context {
+            Context::Object if level.key => {
+                if modifier {
+                    return key
+                }
+                level.key = false;
+                match next {
+                    Tt::Comma | Tt::Rbrace | Tt::Eq => Ident { kind: IdentKind::Shorthand, binding: level.pattern && level.top_level, declaration: level.pattern },
+                    _ => key,
+                }
+            }
+            Context::Class if level.key => {
+                if !(modifier || name == "static" && next == Tt::Lbrace) {
+                    level.key = false;
+                }
+                key
+            }
+            _ => Ident { kind: IdentKind::Ref, binding: level.pattern && level.binding && level.top_level, declaration: level.pattern && level.binding },
+        }
+    }
+}
+
+/// Whether `tt` can end an expression, so that a line break after it may end the statement.
+fn ends_expr(tt: Tt) -> bool {
+    matches!(tt,
+        Tt::Id(_) | Tt::StrLitSgl(_) | Tt::StrLitDbl(_) | Tt::RegExpLit(..) |
+        Tt::NumLitBin(_) | Tt::NumLitOct(_) | Tt::NumLitDec(_) | Tt::NumLitHex(_) |
+        Tt::TemplateNoSub(_) | Tt::TemplateEnd(_) |
+        Tt::Rparen | Tt::Rbracket | Tt::Rbrace |
+        Tt::PlusPlus | Tt::MinusMinus |
+        Tt::Null | Tt::True | Tt::False | Tt::This | Tt::Super)
+}
+
+/// Whether `tt` can't continue an expression, so that a line break before it ends the statement.
+fn starts_statement(tt: Tt) -> bool {
+    matches!(tt,
+        Tt::Id(_) | Tt::StrLitSgl(_) | Tt::StrLitDbl(_) |
+        Tt::NumLitBin(_) | Tt::NumLitOct(_) | Tt::NumLitDec(_) | Tt::NumLitHex(_) |
+        Tt::Var | Tt::Const | Tt::Function | Tt::Class |
+        Tt::If | Tt::For | Tt::While | Tt::Do | Tt::Switch | Tt::Try |
+        Tt::Return | Tt::Throw | Tt::Break | Tt::Continue |
+        Tt::This | Tt::New | Tt::Typeof | Tt::Delete | Tt::Void |
+        Tt::Null | Tt::True | Tt::False | Tt::Lbrace)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn declared_names(source: &str) -> Vec<String> {
+        let mut declared = declared(source).unwrap().into_iter().collect::<Vec<_>>();
+        declared.sort();
+        declared
+    }
+
+    fn rename_all(source: &str, names: &[&str]) -> String {
+        let renames = names.iter().map(|&name| (name.to_owned(), format!("{}$1", name))).collect();
+        rename(source, &renames)
+    }
+
+    #[test]
+    fn test_declared() {
+        assert_eq!(declared_names("const a = b; function c(d, [e]) { var f = g; let {h, i: j} = k }"), ["a", "c", "d", "e", "f", "h", "j"]);
+        assert_eq!(declared_names("x = (a, b) => a + c; y = d => d; try {} catch (e) {} z = function f() {}; class G { h(i) { return i } }"), ["G", "a", "b", "d", "e", "f", "i"]);
+        assert_eq!(declared_names("a: for (;;) { f(b); if (c) break a; switch (d) { case e: g ? h : i } }"), ["a"]);
+        // the names in a parameter list's default values count as well
+        assert_eq!(declared_names("x = f((a = b) => a)"), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_rename() {
+        assert_eq!(rename_all("const a = {a, b: a, a: b, [a]: 1, a() {}}; a.a", &["a"]), "const a$1 = {a: a$1, b: a$1, a: b, [a$1]: 1, a() {}}; a$1.a");
+        assert_eq!(rename_all("const {a, b: [c] = a} = x", &["a", "c"]), "const {a: a$1, b: [c$1] = a$1} = x");
+        assert_eq!(rename_all("class A extends a { a = a; static a() { return a } get [a]() {} }", &["a"]), "class A extends a$1 { a = a$1; static a() { return a$1 } get [a$1]() {} }");
+        assert_eq!(rename_all("a: for (;;) { if (a) break a; x = `${a}` }", &["a"]), "a$1: for (;;) { if (a$1) break a$1; x = `${a$1}` }");
+        assert_eq!(rename_all("x = a ? {a} : a; switch (a) { case a: { a } }", &["a"]), "x = a$1 ? {a: a$1} : a$1; switch (a$1) { case a$1: { a$1 } }");
+        assert_eq!(rename_all("x = a?.a ?? {a}\n{ a(a) }", &["a"]), "x = a$1?.a ?? {a: a$1}\n{ a$1(a$1) }");
+        assert_eq!(rename_all("class B {\n  a = 1\n  b = a\n  a\n}", &["a"]), "class B {\n  a = 1\n  b = a$1\n  a\n}");
+    }
+
+    #[test]
+    fn test_walk_err() {
+        assert_eq!(declared("eval('a')"), None);
+        assert_eq!(declared("const async = 1"), None);
+        assert_eq!(declared("const a = 'b"), None);
+    }
+}
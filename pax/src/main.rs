@@ -36,8 +36,7 @@ use std::path::{self, PathBuf, Path, Component};
 use std::sync::mpsc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::marker::PhantomData;
-use std::sync::Arc;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex, RwLock};
 use std::any::Any;
 use std::borrow::Cow;
 use std::cell::RefCell;
@@ -46,70 +45,114 @@ use fnv::{FnvHashMap, FnvHashSet};
 use crossbeam::sync::SegQueue;
 use notify::Watcher;
 use esparse::lex::{self, Tt};
-use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::de::{self, Deserialize, DeserializeSeed, Deserializer, Visitor};
 use serde::ser::{Serialize, Serializer, SerializeSeq};
 use regex::Regex;
+use context::{ContextRequest, Glob, GlobCall};
 
 mod opts;
 mod es6;
-
-macro_rules! map {
-    {} => {
-        Default::default()
-    };
-    { $($key:expr => $value:expr,)+ } => {
-        {
-            let mut map = FnvHashMap::default();
-            $(map.insert($key, $value);)+
-            map
-        }
-    };
-    { $($key:expr => $value:expr),+ } => {
-        map!{$($key => $value,)+}
-    };
-}
+mod tsconfig;
+mod pnp;
+mod context;
+mod idents;
+mod hoist;
 
 const HEAD_JS: &str = include_str!("head.js");
 const TAIL_JS: &str = include_str!("tail.js");
+const CHUNK_HEAD_JS: &str = include_str!("chunk_head.js");
+const CHUNK_TAIL_JS: &str = include_str!("chunk_tail.js");
+const CHUNK_TAIL_ESM_JS: &str = include_str!("chunk_tail_esm.js");
 const CORE_MODULES: &[&str] = &["assert", "buffer", "child_process", "cluster", "crypto", "dgram", "dns", "domain", "events", "fs", "http", "https", "net", "os", "path", "punycode", "querystring", "readline", "stream", "string_decoder", "tls", "tty", "url", "util", "v8", "vm", "zlib"];
 
-fn cjs_parse_deps<'f, 's>(lex: &mut lex::Lexer<'f, 's>) -> Result<FnvHashSet<Cow<'s, str>>, CliError> {
+/// Browser polyfill packages for `CORE_MODULES`, used with --polyfill-core.
+const CORE_POLYFILLS: &[(&str, &str)] = &[
+    ("assert", "assert"),
+    ("buffer", "buffer"),
+    ("crypto", "crypto-browserify"),
+    ("domain", "domain-browser"),
+    ("events", "events"),
+    ("http", "stream-http"),
+    ("https", "https-browserify"),
+    ("os", "os-browserify/browser.js"),
+    ("path", "path-browserify"),
+    ("punycode", "punycode"),
+    ("querystring", "querystring-es3"),
+    ("stream", "stream-browserify"),
+    ("string_decoder", "string_decoder"),
+    ("tty", "tty-browserify"),
+    ("url", "url"),
+    ("util", "util"),
+    ("vm", "vm-browserify"),
+    ("zlib", "browserify-zlib"),
+];
+
+/// The names that a module's function gives it, which modules run in another module's function mustn't use without declaring them.
+const WRAPPER_NAMES: &[&str] = &["module", "exports", "require", "__filename", "__dirname", "__import_meta"];
+/// The names that the code linking modules in the same function uses, which those modules mustn't declare.
+const LINKER_NAMES: &[&str] = &["Pax", "Object"];
+
+lazy_static! {
+    static ref COMMA: Regex = Regex::new(r#"\s*,\s*"#).unwrap();
+    static ref GLOBAL_NAME: Regex = Regex::new(r#"^[A-Za-z_$][\w$]*(\.[A-Za-z_$][\w$]*)*$"#).unwrap();
+}
+
+/// The modules that a CJS module refers to.
+#[derive(Debug, Default)]
+struct CjsDeps<'s> {
+    deps: FnvHashSet<Cow<'s, str>>,
+    contexts: FnvHashSet<ContextRequest>,
+    /// The calls to `require.context()`, by their range in the source code.
+    globs: Vec<GlobCall>,
+}
+
+fn cjs_parse_deps<'f, 's>(lex: &mut lex::Lexer<'f, 's>) -> Result<CjsDeps<'s>, CliError> {
     // TODO should we panic on dynamic requires?
-    let mut deps = FnvHashSet::default();
+    let mut result = CjsDeps::default();
     loop {
-        eat!(lex,
+        eat!(lex => tok,
             // Tt::Id(s) if s == "require" => eat!(lex,
             Tt::Id("require") => eat!(lex,
-                Tt::Lparen => eat!(lex,
-                    Tt::StrLitSgl(s) |
-                    Tt::StrLitDbl(s) => eat!(lex,
-                        Tt::Rparen => {
-                            deps.insert(lex::str_lit_value(s)?);
+                Tt::Lparen => match context::parse_request(lex)? {
+                    Some(context::Request::Static(dep)) => {
+                        result.deps.insert(dep);
+                    }
+                    Some(context::Request::Context(request)) => {
+                        result.contexts.insert(request);
+                    }
+                    None => {}
+                },
+                Tt::Dot => eat!(lex,
+                    Tt::Id("resolve") => eat!(lex,
+                        Tt::Lparen => eat!(lex,
+                            Tt::StrLitSgl(s) |
+                            Tt::StrLitDbl(s) => eat!(lex,
+                                Tt::Rparen => {
+                                    result.deps.insert(lex::str_lit_value(s)?);
+                                },
+                                _ => {},
+                            ),
+                            _ => {},
+                        ),
+                        _ => {},
+                    ),
+                    Tt::Id("context") => eat!(lex,
+                        Tt::Lparen => {
+                            if let Some(glob) = context::parse_require_context(lex)? {
+                                let here = lex.here();
+                                result.globs.push(GlobCall {
+                                    range: tok.span.start..here.span.start - here.ws_before.len(),
+                                    glob,
+                                });
+                            }
                         },
                         _ => {},
                     ),
                     _ => {},
                 ),
-                // Tt::Dot => eat!(lex,
-                //     Tt::Id("resolve") => eat!(lex,
-                //         Tt::Lparen => eat!(lex,
-                //             Tt::StrLitSgl(s) |
-                //             Tt::StrLitDbl(s) => eat!(lex,
-                //                 Tt::Rparen => {
-                //                     // TODO handle error
-                //                     return Some(lex::str_lit_value(s).unwrap())
-                //                 },
-                //                 _ => {},
-                //             ),
-                //             _ => {},
-                //         ),
-                //         _ => {},
-                //     ),
-                //     _ => {},
-                // ),
                 _ => {},
             ),
-            Tt::Eof => return Ok(deps),
+            Tt::Eof => return Ok(result),
             _ => {
                 lex.advance();
             },
@@ -117,74 +160,811 @@ fn cjs_parse_deps<'f, 's>(lex: &mut lex::Lexer<'f, 's>) -> Result<FnvHashSet<Cow
     }
 }
 
+/// A file next to the bundles with modules that are only reachable through `import()`, or that several bundles share.
+#[derive(Debug)]
+struct Chunk {
+    /// The file name, in the bundles' directory.
+    name: String,
+    modules: Vec<PathBuf>,
+}
+
+/// A file that modules are written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    /// The bundle for the entry point with this index.
+    Bundle(usize),
+    Chunk(usize),
+}
+
+/// How the modules are divided between the bundles and their chunks. By default, every module is in the first bundle.
+#[derive(Debug, Default)]
+struct Split {
+    chunks: Vec<Chunk>,
+    /// The file that each module not in the first bundle is in.
+    parts: FnvHashMap<PathBuf, Part>,
+    /// The chunks to load before running each module that's imported with `import()` but isn't in a bundle.
+    loads: Vec<(PathBuf, Vec<usize>)>,
+    /// The chunk with the modules that several bundles require, which every bundle loads before it runs.
+    shared: Option<usize>,
+}
+
+impl Split {
+    fn part_of(&self, path: &Path) -> Part {
+        self.parts.get(path).cloned().unwrap_or(Part::Bundle(0))
+    }
+}
+
+/// The modules that run in an entry point's function, and what they become there.
+#[derive(Debug, Default)]
+struct HoistedScope {
+    /// The modules in the order they run, ending with the entry point.
+    order: Vec<PathBuf>,
+    sources: Vec<Source>,
+    /// What the entry point's function requires the modules that aren't hoisted by.
+    deps: Vec<(String, Resolved)>,
+}
+
+/// An ES module whose scope could be hoisted into its entry point's function, as far as its source says.
+#[derive(Debug)]
+struct Hoistable<'m> {
+    module: &'m Module,
+    linkage: &'m es6::Linkage,
+    scope: hoist::Scope,
+}
+
+type Hoistables<'m> = FnvHashMap<&'m Path, Hoistable<'m>>;
+
+/// A binding in a module whose scope is hoisted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum HoistedBinding<'m> {
+    /// A binding that the module declares, or imports from a module that isn't hoisted.
+    Local(&'m Path, &'m str),
+    /// The module's namespace object.
+    Namespace(&'m Path),
+}
+
+/// Names the bindings of hoisted modules so that they don't collide.
+#[derive(Debug, Default)]
+struct HoistedNamer {
+    /// How many modules each name appears in.
+    mentions: FnvHashMap<String, usize>,
+    /// The names that a new name mustn't be.
+    used: FnvHashSet<String>,
+    /// The names that bindings already have.
+    claimed: FnvHashSet<String>,
+}
+
+impl HoistedNamer {
+    /// Names a binding that was called `name`, which keeps its name if no other module mentions it.
+    fn name(&mut self, name: &str) -> String {
+        let keep = !WRAPPER_NAMES.contains(&name) &&
+            !LINKER_NAMES.contains(&name) &&
+            self.mentions.get(name).is_none_or(|&count| count <= 1) &&
+            !self.claimed.contains(name);
+        let new_name = if keep {
+            name.to_owned()
+        } else {
+            (1..).map(|i| format!("{}${}", name, i)).find(|new_name| !self.used.contains(new_name)).unwrap()
+        };
+        self.used.insert(new_name.clone());
+        self.claimed.insert(new_name.clone());
+        new_name
+    }
+}
+
+/// The module that `module` imports as `name`, if it's in the bundle.
+fn normal_dep<'m>(module: &'m Module, name: &str) -> Option<&'m Path> {
+    match module.deps.get(name) {
+        Some(Resolved::Normal(ref path)) => Some(path),
+        _ => None,
+    }
+}
+
+/// The binding that the hoisted module `path` exports as `name`, following re-exports, or `None` if there's no such export among the hoisted modules.
+fn resolve_hoisted_export<'m>(modules: &Hoistables<'m>, path: &'m Path, name: &str, seen: &mut FnvHashSet<(&'m Path, String)>) -> Option<HoistedBinding<'m>> {
+    if !seen.insert((path, name.to_owned())) {
+        return None
+    }
+    let module: &'m Module = modules.get(path)?.module;
+    let linkage: &'m es6::Linkage = modules[path].linkage;
+    if let Some((_, bind)) = linkage.exports.iter().find(|(export, _)| export == name) {
+        return resolve_hoisted_binding(modules, path, bind, seen)
+    }
+    if let Some(reexport) = linkage.reexports.iter().find(|reexport| reexport.export == name) {
+        return resolve_hoisted_export(modules, normal_dep(module, &reexport.module)?, &reexport.name, seen)
+    }
+    // `export * from` doesn't re-export the default export
+    if name != "default" {
+        for from in &module.exports.as_ref()?.all_from {
+            if let Some(binding) = normal_dep(module, from).and_then(|target| resolve_hoisted_export(modules, target, name, seen)) {
+                return Some(binding)
+            }
+        }
+    }
+    None
+}
+
+/// The binding that `bind`, declared or imported at the top level of the hoisted module `path`, refers to.
+fn resolve_hoisted_binding<'m>(modules: &Hoistables<'m>, path: &'m Path, bind: &'m str, seen: &mut FnvHashSet<(&'m Path, String)>) -> Option<HoistedBinding<'m>> {
+    let hoistable = modules.get(path)?;
+    let module: &'m Module = hoistable.module;
+    let linkage: &'m es6::Linkage = hoistable.linkage;
+    if let Some(import) = linkage.imports.iter().find(|import| import.bind == bind) {
+        return match normal_dep(module, &import.module) {
+            Some(target) if modules.contains_key(target) => match import.name {
+                Some(ref name) => resolve_hoisted_export(modules, target, name, seen),
+                None => Some(HoistedBinding::Namespace(target)),
+            },
+            _ => Some(HoistedBinding::Local(path, bind)),
+        }
+    }
+    if hoistable.scope.bindings.contains(bind) {
+        Some(HoistedBinding::Local(path, bind))
+    } else {
+        None
+    }
+}
+
+/// The names that the hoisted module `path` exports, following `export * from`, like `Writer::entry_exports`.
+fn hoisted_export_names<'m>(modules: &Hoistables<'m>, path: &'m Path, is_star: bool, names: &mut Vec<&'m str>, seen: &mut FnvHashSet<&'m Path>) {
+    if !seen.insert(path) {
+        return
+    }
+    let module: &'m Module = match modules.get(path) {
+        Some(hoistable) => hoistable.module,
+        None => return,
+    };
+    let exports = match module.exports {
+        Some(ref exports) => exports,
+        None => return,
+    };
+    for name in &exports.names {
+        if !(names.contains(&name.as_str()) || is_star && name == "default") {
+            names.push(name);
+        }
+    }
+    for from in &exports.all_from {
+        if let Some(target) = normal_dep(module, from) {
+            hoisted_export_names(modules, target, true, names, seen);
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Writer<'a, 'b> {
     modules: FnvHashMap<PathBuf, Module>,
-    entry_point: &'a Path,
+    entry_points: &'a [PathBuf],
+    /// How to output the chunks' source maps, and those of the bundles unless told otherwise.
     map_output: &'b SourceMapOutput<'b>,
+    split: Split,
+    format: &'a OutputFormat,
+    /// The directory that the names of modules in the bundle are relative to.
+    root: PathBuf,
+    /// For each entry point, the modules that run in its function when scopes are hoisted, in the order they run, ending with the entry point itself.
+    scopes: Vec<Vec<PathBuf>>,
+    /// The modules that run in an entry point's function, rather than their own.
+    merged: FnvHashSet<PathBuf>,
 }
 
 impl<'a, 'b> Writer<'a, 'b> {
-    fn sorted_modules(&self) -> Vec<(&Path, &Module)> {
+    fn sorted_modules(&self, part: Part) -> Vec<(&Path, &Module)> {
+        if let Part::Chunk(chunk) = part {
+            // already sorted
+            return self.split.chunks[chunk].modules
+                .iter()
+                .map(|p| (p.as_path(), &self.modules[p]))
+                .collect()
+        }
+        let scope = self.scope(part);
         let mut modules = self.modules
             .iter()
+            .filter(|(p, _)| self.split.part_of(p) == part && !self.merged.contains(*p) && scope.last() != Some(*p))
             .map(|(p, m)| (p.as_path(), m))
             .collect::<Vec<_>>();
         modules.sort_by(|(f, _), (g, _)| f.cmp(g));
+        // the entry point's function goes last, with the modules that run in it
+        modules.extend(scope.iter().map(|p| (p.as_path(), &self.modules[p])));
         modules
     }
 
-    fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+    /// The modules that run in the entry point's function in `part`, if it's a bundle and scopes are hoisted.
+    fn scope(&self, part: Part) -> &[PathBuf] {
+        match part {
+            Part::Bundle(entry) => self.scopes.get(entry).map_or(&[], Vec::as_slice),
+            Part::Chunk(_) => &[],
+        }
+    }
+
+    /// Writes the bundle for the entry point with index `entry`.
+    fn write_to<W: io::Write>(&self, w: &mut W, entry: usize, map_output: &SourceMapOutput) -> io::Result<()> {
+        let externals = self.external_names();
+        if *self.format == OutputFormat::Esm {
+            for (i, name) in externals.iter().enumerate() {
+                writeln!(w, "import * as __pax_external{} from {}", i, to_quoted_json_string(name))?;
+            }
+            writeln!(w, "let __pax_main")?;
+        }
         w.write_all(HEAD_JS.as_bytes())?;
         // for (module, main) in self.mains {
         //     write!(w,
         //         "\n  Pax.mains[{mod_path}] = {main_path}",
-        //         mod_path = Self::js_path(&module),
-        //         main_path = Self::js_path(&main),
+        //         mod_path = self.js_path(&module),
+        //         main_path = self.js_path(&main),
         //     );
         // }
 
-        for (file, info) in self.sorted_modules() {
-            let id = Self::name_path(&file);
-            let deps = Self::stringify_deps(&info.deps);
-            let filename = Self::js_path(&file);
-
-            write!(w,
-                "\n  Pax.files[{filename}] = {id}; {id}.deps = {deps}; {id}.filename = {filename}; function {id}(module, exports, require, __filename, __dirname, __import_meta) {{\n",
-                filename = filename,
-                id = id,
-                deps = deps,
-            )?;
+        self.write_modules_to(w, Part::Bundle(entry))?;
+        if !self.split.loads.is_empty() {
+            w.write_all(b"\n  Pax.chunks = {")?;
+            for (i, (file, chunks)) in self.split.loads.iter().enumerate() {
+                if i > 0 {
+                    w.write_all(b", ")?;
+                }
+                write!(w, "{}: [", self.js_path(file))?;
+                for (j, &chunk) in chunks.iter().enumerate() {
+                    if j > 0 {
+                        w.write_all(b", ")?;
+                    }
+                    w.write_all(to_quoted_json_string(&self.split.chunks[chunk].name).as_bytes())?;
+                }
+                w.write_all(b"]")?;
+            }
+            w.write_all(b"}")?;
+        }
+        // an entry point that another entry requires is in the shared chunk, so look it up once that's loaded
+        let main = match self.split.part_of(&self.entry_points[entry]) {
+            Part::Bundle(_) => self.name_path(&self.entry_points[entry]),
+            Part::Chunk(_) => self.js_path(&self.entry_points[entry]),
+        };
+        match *self.format {
+            OutputFormat::Cjs => {
+                let start = match self.split.shared {
+                    Some(chunk) => format!("Pax.start([{}])", to_quoted_json_string(&self.split.chunks[chunk].name)),
+                    None => "Pax.makeRequire(null)()".to_owned(),
+                };
+                write!(w,
+                    "\n  Pax.main = {main}; {start}\n  if (typeof module !== 'undefined') module.exports = Pax.main.module && Pax.main.module.exports\n",
+                    main = main,
+                    start = start,
+                )?;
+                w.write_all(TAIL_JS.as_bytes())?;
+            }
+            OutputFormat::Umd { ref global_name } |
+            OutputFormat::Iife { ref global_name } => {
+                let umd = matches!(*self.format, OutputFormat::Umd { .. });
+                if umd || global_name.is_some() {
+                    w.write_all(b"\n  Pax.expose = exports => {")?;
+                    if umd {
+                        w.write_all(b"\n    if (typeof define === 'function' && define.amd) define([], () => exports)\n    else if (typeof module === 'object' && module.exports) module.exports = exports")?;
+                    }
+                    if let Some(ref global_name) = *global_name {
+                        w.write_all(if umd { b"\n    else " } else { b"\n    " })?;
+                        // create the objects that a dotted name's last part goes in
+                        let mut end = 0;
+                        while let Some(dot) = global_name[end..].find('.') {
+                            end += dot;
+                            write!(w, "global.{0} = global.{0} || {{}}, ", &global_name[..end])?;
+                            end += 1;
+                        }
+                        write!(w, "global.{} = exports", global_name)?;
+                    }
+                    w.write_all(b"\n  }")?;
+                }
+                let start = match (self.split.shared, umd || global_name.is_some()) {
+                    (Some(chunk), true) => format!("Pax.start([{}], Pax.expose)", to_quoted_json_string(&self.split.chunks[chunk].name)),
+                    (Some(chunk), false) => format!("Pax.start([{}])", to_quoted_json_string(&self.split.chunks[chunk].name)),
+                    (None, true) => "Pax.expose(Pax.makeRequire(null)())".to_owned(),
+                    (None, false) => "Pax.makeRequire(null)()".to_owned(),
+                };
+                write!(w,
+                    "\n  Pax.main = {main}; {start}\n",
+                    main = main,
+                    start = start,
+                )?;
+                w.write_all(TAIL_JS.as_bytes())?;
+            }
+            OutputFormat::Esm => {
+                if !externals.is_empty() {
+                    w.write_all(b"\n  Pax.externals = {")?;
+                    for (i, name) in externals.iter().enumerate() {
+                        if i > 0 {
+                            w.write_all(b", ")?;
+                        }
+                        write!(w, "{}: __pax_external{}", to_quoted_json_string(name), i)?;
+                    }
+                    w.write_all(b"}")?;
+                }
+                if !self.split.chunks.is_empty() {
+                    // chunks are ES modules too, which export the function that registers their modules
+                    w.write_all(b"\n  Pax.loadChunk = name => Pax.loadedChunks[name] || (Pax.loadedChunks[name] = import(new URL(name, import.meta.url).href).then(chunk => chunk.default(Pax)))")?;
+                }
+                let start = match self.split.shared {
+                    Some(chunk) => format!("Promise.all([{}].map(Pax.loadChunk)).then(() => Pax.makeRequire(null)())", to_quoted_json_string(&self.split.chunks[chunk].name)),
+                    None => "Pax.makeRequire(null)()".to_owned(),
+                };
+                write!(w,
+                    "\n  Pax.main = {main}; __pax_main = {start}\n",
+                    main = main,
+                    start = start,
+                )?;
+                w.write_all(TAIL_JS.as_bytes())?;
+                w.write_all(match self.split.shared {
+                    Some(_) => b"const __pax_exports = await __pax_main\n" as &[u8],
+                    None => b"const __pax_exports = __pax_main\n",
+                })?;
+                match self.entry_exports(entry) {
+                    None => w.write_all(b"export default __pax_exports\n")?,
+                    Some(ref names) if names.is_empty() => {}
+                    Some(names) => {
+                        // copy the exports out first, since `export` can't take a member expression
+                        w.write_all(b"const ")?;
+                        for (i, name) in names.iter().enumerate() {
+                            if i > 0 {
+                                w.write_all(b", ")?;
+                            }
+                            write!(w, "__pax_export{} = __pax_exports[{}]", i, to_quoted_json_string(name))?;
+                        }
+                        w.write_all(b"\nexport {")?;
+                        for (i, name) in names.iter().enumerate() {
+                            if i > 0 {
+                                w.write_all(b", ")?;
+                            }
+                            write!(w, "__pax_export{} as {}", i, name)?;
+                        }
+                        w.write_all(b"}\n")?;
+                    }
+                }
+            }
+        }
+        self.write_map_url_to(w, Part::Bundle(entry), map_output)
+    }
+
+    /// The names of the external modules that any module requires, which an ES module bundle imports.
+    fn external_names(&self) -> Vec<&str> {
+        let mut names = self.modules.values()
+            .flat_map(|module| module.deps.iter())
+            .filter(|(_, resolved)| **resolved == Resolved::External)
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// The names that the entry point with index `entry` exports, following `export * from`, or `None` if it isn't an ES module.
+    fn entry_exports(&self, entry: usize) -> Option<Vec<&str>> {
+        fn add<'m>(modules: &'m FnvHashMap<PathBuf, Module>, path: &'m Path, is_entry: bool, names: &mut Vec<&'m str>, seen: &mut FnvHashSet<&'m Path>) {
+            if !seen.insert(path) { return }
+            let module = &modules[path];
+            let exports = match module.exports {
+                Some(ref exports) => exports,
+                None => return,
+            };
+            // `export * from` doesn't re-export the default export
+            names.extend(exports.names.iter()
+                .map(String::as_str)
+                .filter(|&name| is_entry || name != "default"));
+            for name in &exports.all_from {
+                if let Some(Resolved::Normal(ref path)) = module.deps.get(name) {
+                    add(modules, path, false, names, seen);
+                }
+            }
+        }
+
+        let path = &self.entry_points[entry];
+        self.modules[path].exports.as_ref()?;
+        let mut names = Vec::new();
+        add(&self.modules, path, true, &mut names, &mut FnvHashSet::default());
+        let mut seen = FnvHashSet::default();
+        names.retain(|&name| seen.insert(name));
+        Some(names)
+    }
+
+    /// Runs the ES modules of each entry point that nothing else requires in its function, as `hoist_scope` says.
+    fn hoist_scopes(&mut self) {
+        self.scopes = vec![Vec::new(); self.entry_points.len()];
+        for entry in 0..self.entry_points.len() {
+            let scope = match self.hoist_scope(entry) {
+                Some(scope) => scope,
+                None => continue,
+            };
+            for (path, source) in scope.order.iter().zip(scope.sources) {
+                let module = self.modules.get_mut(path).unwrap();
+                let old = mem::replace(&mut module.source, source);
+                module.source.original = old.original.or(Some(old.body));
+            }
+            let module = self.modules.get_mut(&self.entry_points[entry]).unwrap();
+            module.deps.extend(scope.deps);
+            self.merged.extend(scope.order[..scope.order.len() - 1].iter().cloned());
+            self.scopes[entry] = scope.order;
+        }
+    }
+
+    /// Concatenates the entry point with index `entry` and the ES modules that only it and those modules import, in the order they run, renaming top-level bindings that would collide and turning imports between them into references to the bindings they import. Returns `None` if there's nothing to hoist.
+    ///
+    /// Modules that anything else requires keep their own functions, as do modules that use `import()` or what their function gives them, like `require`. Modules that are imported from modules in the entry point's function are required where the import would have run them, and their bindings are read from them whenever they're used, like in an ES module bundle.
+    fn hoist_scope(&self, entry: usize) -> Option<HoistedScope> {
+        let entry_point = self.entry_points[entry].as_path();
+        let part = Part::Bundle(entry);
+        let mut modules = self.modules.iter()
+            .filter(|(path, _)| self.split.part_of(path) == part)
+            .filter_map(|(path, module)| {
+                let linkage = module.linkage.as_ref()?;
+                if !module.dynamic_deps.is_empty() {
+                    return None
+                }
+                let scope = hoist::scope(&module.source.body)?;
+                // they'd refer to the entry point's instead of their own
+                if WRAPPER_NAMES.iter().any(|&name| scope.names.contains(name) && !scope.bindings.contains(name)) {
+                    return None
+                }
+                // the linking code's names
+                if scope.names.iter().any(|name| name.starts_with("__pax_")) {
+                    return None
+                }
+                Some((path.as_path(), Hoistable { module, linkage, scope }))
+            })
+            .collect::<Hoistables>();
+
+        loop {
+            let len = modules.len();
+
+            let mut reachable = FnvHashSet::default();
+            let mut stack = vec![entry_point];
+            while let Some(path) = stack.pop() {
+                let hoistable = match modules.get(path) {
+                    Some(hoistable) => hoistable,
+                    None => continue,
+                };
+                if reachable.insert(path) {
+                    stack.extend(hoistable.linkage.requests.iter().filter_map(|request| normal_dep(hoistable.module, request)));
+                }
+            }
+            modules.retain(|path, _| reachable.contains(path));
+
+            let mut unlinked = FnvHashSet::default();
+            for (path, module) in &self.modules {
+                if modules.contains_key(path.as_path()) {
+                    continue
+                }
+                for resolved in module.deps.values() {
+                    if let Resolved::Normal(ref dep) = *resolved {
+                        if dep != entry_point {
+                            unlinked.insert(dep.as_path());
+                        }
+                    }
+                }
+            }
+            for (&path, hoistable) in &modules {
+                let module = hoistable.module;
+                let linkage = hoistable.linkage;
+                let imports = linkage.imports.iter().all(|import| match (normal_dep(module, &import.module), import.name.as_ref()) {
+                    (Some(target), Some(name)) if modules.contains_key(target) => resolve_hoisted_export(&modules, target, name, &mut FnvHashSet::default()).is_some(),
+                    _ => true,
+                });
+                let exports = linkage.exports.iter().all(|(_, bind)| resolve_hoisted_binding(&modules, path, bind, &mut FnvHashSet::default()).is_some());
+                let reexports = linkage.reexports.iter().all(|reexport| normal_dep(module, &reexport.module)
+                    .and_then(|target| resolve_hoisted_export(&modules, target, &reexport.name, &mut FnvHashSet::default()))
+                    .is_some());
+                let all_from = module.exports.as_ref().is_none_or(|exports| exports.all_from.iter()
+                    .all(|from| normal_dep(module, from).is_some_and(|target| modules.contains_key(target))));
+                if !(imports && exports && reexports && all_from) {
+                    unlinked.insert(path);
+                }
+            }
+            modules.retain(|path, _| !unlinked.contains(path));
+
+            if modules.len() == len {
+                break
+            }
+        }
+        if !modules.contains_key(entry_point) || modules.len() < 2 {
+            return None
+        }
+
+        // depth first, like ES modules run; the modules that aren't hoisted run before the next one that is
+        fn visit<'m>(modules: &Hoistables<'m>, path: &'m Path, visited: &mut FnvHashSet<&'m Path>, pending: &mut Vec<(&'m str, &'m Resolved)>, order: &mut Vec<(&'m Path, Vec<(&'m str, &'m Resolved)>)>) {
+            if !visited.insert(path) {
+                return
+            }
+            let module: &'m Module = modules[path].module;
+            let linkage: &'m es6::Linkage = modules[path].linkage;
+            for request in &linkage.requests {
+                match module.deps.get(request) {
+                    Some(Resolved::Normal(ref dep)) if modules.contains_key(dep.as_path()) => visit(modules, dep, visited, pending, order),
+                    Some(resolved) => pending.push((request, resolved)),
+                    None => {}
+                }
+            }
+            order.push((path, mem::take(pending)));
+        }
+        let mut order = Vec::new();
+        visit(&modules, entry_point, &mut FnvHashSet::default(), &mut Vec::new(), &mut order);
+
+        let mut namer = HoistedNamer::default();
+        for hoistable in modules.values() {
+            // imports from hoisted modules become the names of the bindings they import
+            let linked = hoistable.linkage.imports.iter()
+                .filter(|import| normal_dep(hoistable.module, &import.module).is_some_and(|target| modules.contains_key(target)))
+                .map(|import| import.bind.as_str())
+                .collect::<FnvHashSet<_>>();
+            let mut names = hoistable.scope.names.iter().map(String::as_str).collect::<FnvHashSet<_>>();
+            names.extend(hoistable.linkage.imports.iter().map(|import| import.bind.as_str()));
+            for name in names {
+                namer.used.insert(name.to_owned());
+                if !linked.contains(name) {
+                    *namer.mentions.entry(name.to_owned()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        // the namespace objects that imports or exports refer to
+        let mut links = Vec::new();
+        for &(path, _) in &order {
+            let hoistable = &modules[path];
+            for import in &hoistable.linkage.imports {
+                let target = match normal_dep(hoistable.module, &import.module) {
+                    Some(target) if modules.contains_key(target) => target,
+                    _ => continue,
+                };
+                let binding = match import.name {
+                    Some(ref name) => resolve_hoisted_export(&modules, target, name, &mut FnvHashSet::default()).unwrap(),
+                    None => HoistedBinding::Namespace(target),
+                };
+                links.push((path, import.bind.as_str(), binding));
+            }
+        }
+        // what the entry point's function requires the modules that aren't hoisted by, in the order they run
+        let mut requires = FnvHashMap::default();
+        for (_, steps) in &order {
+            for &(name, resolved) in steps {
+                let index = requires.len();
+                requires.entry(self.hoisted_dep_key(name, resolved)).or_insert(index);
+            }
+        }
+
+        // the bindings each module declares, and those it imports from modules that aren't hoisted
+        let mut finals = FnvHashMap::default();
+        let mut snapshots = FnvHashMap::default();
+        for &(path, _) in &order {
+            let hoistable = &modules[path];
+            let mut binds = hoistable.scope.bindings.iter().map(String::as_str).collect::<Vec<_>>();
+            binds.sort();
+            for bind in binds {
+                finals.insert(HoistedBinding::Local(path, bind), namer.name(bind));
+            }
+            // imports read the binding from the module whenever they're used, unless a module that refers to it declares the name somewhere else too
+            for import in &hoistable.linkage.imports {
+                let binding = HoistedBinding::Local(path, import.bind.as_str());
+                let resolved = match hoistable.module.deps.get(&import.module) {
+                    Some(Resolved::Normal(ref target)) if modules.contains_key(target.as_path()) => continue,
+                    Some(resolved) => resolved,
+                    None => {
+                        finals.insert(binding, namer.name(&import.bind));
+                        continue
+                    }
+                };
+                let index = requires[&self.hoisted_dep_key(&import.module, resolved)];
+                let value = match import.name {
+                    Some(ref export) => format!("__pax_import{}.{}", index, export),
+                    None => format!("__pax_import{}", index),
+                };
+                let declared = hoistable.scope.declared.contains(&import.bind) || links.iter()
+                    .any(|&(importer, bind, target)| target == binding && modules[importer].scope.declared.contains(bind));
+                if declared {
+                    finals.insert(binding, namer.name(&import.bind));
+                    snapshots.insert(binding, value);
+                } else {
+                    finals.insert(binding, value);
+                }
+            }
+        }
+
+        let export_bindings = |path| {
+            let mut names = Vec::new();
+            hoisted_export_names(&modules, path, false, &mut names, &mut FnvHashSet::default());
+            names.into_iter()
+                .map(|name| (name, resolve_hoisted_export(&modules, path, name, &mut FnvHashSet::default()).unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let entry_exports = export_bindings(entry_point);
+        let mut namespaces = Vec::new();
+        let mut pending = links.iter().map(|&(_, _, binding)| binding)
+            .chain(entry_exports.iter().map(|&(_, binding)| binding))
+            .collect::<Vec<_>>();
+        while let Some(binding) = pending.pop() {
+            if let HoistedBinding::Namespace(path) = binding {
+                if finals.contains_key(&binding) {
+                    continue
+                }
+                let stem = path.file_stem().map_or(Cow::Borrowed(""), |stem| stem.to_string_lossy());
+                let mut base = stem.chars()
+                    .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '$' { c } else { '_' })
+                    .collect::<String>();
+                if !base.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '$') {
+                    base.insert(0, '_');
+                }
+                finals.insert(binding, namer.name(&base));
+                let exports = export_bindings(path);
+                pending.extend(exports.iter().map(|&(_, binding)| binding));
+                namespaces.push((binding, exports));
+            }
+        }
+
+        let getters = |result: &mut String, exports: &[(&str, HoistedBinding)]| {
+            for &(name, binding) in exports {
+                write!(result, "\n  {}: {{get() {{return {}}}, enumerable: true}},", to_quoted_json_string(name), finals[&binding]).unwrap();
+            }
+        };
+        let mut head = "'use strict';\nObject.defineProperty(exports, '__esModule', {value: true})\n".to_owned();
+        if !entry_exports.is_empty() {
+            head.push_str("Object.defineProperties(exports, {");
+            getters(&mut head, &entry_exports);
+            head.push_str("\n});\n");
+        }
+        for (binding, exports) in &namespaces {
+            write!(head, "const {} = Object.defineProperties({{}}, {{\n  __esModule: {{value: true}},", finals[binding]).unwrap();
+            getters(&mut head, exports);
+            head.push_str("\n});\n");
+        }
+
+        let mut scope = HoistedScope::default();
+        for (i, (path, steps)) in order.into_iter().enumerate() {
+            let hoistable = &modules[path];
+            let mut prefix = String::new();
+            if i == 0 {
+                prefix.push_str(&head);
+            }
+            for (name, resolved) in steps {
+                let key = self.hoisted_dep_key(name, resolved);
+                let index = requires[&key];
+                // each module is required once, in the order of `requires`
+                if index < scope.deps.len() {
+                    continue
+                }
+                writeln!(prefix, "const __pax_import{} = require._esModule({})", index, to_quoted_json_string(&key)).unwrap();
+                scope.deps.push((key, resolved.clone()));
+            }
+
+            let mut renames = FnvHashMap::default();
+            for import in &hoistable.linkage.imports {
+                let binding = HoistedBinding::Local(path, &import.bind);
+                if let Some(value) = snapshots.get(&binding) {
+                    writeln!(prefix, "const {} = {}", finals[&binding], value).unwrap();
+                }
+                if let Some(name) = finals.get(&binding) {
+                    renames.insert(import.bind.clone(), name.clone());
+                }
+            }
+            for bind in &hoistable.scope.bindings {
+                renames.insert(bind.clone(), finals[&HoistedBinding::Local(path, bind)].clone());
+            }
+            for &(importer, bind, binding) in &links {
+                if importer == path {
+                    renames.insert(bind.to_owned(), finals[&binding].clone());
+                }
+            }
+            renames.retain(|name, new_name| name != new_name);
+
+            if prefix.ends_with('\n') {
+                prefix.pop();
+            }
+            scope.order.push(path.to_owned());
+            scope.sources.push(Source {
+                prefix,
+                body: idents::rename(&hoistable.module.source.body, &renames),
+                suffix: String::new(),
+                original: None,
+                import_refs: None,
+            });
+        }
+        Some(scope)
+    }
+
+    /// The name that the entry point's function requires a module that isn't hoisted by, for a module whose function required it as `name`.
+    fn hoisted_dep_key(&self, name: &str, resolved: &Resolved) -> String {
+        match *resolved {
+            Resolved::External => name.to_owned(),
+            Resolved::Ignore => "pax:ignored".to_owned(),
+            Resolved::Normal(ref path) => format!("pax:{}", self.relative_path(path).display()),
+        }
+    }
+
+    /// Writes the chunk with index `chunk`, which registers its modules with the bundle that loads it.
+    fn write_chunk_to<W: io::Write>(&self, w: &mut W, chunk: usize) -> io::Result<()> {
+        w.write_all(CHUNK_HEAD_JS.as_bytes())?;
+        self.write_modules_to(w, Part::Chunk(chunk))?;
+        w.write_all(b"\n")?;
+        w.write_all(match *self.format {
+            OutputFormat::Esm => CHUNK_TAIL_ESM_JS,
+            _ => CHUNK_TAIL_JS,
+        }.as_bytes())?;
+        self.write_map_url_to(w, Part::Chunk(chunk), self.map_output)
+    }
+
+    /// Writes every chunk to `dir`, with its source map next to it unless it's inline or suppressed.
+    fn write_chunks(&self, dir: &Path) -> Result<(), CliError> {
+        for (index, chunk) in self.split.chunks.iter().enumerate() {
+            let path = dir.join(&chunk.name);
+            let file = fs::File::create(&path)?;
+            self.write_chunk_to(&mut io::BufWriter::new(file), index)?;
+            if let SourceMapOutput::File(..) = *self.map_output {
+                let mut map_path = path.into_os_string();
+                map_path.push(".map");
+                let file = fs::File::create(map_path)?;
+                self.write_map_to(&mut io::BufWriter::new(file), Part::Chunk(index))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_modules_to<W: io::Write>(&self, w: &mut W, part: Part) -> io::Result<()> {
+        let scope = self.scope(part);
+        for (file, info) in self.sorted_modules(part) {
+            let in_scope = scope.iter().any(|p| p == file);
+            if in_scope && scope[0] != file {
+                // the modules in the entry point's function keep a line to themselves, like the others' headers
+                write!(w, "\n  // {}\n", self.js_path(file))?;
+            } else {
+                // the entry point's function starts with the first module that runs in it
+                let (file, info) = match scope.last() {
+                    Some(entry_point) if in_scope => (entry_point.as_path(), &self.modules[entry_point]),
+                    _ => (file, info),
+                };
+                let id = self.name_path(file);
+                let deps = self.stringify_deps(&info.deps, part);
+                let filename = self.js_path(file);
+
+                write!(w,
+                    "\n  Pax.files[{filename}] = {id}; {id}.deps = {deps}; {id}.filename = {filename}; function {id}(module, exports, require, __filename, __dirname, __import_meta) {{\n",
+                    filename = filename,
+                    id = id,
+                    deps = deps,
+                )?;
+            }
             if !info.source.prefix.is_empty() {
                 w.write_all(info.source.prefix.as_bytes())?;
                 w.write_all(b"\n")?;
             }
-            w.write_all(info.source.body.as_bytes())?;
-            if !matches!(info.source.body.chars().last(), None | Some('\n') | Some('\r') | Some('\u{2028}') | Some('\u{2029}')) {
+            // the lines stay where they are, so the source map doesn't change
+            let body = match info.source.import_refs {
+                None => Cow::Borrowed(&info.source.body),
+                Some(ref import_refs) => Cow::Owned(idents::rename(&info.source.body, import_refs)),
+            };
+            w.write_all(body.as_bytes())?;
+            if !matches!(body.chars().last(), None | Some('\n') | Some('\r') | Some('\u{2028}') | Some('\u{2029}')) {
                 w.write_all(b"\n")?;
             }
             if !info.source.suffix.is_empty() {
                 w.write_all(info.source.suffix.as_bytes())?;
             }
-            write!(w, "}}")?;
+            if !in_scope || scope.last().map(PathBuf::as_path) == Some(file) {
+                write!(w, "}}")?;
+            }
         }
-        let main = Self::name_path(self.entry_point);
-        write!(w,
-            "\n  Pax.main = {main}; Pax.makeRequire(null)()\n  if (typeof module !== 'undefined') module.exports = Pax.main.module && Pax.main.module.exports\n",
-            main = main,
-        )?;
-        w.write_all(TAIL_JS.as_bytes())?;
-        match *self.map_output {
+        Ok(())
+    }
+
+    fn write_map_url_to<W: io::Write>(&self, w: &mut W, part: Part, map_output: &SourceMapOutput) -> io::Result<()> {
+        match *map_output {
             SourceMapOutput::Suppressed => {}
             SourceMapOutput::Inline => {
                 let mut map = Vec::new();
-                self.write_map_to(&mut map)?;
+                self.write_map_to(&mut map, part)?;
                 write!(w,
                     "//# sourceMappingURL=data:application/json;charset=utf-8;base64,{data}\n",
                     data = base64::encode(&map),
                 )?;
             }
             SourceMapOutput::File(ref path, output_file) => {
+                if let Part::Chunk(chunk) = part {
+                    // chunk maps are always next to their chunks
+                    writeln!(w,
+                        "//# sourceMappingURL={name}.map",
+                        name = self.split.chunks[chunk].name,
+                    )?;
+                    return Ok(())
+                }
                 // TODO handle error
                 let relative = path.relative_from(output_file.parent().unwrap());
                 let map = relative.as_ref().unwrap_or(path);
@@ -197,11 +977,22 @@ impl<'a, 'b> Writer<'a, 'b> {
         Ok(())
     }
 
-    fn write_map_to<W: io::Write>(&self, w: &mut W) -> serde_json::Result<()> {
+    fn write_map_to<W: io::Write>(&self, w: &mut W, part: Part) -> serde_json::Result<()> {
         // https://sourcemaps.info/spec.html
 
-        let ref modules = self.sorted_modules();
-        let dir = self.entry_point.parent().unwrap();
+        let ref modules = self.sorted_modules(part);
+        let dir = self.entry_points[0].parent().unwrap();
+        let (head_len, tail_len) = match part {
+            Part::Bundle(_) => {
+                // the imports and declaration above the head of an ES module
+                let prefix_len = match *self.format {
+                    OutputFormat::Esm => self.external_names().len() + 1,
+                    _ => 0,
+                };
+                (prefix_len + count_lines(HEAD_JS), 2 + count_lines(TAIL_JS) + 1 - 1 - 1)
+            }
+            Part::Chunk(_) => (count_lines(CHUNK_HEAD_JS), count_lines(CHUNK_TAIL_JS) - 1),
+        };
 
         #[derive(Serialize, Debug)]
         #[serde(rename_all = "camelCase")]
@@ -252,6 +1043,8 @@ impl<'a, 'b> Writer<'a, 'b> {
         #[derive(Debug)]
         struct Mappings<'a> {
             modules: &'a [(&'a Path, &'a Module)],
+            head_len: usize,
+            tail_len: usize,
         }
 
         impl<'a> Serialize for Mappings<'a> {
@@ -262,8 +1055,7 @@ impl<'a, 'b> Writer<'a, 'b> {
 
         impl<'a> Display for Mappings<'a> {
             fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
-                let prefix_len = count_lines(HEAD_JS); /*+ this.mains.size*/
-                for _ in 0..prefix_len {
+                for _ in 0..self.head_len {
                     w.write_str(";")?;
                 }
 
@@ -300,7 +1092,7 @@ impl<'a, 'b> Writer<'a, 'b> {
                         w.write_str(";")?;
                     }
                 }
-                for _ in 0..2 + count_lines(TAIL_JS) + 1 - 1 - 1 {
+                for _ in 0..self.tail_len {
                     w.write_str(";")?;
                 }
                 Ok(())
@@ -314,11 +1106,12 @@ impl<'a, 'b> Writer<'a, 'b> {
             sources: Sources { modules, dir },
             sources_content: SourcesContent { modules },
             names: [],
-            mappings: Mappings { modules },
+            mappings: Mappings { modules, head_len, tail_len },
         })
     }
 
-    fn stringify_deps(deps: &FnvHashMap<String, Resolved>) -> String {
+    /// The `deps` of a module in `part`; modules in other files are referred to by their file names, so that they can be loaded later.
+    fn stringify_deps(&self, deps: &FnvHashMap<String, Resolved>, part: Part) -> String {
         let mut result = "{".to_owned();
         let mut comma = false;
         for (name, resolved) in deps {
@@ -332,13 +1125,19 @@ impl<'a, 'b> Writer<'a, 'b> {
                     result.push_str(":Pax.ignored");
                     comma = true;
                 }
+                // modules in an entry point's function have no function of their own to refer to
+                Resolved::Normal(ref path) if self.merged.contains(path) => {}
                 Resolved::Normal(ref path) => {
                     if comma {
                         result.push(',');
                     }
                     result.push_str(&to_quoted_json_string(name));
                     result.push(':');
-                    Self::write_name_path(path, &mut result);
+                    if self.split.part_of(path) == part {
+                        self.write_name_path(path, &mut result);
+                    } else {
+                        result.push_str(&self.js_path(path));
+                    }
                     comma = true;
                 }
             }
@@ -347,26 +1146,37 @@ impl<'a, 'b> Writer<'a, 'b> {
         result
     }
 
+    /// `path` relative to the root, so that the bundle doesn't depend on where the project is.
+    fn relative_path<'p>(&self, path: &'p Path) -> Cow<'p, Path> {
+        match path.relative_from(&self.root) {
+            Some(relative) => Cow::Owned(relative),
+            None => Cow::Borrowed(path),
+        }
+    }
+
     #[cfg(target_os = "windows")]
-    fn js_path(path: &Path) -> String {
+    fn js_path(&self, path: &Path) -> String {
         // TODO untested
+        let path = self.relative_path(path);
         let string = path.to_string_lossy();
         let replaced = string.replace('\\', "/");
         to_quoted_json_string(&replaced)
     }
 
     #[cfg(not(target_os = "windows"))]
-    fn js_path(path: &Path) -> String {
+    fn js_path(&self, path: &Path) -> String {
+        let path = self.relative_path(path);
         let string = path.to_string_lossy();
         to_quoted_json_string(&string)
     }
 
-    fn name_path(path: &Path) -> String {
+    fn name_path(&self, path: &Path) -> String {
         let mut result = String::new();
-        Self::write_name_path(path, &mut result);
+        self.write_name_path(path, &mut result);
         result
     }
-    fn write_name_path(path: &Path, result: &mut String) {
+    fn write_name_path(&self, path: &Path, result: &mut String) {
+        let path = self.relative_path(path);
         let string = path.to_string_lossy();
         // let slice = string.as_ref();
         let bytes = string.as_bytes();
@@ -399,6 +1209,34 @@ fn to_quoted_json_string(s: &str) -> String {
     serde_json::to_string(s).unwrap()
 }
 
+/// The code that a call to `glob` is replaced with, given the key and module name of each file it includes.
+fn glob_replacement(glob: &Glob, entries: &[(String, String)]) -> String {
+    let mut result = String::new();
+    match *glob {
+        Glob::ImportMeta { eager, .. } => {
+            result.push_str("Pax.glob(require, [");
+            for (i, (_, name)) in entries.iter().enumerate() {
+                if i > 0 {
+                    result.push_str(", ");
+                }
+                result.push_str(&to_quoted_json_string(name));
+            }
+            write!(result, "], {})", eager).unwrap();
+        }
+        Glob::RequireContext { .. } => {
+            result.push_str("Pax.context(require, {");
+            for (i, (key, name)) in entries.iter().enumerate() {
+                if i > 0 {
+                    result.push_str(", ");
+                }
+                write!(result, "{}: {}", to_quoted_json_string(key), to_quoted_json_string(name)).unwrap();
+            }
+            result.push_str("})");
+        }
+    }
+    result
+}
+
 fn count_lines(source: &str) -> usize {
     // TODO non-ASCII line terminators?
     1 + memchr::Memchr::new(b'\n', source.as_bytes()).count()
@@ -437,6 +1275,8 @@ const B64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456
 struct WorkerInit {
     tx: mpsc::Sender<Result<WorkDone, CliError>>,
     input_options: InputOptions,
+    format: OutputFormat,
+    cache: Arc<PackageCache>,
     queue: Arc<SegQueue<Work>>,
     quit: Arc<AtomicBool>,
 }
@@ -444,6 +1284,7 @@ struct WorkerInit {
 struct Worker {
     tx: mpsc::Sender<Result<WorkDone, CliError>>,
     resolver: Resolver,
+    format: OutputFormat,
     queue: Arc<SegQueue<Work>>,
     quit: Arc<AtomicBool>,
 }
@@ -451,12 +1292,27 @@ struct Worker {
 #[derive(Debug, Clone, Default)]
 struct Resolver {
     input_options: InputOptions,
-    cache: PackageCache,
+    extensions: Vec<String>,
+    cache: Arc<PackageCache>,
+    tsconfigs: tsconfig::TsConfigCache,
+    warnings: RefCell<Vec<Warning>>,
+    /// The steps taken by the current resolution, with --trace-resolve.
+    trace: RefCell<Option<Vec<TraceStep>>>,
 }
 
-#[derive(Debug, Clone, Default)]
-struct PackageCache {
-    pkgs: RefCell<FnvHashMap<PathBuf, Option<Rc<PackageInfo>>>>,
+/// Parsed package.json files, shared by all workers and kept between builds in watch mode.
+#[derive(Debug, Default)]
+pub struct PackageCache {
+    main_fields: Vec<String>,
+    /// The extensions that browser field keys are resolved with.
+    extensions: Vec<String>,
+    pkgs: RwLock<FnvHashMap<PathBuf, Option<Arc<PackageInfo>>>>,
+    /// The package.json files and PnP manifests that were read, valid or not, and the real paths to watch them at.
+    files: Mutex<FnvHashMap<PathBuf, PathBuf>>,
+    /// The nearest Yarn Plug'n'Play manifest to each directory.
+    pnp: RwLock<FnvHashMap<PathBuf, Option<Arc<pnp::Manifest>>>>,
+    /// The names in each directory, for --check-case.
+    dirs: RwLock<FnvHashMap<PathBuf, Option<Arc<FnvHashSet<OsString>>>>>,
 }
 
 #[derive(Debug)]
@@ -468,21 +1324,41 @@ enum Work {
 enum WorkDone {
     Resolve { context: PathBuf, name: String, resolved: Resolved },
     Include { module: PathBuf, info: ModuleInfo },
+    Warning(Warning),
 }
 #[derive(Debug)]
 enum ModuleState {
     Loading,
-    Loaded(Module),
+    Loaded(Box<Module>),
 }
 #[derive(Debug)]
 pub struct Module {
     pub source: Source,
     pub deps: FnvHashMap<String, Resolved>,
+    /// The names in `deps` that are only imported with `import()`, whose modules can go in separate chunks.
+    pub dynamic_deps: FnvHashSet<String>,
+    /// The directories searched for the files that partially dynamic requires and globs refer to, which are watched for added and removed files.
+    pub dirs: Vec<PathBuf>,
+    /// What the module exports, if it is an ES module.
+    pub exports: Option<Box<es6::ExportNames>>,
+    /// How the module's imports and exports refer to other modules, if it is an ES module.
+    pub linkage: Option<Box<es6::Linkage>>,
+}
+/// The modules that were bundled.
+#[derive(Debug)]
+pub struct Bundled {
+    pub modules: FnvHashMap<PathBuf, Module>,
+    /// The number of duplicate modules that `InputOptions::dedupe` dropped, and their size in bytes.
+    pub deduped: (usize, usize),
 }
 #[derive(Debug)]
 struct ModuleInfo {
     source: Source,
     deps: Vec<String>,
+    dynamic_deps: FnvHashSet<String>,
+    dirs: Vec<PathBuf>,
+    exports: Option<es6::ExportNames>,
+    linkage: Option<Box<es6::Linkage>>,
 }
 #[derive(Debug)]
 pub struct Source {
@@ -490,6 +1366,8 @@ pub struct Source {
     pub body: String,
     pub suffix: String,
     pub original: Option<String>,
+    /// The expressions that references to imported bindings in `body` become when it's written, as `es6::CjsModule::import_refs` says.
+    pub import_refs: Option<Box<FnvHashMap<String, String>>>,
 }
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Resolved {
@@ -499,14 +1377,26 @@ pub enum Resolved {
     Normal(PathBuf),
 }
 #[derive(Debug, Clone, PartialEq, Eq)]
-enum ModuleSubstitution {
+pub enum ModuleSubstitution {
     Normal,
     Ignore,
     External,
-    Replace(String),
+    /// Resolve another name instead, because of --alias or the `browser` field of the package.json in `package`.
+    Replace { name: String, package: Option<PathBuf> },
+    /// Resolve a core module's browser polyfill, or an empty module if there is none.
+    Polyfill(Option<String>),
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ModuleKind {
+    /// ECMAScript module syntax only.
+    Esm,
+    /// ECMAScript module syntax and CJS-style `require()` calls.
+    Mixed,
+    Cjs,
+    Json,
 }
 #[derive(Debug, Clone, PartialEq, Eq)]
-enum PathSubstitution {
+pub enum PathSubstitution {
     Missing,
     Normal,
     Ignore,
@@ -519,6 +1409,25 @@ pub struct InputOptions {
     pub es6_syntax: bool,
     pub es6_syntax_everywhere: bool,
     pub external: FnvHashSet<String>,
+    pub conditions: FnvHashSet<String>,
+    pub main_fields: Vec<String>,
+    pub aliases: Vec<(String, String)>,
+    pub preserve_symlinks: bool,
+    pub resolve_extensions: Vec<String>,
+    pub modules_dirs: Vec<PathBuf>,
+    pub polyfill_core: bool,
+    pub polyfills: Vec<(String, Option<String>)>,
+    pub trace_resolve: bool,
+    pub check_case: Option<CaseCheck>,
+    pub dedupe: bool,
+    pub max_context_files: Option<usize>,
+}
+
+/// What to do when a module's path differs in case from the file on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseCheck {
+    Warn,
+    Error,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -528,37 +1437,254 @@ pub enum SourceMapOutput<'a> {
     File(PathBuf, &'a Path),
 }
 
-impl ModuleState {
-    fn expect(self, message: &str) -> Module {
-        match self {
-            ModuleState::Loading => panic!("{}", message),
-            ModuleState::Loaded(module) => module,
+/// What kind of script a bundle is, which decides how other code gets at the entry point's exports.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// A script that sets `module.exports` when it's loaded as a CommonJS module.
+    #[default]
+    Cjs,
+    /// An ES module that exports what the entry point exports, and imports the external modules.
+    Esm,
+    /// A script that defines an AMD module, sets `module.exports`, or else assigns the exports to the global variable `global_name`, which may be dotted.
+    Umd { global_name: Option<String> },
+    /// A script that assigns the exports to the global variable `global_name`, if any, which may be dotted.
+    Iife { global_name: Option<String> },
+}
+
+/// How to write bundles, as opposed to which modules go in them.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    /// The directory that the names, filenames, and dirnames of modules in the bundle are relative to. Defaults to the package root of the (first) entry point.
+    pub root: Option<PathBuf>,
+    /// Whether to hoist the scopes of ES modules into their entry point's function.
+    pub scope_hoist: bool,
+}
+
+impl OutputOptions {
+    fn root(&self, cache: &PackageCache, entry_point: &Path) -> Result<PathBuf, CliError> {
+        if let Some(ref root) = self.root {
+            return Ok(root.clone())
         }
-    }
-    fn unwrap(self) -> Module {
-        self.expect("unwrapped ModuleState that was still loading")
+        let dir = entry_point.parent().unwrap_or_else(|| Path::new("/")).to_owned();
+        Ok(match cache.nearest_package_info(dir.clone())? {
+            Some(info) => info.dir.clone(),
+            None => dir,
+        })
     }
 }
 
-pub fn bundle(entry_point: &Path, input_options: InputOptions, output: &str, map_output: &SourceMapOutput) -> Result<FnvHashMap<PathBuf, Module>, CliError> {
-    let mut pending = 0;
-    let thread_count = num_cpus::get();
-    let (tx, rx) = mpsc::channel();
-    let worker_init = WorkerInit {
-        tx,
-        input_options,
-        quit: Arc::new(AtomicBool::new(false)),
-        queue: Arc::new(SegQueue::new()),
+impl InputOptions {
+    /// The most files that a partially dynamic require() may include.
+    fn max_context_files(&self) -> usize {
+        self.max_context_files.unwrap_or(100)
+    }
+
+    /// The extensions to try, without the leading '.', in priority order.
+    fn resolve_extensions(&self) -> Vec<String> {
+        if !self.resolve_extensions.is_empty() {
+            return self.resolve_extensions.clone()
+        }
+        let mut extensions = Vec::new();
+        if self.es6_syntax {
+            extensions.push("mjs".to_owned());
+        }
+        extensions.push("js".to_owned());
+        extensions.push("json".to_owned());
+        extensions
+    }
+
+    /// The package.json fields to read a package's entry point from, in priority order.
+    fn main_fields(&self) -> Vec<String> {
+        if !self.main_fields.is_empty() {
+            return self.main_fields.clone()
+        }
+        let mut fields = Vec::new();
+        if self.for_browser {
+            fields.push("browser".to_owned());
+        }
+        if self.es6_syntax {
+            fields.push("module".to_owned());
+        }
+        fields.push("main".to_owned());
+        fields
+    }
+}
+
+impl Module {
+    /// The modules that this module imports with `import()` only, if `dynamic`, or otherwise the other modules it requires.
+    fn module_deps(&self, dynamic: bool) -> impl Iterator<Item = &Path> {
+        self.deps.iter().filter_map(move |(name, resolved)| match *resolved {
+            Resolved::Normal(ref path) if self.dynamic_deps.contains(name) == dynamic => Some(path.as_path()),
+            _ => None,
+        })
+    }
+}
+
+impl ModuleState {
+    fn expect(self, message: &str) -> Module {
+        match self {
+            ModuleState::Loading => panic!("{}", message),
+            ModuleState::Loaded(module) => *module,
+        }
+    }
+    fn unwrap(self) -> Module {
+        self.expect("unwrapped ModuleState that was still loading")
+    }
+}
+
+pub fn bundle(entry_point: &Path, input_options: InputOptions, output: &str, map_output: &SourceMapOutput) -> Result<Bundled, CliError> {
+    let cache = Arc::new(PackageCache::new(&input_options));
+    bundle_with_cache(entry_point, input_options, &cache, output, map_output, &OutputOptions::default())
+}
+
+/// Like `bundle`, but reuses the package.json files in `cache`, which must have been created with the same `input_options`, and writes the bundle as `output_options` say.
+pub fn bundle_with_cache(entry_point: &Path, input_options: InputOptions, cache: &Arc<PackageCache>, output: &str, map_output: &SourceMapOutput, output_options: &OutputOptions) -> Result<Bundled, CliError> {
+    let entry_points = [entry_point.to_owned()];
+    let Bundled { modules, deduped } = build_modules(&entry_points, input_options, cache, &output_options.format)?;
+    let root = output_options.root(cache, &entry_points[0])?;
+    let split = match output {
+        "-" => Split::default(),
+        _ => {
+            let output = Path::new(output);
+            let stem = output.file_stem().map_or(Cow::Borrowed("bundle"), |stem| stem.to_string_lossy());
+            let extension = output.extension().map_or(Cow::Borrowed("js"), |extension| extension.to_string_lossy());
+            split_chunks(&modules, &entry_points, &format!("{}.", stem), &extension, FnvHashSet::default())
+        }
+    };
+    let mut writer = Writer {
+        modules,
+        entry_points: &entry_points,
+        map_output,
+        split,
+        format: &output_options.format,
+        root,
+        scopes: Vec::new(),
+        merged: FnvHashSet::default(),
+    };
+    if output_options.scope_hoist {
+        writer.hoist_scopes();
+    }
+
+    match &*output {
+        "-" => {
+            let stdout = io::stdout();
+            let mut handle = stdout.lock();
+            writer.write_to(&mut handle, 0, map_output)?;
+        }
+        _ => {
+            let output = Path::new(output);
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let file = fs::File::create(&output)?;
+            let mut buf_writer = io::BufWriter::new(file);
+            writer.write_to(&mut buf_writer, 0, map_output)?;
+        }
+    }
+    match *map_output {
+        SourceMapOutput::Suppressed => {}
+        SourceMapOutput::Inline => {
+            // handled in Writer::write_to()
+        }
+        SourceMapOutput::File(ref path, _) => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let file = fs::File::create(path)?;
+            let mut buf_writer = io::BufWriter::new(file);
+            writer.write_map_to(&mut buf_writer, Part::Bundle(0))?;
+        }
+    }
+    writer.write_chunks(Path::new(output).parent().unwrap_or_else(|| Path::new("")))?;
+    // println!("entry point: {:?}", entry_point);
+    // println!("{:#?}", modules);
+
+    Ok(Bundled { modules: writer.modules, deduped })
+}
+
+/// Bundles each of the named `entries` into `<outdir>/<name>.js`, from a single module graph. Modules that several entry points require go in a chunk that every bundle loads. With `SourceMapOutput::File`, the source map of each bundle and chunk is written next to it, whatever the path.
+pub fn bundle_entries(entries: &[(String, PathBuf)], input_options: InputOptions, outdir: &Path, map_output: &SourceMapOutput) -> Result<Bundled, CliError> {
+    let cache = Arc::new(PackageCache::new(&input_options));
+    bundle_entries_with_cache(entries, input_options, &cache, outdir, map_output, &OutputOptions::default())
+}
+
+/// Like `bundle_entries`, but reuses the package.json files in `cache`, which must have been created with the same `input_options`, and writes the bundles as `output_options` say. ES module bundles and chunks are named `.mjs` instead of `.js`.
+pub fn bundle_entries_with_cache(entries: &[(String, PathBuf)], input_options: InputOptions, cache: &Arc<PackageCache>, outdir: &Path, map_output: &SourceMapOutput, output_options: &OutputOptions) -> Result<Bundled, CliError> {
+    let entry_points = entries.iter().map(|(_, path)| path.clone()).collect::<Vec<_>>();
+    let Bundled { modules, deduped } = build_modules(&entry_points, input_options, cache, &output_options.format)?;
+    let root = output_options.root(cache, &entry_points[0])?;
+    // node only runs ES modules as such if they're named .mjs
+    let extension = match output_options.format {
+        OutputFormat::Esm => "mjs",
+        _ => "js",
+    };
+    let names = entries.iter().map(|(name, _)| format!("{}.{}", name, extension)).collect();
+    let split = split_chunks(&modules, &entry_points, "", extension, names);
+    let mut writer = Writer {
+        modules,
+        entry_points: &entry_points,
+        map_output,
+        split,
+        format: &output_options.format,
+        root,
+        scopes: Vec::new(),
+        merged: FnvHashSet::default(),
+    };
+    if output_options.scope_hoist {
+        writer.hoist_scopes();
+    }
+
+    fs::create_dir_all(outdir)?;
+    for (index, (name, _)) in entries.iter().enumerate() {
+        let output = outdir.join(format!("{}.{}", name, extension));
+        let bundle_map_output = match *map_output {
+            SourceMapOutput::File(..) => {
+                let mut map = output.clone().into_os_string();
+                map.push(".map");
+                SourceMapOutput::File(PathBuf::from(map), &output)
+            }
+            ref other => other.clone(),
+        };
+        let file = fs::File::create(&output)?;
+        writer.write_to(&mut io::BufWriter::new(file), index, &bundle_map_output)?;
+        if let SourceMapOutput::File(ref path, _) = bundle_map_output {
+            let file = fs::File::create(path)?;
+            writer.write_map_to(&mut io::BufWriter::new(file), Part::Bundle(index))?;
+        }
+    }
+    writer.write_chunks(outdir)?;
+
+    Ok(Bundled { modules: writer.modules, deduped })
+}
+
+/// Finds and reads every module that `entry_points` require, in parallel, for a bundle in `format`.
+fn build_modules(entry_points: &[PathBuf], input_options: InputOptions, cache: &Arc<PackageCache>, format: &OutputFormat) -> Result<Bundled, CliError> {
+    let mut pending = 0;
+    let thread_count = num_cpus::get();
+    let (tx, rx) = mpsc::channel();
+    let worker_init = WorkerInit {
+        tx,
+        input_options,
+        format: format.clone(),
+        cache: cache.clone(),
+        quit: Arc::new(AtomicBool::new(false)),
+        queue: Arc::new(SegQueue::new()),
     };
 
     // TODO: context.require('…')
     // TODO: watch for missing files on error?
 
     let mut modules = FnvHashMap::<PathBuf, ModuleState>::default();
-
-    worker_init.add_work(Work::Include { module: entry_point.to_owned() });
-    pending += 1;
-    modules.insert(entry_point.to_owned(), ModuleState::Loading);
+    let mut warnings = FnvHashSet::default();
+
+    for entry_point in entry_points {
+        modules.entry(entry_point.clone()).or_insert_with(|| {
+            worker_init.add_work(Work::Include { module: entry_point.clone() });
+            pending += 1;
+            ModuleState::Loading
+        });
+    }
 
     let children: Vec<_> = (0..thread_count).map(|_| {
         let init = worker_init.clone();
@@ -576,6 +1702,13 @@ pub fn bundle(entry_point: &Path, input_options: InputOptions, output: &str, map
                 worker_init.quit.store(true, Ordering::Relaxed);
                 return Err(error)
             }
+            Ok(WorkDone::Warning(warning)) => {
+                // warnings don't count as work
+                if warnings.insert(warning.to_string()) {
+                    eprintln!("warning: {}", warning);
+                }
+                continue
+            }
             Ok(work_done) => {
                 pending -= 1;
                 work_done
@@ -585,8 +1718,8 @@ pub fn bundle(entry_point: &Path, input_options: InputOptions, output: &str, map
             WorkDone::Resolve { context, name, resolved } => {
                 match *modules.get_mut(&context).unwrap() {
                     ModuleState::Loading => unreachable!(),
-                    ModuleState::Loaded(Module { ref mut deps, .. }) => {
-                        deps.insert(name, resolved.clone());
+                    ModuleState::Loaded(ref mut module) => {
+                        module.deps.insert(name, resolved.clone());
                     }
                 }
                 match resolved {
@@ -602,10 +1735,14 @@ pub fn bundle(entry_point: &Path, input_options: InputOptions, output: &str, map
                 }
             }
             WorkDone::Include { module, info } => {
-                let old = modules.insert(module.clone(), ModuleState::Loaded(Module {
+                let old = modules.insert(module.clone(), ModuleState::Loaded(Box::new(Module {
                     source: info.source,
                     deps: FnvHashMap::default(),
-                }));
+                    dynamic_deps: info.dynamic_deps,
+                    dirs: info.dirs,
+                    exports: info.exports.map(Box::new),
+                    linkage: info.linkage,
+                })));
                 debug_assert_matches!(old, Some(ModuleState::Loading));
                 for dep in info.deps {
                     worker_init.add_work(Work::Resolve {
@@ -615,6 +1752,7 @@ pub fn bundle(entry_point: &Path, input_options: InputOptions, output: &str, map
                     pending += 1;
                 }
             }
+            WorkDone::Warning(_) => unreachable!(),
         }
         if pending == 0 {
             break
@@ -626,48 +1764,203 @@ pub fn bundle(entry_point: &Path, input_options: InputOptions, output: &str, map
         child.join()?;
     }
 
-    let writer = Writer {
-        modules: modules.into_iter()
+    let mut modules = modules.into_iter()
         .map(|(k, ms)| (k, ms.unwrap()))
-        .collect(),
-        entry_point,
-        map_output,
+        .collect();
+    let deduped = if worker_init.input_options.dedupe {
+        dedupe(&mut modules, entry_points, cache)?
+    } else {
+        (0, 0)
     };
+    Ok(Bundled { modules, deduped })
+}
 
-    match &*output {
-        "-" => {
-            let stdout = io::stdout();
-            let mut handle = stdout.lock();
-            writer.write_to(&mut handle)?;
+/// Makes every module refer to one copy of each file that's in several installs of the same version of a package, then drops the modules that are no longer required. Returns the number of modules dropped and their size in bytes.
+fn dedupe(modules: &mut FnvHashMap<PathBuf, Module>, entry_points: &[PathBuf], cache: &PackageCache) -> Result<(usize, usize), CliError> {
+    let mut copies = FnvHashMap::<(String, String, PathBuf), Vec<&Path>>::default();
+    for path in modules.keys() {
+        let info = match path.parent() {
+            Some(dir) => cache.nearest_package_info(dir.to_owned())?,
+            None => None,
+        };
+        if let Some(info) = info {
+            if let (Some(name), Some(version), Ok(inner)) = (&info.name, &info.version, path.strip_prefix(&info.dir)) {
+                copies.entry((name.clone(), version.clone(), inner.to_owned())).or_default().push(path);
+            }
         }
-        _ => {
-            let output = Path::new(output);
-            if let Some(parent) = output.parent() {
-                fs::create_dir_all(parent)?;
+    }
+
+    copies.retain(|_, paths| paths.len() >= 2);
+    let keys: FnvHashMap<&Path, &(String, String, PathBuf)> = copies.iter()
+        .flat_map(|(key, paths)| paths.iter().map(move |path| (*path, key)))
+        .collect();
+    // copies are only the same if their dependencies are the same files or copies of them, which may have nested dependencies of their own
+    let mut same: FnvHashSet<_> = copies.keys().collect();
+    loop {
+        let same_dep = |a: &Resolved, b: &Resolved| match (a, b) {
+            (Resolved::Normal(a), Resolved::Normal(b)) => a == b || keys.get(a.as_path()).is_some_and(|key| keys.get(b.as_path()) == Some(key) && same.contains(key)),
+            _ => a == b,
+        };
+        let differing: Vec<_> = same.iter()
+            .filter(|key| {
+                let paths = &copies[**key];
+                let deps = &modules[paths[0]].deps;
+                paths[1..].iter().any(|path| {
+                    let other_deps = &modules[*path].deps;
+                    other_deps.len() != deps.len() || deps.iter().any(|(name, resolved)| other_deps.get(name).is_none_or(|other| !same_dep(resolved, other)))
+                })
+            })
+            .cloned()
+            .collect();
+        if differing.is_empty() { break }
+        for key in &differing {
+            same.remove(key);
+        }
+    }
+
+    let mut canonical = FnvHashMap::default();
+    for key in same {
+        let mut paths = copies[key].clone();
+        // prefer an entry point, then the least nested copy
+        paths.sort_by_key(|path| (!entry_points.iter().any(|entry_point| entry_point == path), path.components().count(), path.to_owned()));
+        for path in &paths[1..] {
+            canonical.insert(path.to_path_buf(), paths[0].to_owned());
+        }
+    }
+    if canonical.is_empty() {
+        return Ok((0, 0))
+    }
+    for module in modules.values_mut() {
+        for resolved in module.deps.values_mut() {
+            if let Resolved::Normal(ref mut path) = *resolved {
+                if let Some(new_path) = canonical.get(path) {
+                    *path = new_path.clone();
+                }
             }
-            let file = fs::File::create(&output)?;
-            let mut buf_writer = io::BufWriter::new(file);
-            writer.write_to(&mut buf_writer)?;
         }
     }
-    match *map_output {
-        SourceMapOutput::Suppressed => {}
-        SourceMapOutput::Inline => {
-            // handled in Writer::write_to()
+
+    // the duplicates' own dependencies may not be required by anything else
+    let mut required = FnvHashSet::default();
+    let mut stack = entry_points.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+    while let Some(path) = stack.pop() {
+        if !required.insert(path) { continue }
+        if let Some(module) = modules.get(path) {
+            stack.extend(module.deps.values().filter_map(|resolved| match *resolved {
+                Resolved::Normal(ref path) => Some(path.as_path()),
+                _ => None,
+            }));
         }
-        SourceMapOutput::File(ref path, _) => {
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent)?;
+    }
+    let unused: Vec<_> = modules.keys()
+        .filter(|path| !required.contains(path.as_path()))
+        .cloned()
+        .collect();
+    let mut bytes = 0;
+    for path in &unused {
+        let source = &modules[path].source;
+        bytes += source.prefix.len() + source.body.len() + source.suffix.len();
+        modules.remove(path);
+    }
+    Ok((unused.len(), bytes))
+}
+
+/// Decides which file each module goes in. Modules that only one entry point can reach go in its bundle, and modules that several can reach go in a shared chunk. Modules that are only reachable through `import()` go in chunks, one for each set of `import()`ed modules that they're reachable from. Chunks are named `<prefix><name>.js`, avoiding the file names in `names`.
+fn split_chunks(modules: &FnvHashMap<PathBuf, Module>, entry_points: &[PathBuf], prefix: &str, extension: &str, mut names: FnvHashSet<String>) -> Split {
+    /// The modules that `root` requires, directly or indirectly, including those it imports with `import()` if `dynamic`.
+    fn reachable<'a>(modules: &'a FnvHashMap<PathBuf, Module>, root: &'a Path, dynamic: bool) -> FnvHashSet<&'a Path> {
+        let mut seen = FnvHashSet::default();
+        let mut stack = vec![root];
+        while let Some(path) = stack.pop() {
+            if !seen.insert(path) { continue }
+            if let Some(module) = modules.get(path) {
+                stack.extend(module.module_deps(false));
+                if dynamic {
+                    stack.extend(module.module_deps(true));
+                }
             }
-            let file = fs::File::create(path)?;
-            let mut buf_writer = io::BufWriter::new(file);
-            writer.write_map_to(&mut buf_writer)?;
         }
+        seen
+    }
+    let mut unique_name = |base: &str| {
+        let mut name = format!("{}{}.{}", prefix, base, extension);
+        let mut n = 1;
+        while !names.insert(name.clone()) {
+            n += 1;
+            name = format!("{}{}-{}.{}", prefix, base, n, extension);
+        }
+        name
+    };
+
+    let mut split = Split::default();
+    let required = entry_points.iter().map(|entry_point| reachable(modules, entry_point, false)).collect::<Vec<_>>();
+    let pages = entry_points.iter().map(|entry_point| reachable(modules, entry_point, true)).collect::<Vec<_>>();
+    let mut shared = Vec::new();
+    for path in modules.keys() {
+        let mut required_by = required.iter().enumerate().filter(|(_, set)| set.contains(path.as_path()));
+        let entry = match required_by.next() {
+            Some((entry, _)) => entry,
+            None => continue,
+        };
+        // a module imported with import() may also be needed on other pages
+        if required_by.next().is_none() && pages.iter().filter(|set| set.contains(path.as_path())).count() == 1 {
+            split.parts.insert(path.clone(), Part::Bundle(entry));
+        } else {
+            shared.push(path.clone());
+        }
+    }
+    if !shared.is_empty() {
+        shared.sort();
+        let chunk = split.chunks.len();
+        for path in &shared {
+            split.parts.insert(path.clone(), Part::Chunk(chunk));
+        }
+        split.chunks.push(Chunk {
+            name: unique_name("shared"),
+            modules: shared,
+        });
+        split.shared = Some(chunk);
     }
-    // println!("entry point: {:?}", entry_point);
-    // println!("{:#?}", modules);
 
-    Ok(writer.modules)
+    let mut roots = modules.values()
+        .flat_map(|module| module.module_deps(true))
+        .filter(|path| !split.parts.contains_key(*path))
+        .collect::<Vec<_>>();
+    roots.sort();
+    roots.dedup();
+    let mut reached_from = FnvHashMap::<&Path, Vec<usize>>::default();
+    for (index, root) in roots.iter().enumerate() {
+        for path in reachable(modules, root, false) {
+            if !split.parts.contains_key(path) {
+                reached_from.entry(path).or_default().push(index);
+            }
+        }
+    }
+    let mut reached_from = reached_from.into_iter().collect::<Vec<_>>();
+    reached_from.sort_by(|(p, a), (q, b)| a.cmp(b).then_with(|| p.cmp(q)));
+
+    split.loads = roots.iter().map(|root| (root.to_path_buf(), Vec::new())).collect();
+    let mut last_indices = None;
+    for (path, indices) in reached_from {
+        if last_indices.as_ref() != Some(&indices) {
+            let base = indices.iter()
+                .map(|&index| roots[index].file_stem().map_or(Cow::Borrowed("chunk"), |stem| stem.to_string_lossy()))
+                .collect::<Vec<_>>()
+                .join("~");
+            for &index in &indices {
+                split.loads[index].1.push(split.chunks.len());
+            }
+            split.chunks.push(Chunk {
+                name: unique_name(&base),
+                modules: Vec::new(),
+            });
+            last_indices = Some(indices);
+        }
+        let chunk = split.chunks.len() - 1;
+        split.chunks[chunk].modules.push(path.to_owned());
+        split.parts.insert(path.to_owned(), Part::Chunk(chunk));
+    }
+    split
 }
 
 fn run() -> Result<(), CliError> {
@@ -684,8 +1977,32 @@ fn run() -> Result<(), CliError> {
     let mut watch = false;
     let mut quiet_watch = false;
     let mut external = FnvHashSet::default();
-
-    let mut iter = opts::args();
+    let mut conditions = FnvHashSet::default();
+    let mut main_fields = Vec::new();
+    let mut aliases = Vec::new();
+    let mut preserve_symlinks = false;
+    let mut resolve_extensions = Vec::new();
+    let mut modules_dirs = Vec::new();
+    let mut node_path = false;
+    let mut polyfill_core = false;
+    let mut polyfills = Vec::new();
+    let mut trace_resolve = false;
+    let mut check_case = None;
+    let mut dedupe = false;
+    let mut max_context_files = None;
+    let mut outdir = None;
+    let mut format = None;
+    let mut global_name = None;
+    let mut root = None;
+    let mut scope_hoist = false;
+    let mut extra_args = Vec::new();
+
+    let mut args = env::args().skip(1).peekable();
+    let why = args.peek().is_some_and(|arg| arg == "why");
+    if why {
+        args.next();
+    }
+    let mut iter = opts::expand(args);
     while let Some(arg) = iter.next() {
         let opt = match arg {
             opts::Arg::Pos(arg) => {
@@ -694,7 +2011,7 @@ fn run() -> Result<(), CliError> {
                 } else if output.is_none() {
                     output = Some(arg)
                 } else {
-                    return Err(CliError::UnexpectedArg(arg))
+                    extra_args.push(arg)
                 }
                 continue
             }
@@ -717,14 +2034,89 @@ fn run() -> Result<(), CliError> {
                 es6_syntax_everywhere = true;
             }
             "-x" | "--external" => {
-                lazy_static! {
-                    static ref COMMA: Regex = Regex::new(r#"\s*,\s*"#).unwrap();
-                }
                 let mods = iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))?;
                 for m in COMMA.split(&mods) {
                     external.insert(m.to_string());
                 }
             }
+            "-C" | "--conditions" => {
+                let conds = iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))?;
+                for c in COMMA.split(&conds) {
+                    conditions.insert(c.to_string());
+                }
+            }
+            "--main-fields" => {
+                if !main_fields.is_empty() {
+                    return Err(CliError::DuplicateOption(opt))
+                }
+                let fields = iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))?;
+                main_fields.extend(COMMA.split(&fields).map(str::to_owned));
+            }
+            "-a" | "--alias" => {
+                let pairs = iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))?;
+                for pair in COMMA.split(&pairs) {
+                    let mut parts = pair.splitn(2, '=');
+                    match (parts.next(), parts.next()) {
+                        (Some(from), Some(to)) if !from.is_empty() && !to.is_empty() => {
+                            aliases.push((from.to_owned(), to.to_owned()));
+                        }
+                        _ => return Err(CliError::BadUsage("--alias expects <from>=<to>")),
+                    }
+                }
+            }
+            "--preserve-symlinks" => preserve_symlinks = true,
+            "--modules-dir" => {
+                let dirs = iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))?;
+                modules_dirs.extend(COMMA.split(&dirs).map(PathBuf::from));
+            }
+            "--node-path" => node_path = true,
+            "--resolve-extensions" => {
+                if !resolve_extensions.is_empty() {
+                    return Err(CliError::DuplicateOption(opt))
+                }
+                let exts = iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))?;
+                for ext in COMMA.split(&exts) {
+                    let ext = ext.trim_start_matches('.');
+                    if ext.is_empty() {
+                        return Err(CliError::BadUsage("--resolve-extensions expects non-empty extensions"))
+                    }
+                    resolve_extensions.push(ext.to_owned());
+                }
+            }
+            "--trace-resolve" => trace_resolve = true,
+            "--dedupe" => dedupe = true,
+            "--max-context-files" => {
+                if max_context_files.is_some() {
+                    return Err(CliError::DuplicateOption(opt))
+                }
+                let limit = iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))?;
+                max_context_files = Some(limit.parse().map_err(|_| CliError::BadUsage("--max-context-files expects a number"))?);
+            }
+            "--check-case" => {
+                if check_case.is_some() {
+                    return Err(CliError::DuplicateOption(opt))
+                }
+                check_case = Some(match &*iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))? {
+                    "warn" => CaseCheck::Warn,
+                    "error" => CaseCheck::Error,
+                    _ => return Err(CliError::BadUsage("--check-case expects 'warn' or 'error'")),
+                });
+            }
+            "--polyfill-core" => polyfill_core = true,
+            "--polyfill" => {
+                let pairs = iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))?;
+                for pair in COMMA.split(&pairs) {
+                    let mut parts = pair.splitn(2, '=');
+                    match (parts.next(), parts.next()) {
+                        (Some(from), Some(to)) if CORE_MODULES.contains(&from) => {
+                            let to = if to.is_empty() { None } else { Some(to.to_owned()) };
+                            polyfills.push((from.to_owned(), to));
+                        }
+                        _ => return Err(CliError::BadUsage("--polyfill expects <core module>=<polyfill>")),
+                    }
+                }
+                polyfill_core = true;
+            }
             "--external-core" => {
                 for m in CORE_MODULES {
                     external.insert(m.to_string());
@@ -742,6 +2134,41 @@ fn run() -> Result<(), CliError> {
                 }
                 input = Some(iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))?)
             }
+            "--outdir" => {
+                if outdir.is_some() {
+                    return Err(CliError::DuplicateOption(opt))
+                }
+                outdir = Some(iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))?)
+            }
+            "--format" => {
+                if format.is_some() {
+                    return Err(CliError::DuplicateOption(opt))
+                }
+                format = Some(match &*iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))? {
+                    "cjs" => OutputFormat::Cjs,
+                    "esm" => OutputFormat::Esm,
+                    "umd" => OutputFormat::Umd { global_name: None },
+                    "iife" => OutputFormat::Iife { global_name: None },
+                    _ => return Err(CliError::BadUsage("--format expects 'cjs', 'esm', 'umd', or 'iife'")),
+                });
+            }
+            "--global-name" => {
+                if global_name.is_some() {
+                    return Err(CliError::DuplicateOption(opt))
+                }
+                let name = iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))?;
+                if !GLOBAL_NAME.is_match(&name) {
+                    return Err(CliError::InvalidGlobalName(name))
+                }
+                global_name = Some(name);
+            }
+            "--root" => {
+                if root.is_some() {
+                    return Err(CliError::DuplicateOption(opt))
+                }
+                root = Some(PathBuf::from(iter.next_arg().ok_or_else(|| CliError::MissingOptionValue(opt))?))
+            }
+            "--scope-hoist" => scope_hoist = true,
             "-o" | "--output" => {
                 if output.is_some() {
                     return Err(CliError::DuplicateOption(opt))
@@ -758,9 +2185,78 @@ fn run() -> Result<(), CliError> {
         return Err(CliError::BadUsage("--map-inline, --map <file>, and --no-map are mutually exclusive"))
     }
 
-    let input = input.ok_or(CliError::MissingFileName)?;
+    let mut format = format.unwrap_or_default();
+    match format {
+        OutputFormat::Umd { global_name: ref mut name } |
+        OutputFormat::Iife { global_name: ref mut name } => *name = global_name,
+        _ if global_name.is_some() => return Err(CliError::BadUsage("--global-name only applies to --format umd and --format iife")),
+        _ => {}
+    }
+
+    let inputs = match outdir {
+        Some(_) if why => return Err(CliError::BadUsage("px why doesn't take --outdir")),
+        Some(_) => {
+            if map.is_some() {
+                return Err(CliError::BadUsage("--outdir writes each source map next to its bundle, so it can't be used with --map"))
+            }
+            let mut inputs = Vec::new();
+            let mut names = FnvHashSet::default();
+            for arg in input.take().into_iter().chain(output.take()).chain(extra_args) {
+                let (name, path) = match arg.find('=') {
+                    Some(i) => (arg[..i].to_owned(), arg[i+1..].to_owned()),
+                    None => (Path::new(&arg).file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()), arg),
+                };
+                if name.is_empty() || name.contains(&['/', '\\'][..]) {
+                    return Err(CliError::InvalidEntryName(name))
+                }
+                if !names.insert(name.clone()) {
+                    return Err(CliError::DuplicateEntryName(name))
+                }
+                inputs.push((name, path));
+            }
+            inputs
+        }
+        None => {
+            if let Some(arg) = extra_args.into_iter().next() {
+                return Err(CliError::UnexpectedArg(arg))
+            }
+            vec![(String::new(), input.ok_or(CliError::MissingFileName)?)]
+        }
+    };
+    if inputs.is_empty() {
+        return Err(CliError::MissingFileName)
+    }
+    let why_name = if why {
+        Some(output.take().ok_or(CliError::BadUsage("px why expects <context> <name>"))?)
+    } else {
+        None
+    };
     let input_dir = env::current_dir()?;
-    let output = output.unwrap_or_else(|| "-".to_owned());
+
+    // relative aliases are relative to the working directory, not the importing module
+    for (_, to) in &mut aliases {
+        if Path::new(to).is_explicitly_relative() {
+            let mut path = input_dir.clone();
+            path.append_resolving(&*to);
+            let mut new_to = path.to_string_lossy().into_owned();
+            if to.ends_with('/') && !new_to.ends_with('/') {
+                new_to.push('/');
+            }
+            *to = new_to;
+        }
+    }
+    if node_path {
+        if let Some(paths) = env::var_os("NODE_PATH") {
+            modules_dirs.extend(env::split_paths(&paths).filter(|p| !p.as_os_str().is_empty()));
+        }
+    }
+    for dir in &mut modules_dirs {
+        dir.prepend_resolving(&input_dir);
+    }
+    if let Some(ref mut root) = root {
+        root.prepend_resolving(&input_dir);
+    }
+    let output = outdir.clone().or(output).unwrap_or_else(|| "-".to_owned());
 
     let map_output = if map_inline {
         SourceMapOutput::Inline
@@ -788,12 +2284,51 @@ fn run() -> Result<(), CliError> {
         es6_syntax,
         es6_syntax_everywhere,
         external,
+        conditions,
+        main_fields,
+        aliases,
+        preserve_symlinks,
+        resolve_extensions,
+        modules_dirs,
+        polyfill_core,
+        polyfills,
+        trace_resolve,
+        check_case,
+        dedupe,
+        max_context_files,
+    };
+
+    if let Some(name) = why_name {
+        let mut context = input_dir;
+        context.append_resolving(&inputs[0].1);
+        return explain_resolve(&context, &name, input_options)
+    }
+
+    let output_options = OutputOptions {
+        format,
+        root,
+        scope_hoist,
     };
 
-    let entry_point = match Resolver::new(input_options.clone()).resolve_main(input_dir, &input)? {
-        Resolved::External => return Err(CliError::ExternalMain),
-        Resolved::Ignore => return Err(CliError::IgnoredMain),
-        Resolved::Normal(path) => path,
+    let cache = Arc::new(PackageCache::new(&input_options));
+    let resolver = Resolver::with_cache(input_options.clone(), cache.clone());
+    let mut entries = Vec::new();
+    for (name, input) in inputs {
+        match resolver.resolve_main(input_dir.clone(), &input)? {
+            Resolved::External => return Err(CliError::ExternalMain),
+            Resolved::Ignore => return Err(CliError::IgnoredMain),
+            Resolved::Normal(path) => entries.push((name, path)),
+        }
+    }
+    let build = || {
+        let Bundled { modules, deduped: (count, bytes) } = match outdir {
+            Some(ref outdir) => bundle_entries_with_cache(&entries, input_options.clone(), &cache, Path::new(outdir), &map_output, &output_options)?,
+            None => bundle_with_cache(&entries[0].1, input_options.clone(), &cache, &output, &map_output, &output_options)?,
+        };
+        if count > 0 {
+            eprintln!("dedupe: removed {} duplicate module{}, saving {} bytes", count, if count == 1 { "" } else { "s" }, bytes);
+        }
+        Ok(modules)
     };
 
     if watch {
@@ -801,7 +2336,7 @@ fn run() -> Result<(), CliError> {
         eprint!("{}", progress_line);
         io::Write::flush(&mut io::stderr())?;
 
-        let mut modules = match bundle(&entry_point, input_options.clone(), &output, &map_output) {
+        let modules = match build() {
             Ok(mods) => mods,
             Err(e) => {
                 eprintln!();
@@ -815,7 +2350,16 @@ fn run() -> Result<(), CliError> {
         let debounce_dur = time::Duration::from_millis(5);
         let mut watcher = notify::raw_watcher(tx.clone())?;
 
-        for path in modules.keys() {
+        // watch the files themselves, even when modules are named by their symlinks
+        let real_paths = |modules: &FnvHashMap<PathBuf, Module>| {
+            let mut paths = cache.files();
+            paths.extend(modules.keys()
+                .chain(modules.values().flat_map(|module| &module.dirs))
+                .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone())));
+            paths
+        };
+        let mut watched = real_paths(&modules);
+        for path in &watched {
             watcher.watch(path, notify::RecursiveMode::NonRecursive)?;
         }
 
@@ -826,34 +2370,37 @@ fn run() -> Result<(), CliError> {
             thread::sleep(debounce_dur);
             for event in iter::once(first_event).chain(rx.try_iter()) {
                 let _op = event.op?;
+                if let Some(path) = event.path {
+                    if let Some(dir) = path.parent() {
+                        // a file may have been renamed to fix its case, or a package.json created
+                        cache.invalidate_dir(dir);
+                    }
+                    let is_config = matches!(path.file_name().and_then(|s| s.to_str()), Some(s) if s == "package.json" || pnp::MANIFEST_NAMES.contains(&s));
+                    if is_config && watched.remove(&path) {
+                        // the file may have been replaced, so watch it again once it's been read
+                        cache.invalidate(&path);
+                        let _ = watcher.unwatch(&path);
+                    }
+                }
             }
 
             eprint!("update {} ...", output);
             io::Write::flush(&mut io::stderr())?;
             let start_inst = time::Instant::now();
-            match bundle(&entry_point, input_options.clone(), &output, &map_output) {
+            match build() {
                 Ok(new_modules) => {
                     let elapsed = start_inst.elapsed();
                     let ms = elapsed.as_secs() * 1_000 + u64::from(elapsed.subsec_millis());
                     eprintln!("{bs}in {ms} ms", ms = ms, bs = "\u{8}".repeat(3));
 
-                    {
-                        let mut to_unwatch = modules.keys().collect::<FnvHashSet<_>>();
-                        let mut to_watch = new_modules.keys().collect::<FnvHashSet<_>>();
-                        for path in modules.keys() {
-                            to_watch.remove(&path);
-                        }
-                        for path in new_modules.keys() {
-                            to_unwatch.remove(&path);
-                        }
-                        for path in to_watch {
-                            watcher.watch(path, notify::RecursiveMode::NonRecursive)?;
-                        }
-                        for path in to_unwatch {
-                            watcher.unwatch(path)?;
-                        }
+                    let new_watched = real_paths(&new_modules);
+                    for path in new_watched.difference(&watched) {
+                        watcher.watch(path, notify::RecursiveMode::NonRecursive)?;
                     }
-                    modules = new_modules;
+                    for path in watched.difference(&new_watched) {
+                        watcher.unwatch(path)?;
+                    }
+                    watched = new_watched;
                 }
                 Err(kind) => {
                     eprintln!("{}error: {}", if quiet_watch { "" } else { "\x07" }, kind);
@@ -861,8 +2408,28 @@ fn run() -> Result<(), CliError> {
             }
         }
     } else {
-        bundle(&entry_point, input_options, &output, &map_output).map(|_| ())
+        build().map(|_| ())
+    }
+}
+
+fn explain_resolve(context: &Path, name: &str, input_options: InputOptions) -> Result<(), CliError> {
+    let resolver = Resolver::new(InputOptions {
+        trace_resolve: true,
+        ..input_options
+    });
+    let (result, trace) = resolver.resolve_traced(context, name);
+    for step in trace {
+        println!("{}", step);
     }
+    for warning in resolver.warnings.borrow().iter() {
+        eprintln!("warning: {}", warning);
+    }
+    match result? {
+        Resolved::External => println!("=> external"),
+        Resolved::Ignore => println!("=> empty module"),
+        Resolved::Normal(path) => println!("=> {}", path.display()),
+    }
+    Ok(())
 }
 
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
@@ -872,6 +2439,8 @@ const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 fn write_usage(f: &mut fmt::Formatter) -> fmt::Result {
     write!(f, "\
 Usage: {0} [options] <input> [output]
+       {0} [options] --outdir <dir> <name>=<input>...
+       {0} why [options] <context> <name>
        {0} [-h | --help | -v | --version]", EXE_NAME)
 }
 
@@ -893,6 +2462,43 @@ Options:
         Write bundle to <output> and source map to <output>.map.
         Default: '-' for stdout.
 
+        Modules that are only loaded with import('<module>') go in chunks
+        next to <output> (e.g., bundle.page.js for bundle.js and './page'),
+        each with its own source map, which the bundle loads when they're
+        imported. With stdout, they stay in the bundle.
+
+    --outdir <dir>
+        Bundle each <name>=<input> (or just <input>, named after its file) to
+        <dir>/<name>.js in a single build, with source maps next to them.
+        Modules that several bundles require go in <dir>/shared.js, which
+        every bundle loads before running its <input>. With --format esm,
+        the bundles and chunks are named .mjs instead.
+
+    --format <format>
+        Output a script that sets module.exports when it's required (cjs); an
+        ES module that exports what <input> exports and imports the external
+        modules (esm); a script that defines an AMD module, sets
+        module.exports, or else sets the --global-name (umd); or a script
+        that only sets the --global-name, if any (iife). Chunks of an ES
+        module are ES modules too.
+        Default: cjs.
+
+    --global-name <name>
+        With --format umd or iife, make the exports of <input> available as
+        the global variable <name>, which may be dotted (e.g., MyOrg.MyLib).
+
+    --root <dir>
+        Name modules in the bundle, and set their __filename and __dirname,
+        relative to <dir>, so that the bundle is the same wherever it's built.
+        Default: the nearest directory above <input> with a package.json.
+
+    --scope-hoist
+        Run the ES modules that only <input>'s ES modules import in the same
+        function as <input>, renaming top-level bindings that would collide,
+        so that imports refer to the bindings directly. Modules that are
+        required or imported with import(), and modules that use require(),
+        module, or exports, keep their own functions.
+
     -m, --map <map>
         Output source map to <map>.
 
@@ -934,15 +2540,113 @@ Options:
         leave them as require('<module>') references in the bundle. Specifying
         a path instead of a module name does nothing.
 
+    -a, --alias <from1=to1,from2=to2,...>
+        Resolve references to the module <from1> (and paths inside it, like
+        '<from1>/file') as if they were references to <to1>, etc., before
+        looking in node_modules. If <from1> ends with '/', replace any module
+        name that starts with it. <to1> may be a module name or a path; relative
+        paths are relative to the current directory. For example:
+
+            -a react=preact/compat,@app/=./src/
+
+    --modules-dir <dir1,dir2,...>
+        Look for modules in <dir1>, <dir2>, etc., in that order, when they
+        aren't in any node_modules directory above the module that references
+        them. Relative directories are relative to the current directory.
+
+    --node-path
+        Also look for modules in the directories listed in the NODE_PATH
+        environment variable, after those given with --modules-dir.
+
     --external-core
         Ignore references to node.js core modules like 'events' and leave them
         as require('<module>') references in the bundle.
 
+    --polyfill-core
+        Replace references to node.js core modules like 'events' with their
+        browser polyfills, like 'events' or 'path-browserify', when those are
+        installed. Core modules without an installed polyfill are replaced
+        with an empty module and a warning.
+
+    --polyfill <module1=polyfill1,module2=polyfill2,...>
+        Use <polyfill1> as the polyfill for the core module <module1>, etc. An
+        empty <polyfill1> replaces <module1> with an empty module without a
+        warning. Implies --polyfill-core.
+
     -b, --for-browser
         Perform substitutions specified by the `browser` field in package.json.
 
         https://github.com/defunctzombie/package-browser-field-spec
 
+    -C, --conditions <condition1,condition2,...>
+        Also match <condition1>, <condition2>, etc. (e.g., 'development' or
+        'production') in the `exports` field of package.json. 'default' always
+        matches; 'browser' or 'node' matches depending on --for-browser; and
+        'import' or 'require' matches depending on whether the importing module
+        uses ECMAScript module syntax.
+
+        https://nodejs.org/api/packages.html#conditional-exports
+
+    --main-fields <field1,field2,...>
+        Use the first of <field1>, <field2>, etc. that a package.json has as the
+        package's entry point. With 'module', files in the directory of an
+        entry point chosen that way may use ECMAScript module syntax.
+        Default: 'browser' with --for-browser, then 'module' with --es-syntax,
+        then 'main'.
+
+    --resolve-extensions <ext1,ext2,...>
+        Try the extensions <ext1>, <ext2>, etc., in that order, when a module
+        name or path doesn't name a file, including when looking for
+        'index.<ext>' in directories. .mjs files always use ECMAScript module
+        syntax, .cjs files never do, and .json files are JSON; files with other
+        extensions are treated like .js files.
+        Default: '.mjs' with --es-syntax, then '.js', then '.json'.
+
+    --preserve-symlinks
+        Identify modules by the path they were found at instead of following
+        symlinks to the file itself, like node's option of the same name. By
+        default, a module reachable through several symlinks (e.g., in
+        node_modules installed by pnpm or yarn workspaces) is only included
+        once, and its dependencies are looked up from its real location.
+
+    --dedupe
+        Include each file of a package only once when the same version of the
+        package is installed in several node_modules directories, and report
+        how many bytes that saved. Copies are matched by the name and version
+        in their package.json and by the path of the file in the package.
+
+    --max-context-files <n>
+        Fail when a partially dynamic require() with a relative prefix, like
+
+            require('./locales/' + lang + '.json')
+            require(`./pages/${{name}}`)
+
+        matches more than <n> files. Every file under the prefix's directory
+        with one of --resolve-extensions that matches the static parts of the
+        name is included, so that any of them can be required at runtime. The
+        same limit applies to globs:
+
+            import.meta.glob('./routes/*.js', {{eager: true}})
+            require.context('./routes', true, /\\.js$/)
+
+        Default: 100.
+
+    --check-case <warn|error>
+        Warn about, or fail on, require()s and imports whose path differs in
+        case from the files and directories on disk, e.g., './Button' for
+        'button.js'. Those work on case-insensitive filesystems like the
+        defaults on macOS and Windows, but not on Linux.
+
+    --trace-resolve
+        When a module can't be found, show each step taken to look for it: the
+        substitutions applied, the directories searched, and the paths tried.
+        To show those steps for any module, use:
+
+            px why [options] <context> <name>
+
+        which explains what the module name <name> refers to in the file
+        <context>.
+
     -h, --help
         Print this message.
 
@@ -963,10 +2667,20 @@ pub enum CliError {
     UnknownOption(String),
     UnexpectedArg(String),
     BadUsage(&'static str),
+    InvalidEntryName(String),
+    DuplicateEntryName(String),
+    InvalidGlobalName(String),
 
     RequireRoot { context: Option<PathBuf>, path: PathBuf },
     EmptyModuleName { context: PathBuf },
-    ModuleNotFound { context: PathBuf, name: String },
+    ModuleNotFound { context: PathBuf, name: String, searched: Vec<PathBuf> },
+    PackagePathNotExported { context: PathBuf, name: String, subpath: String, package: PathBuf },
+    PackageImportNotDefined { context: PathBuf, name: String, package: Option<PathBuf> },
+    SubstitutionCycle { context: PathBuf, names: Vec<String>, package: Option<PathBuf> },
+    CaseMismatch { context: PathBuf, path: PathBuf, actual: PathBuf },
+    ContextTooLarge { context: PathBuf, request: String, limit: usize },
+    InvalidGlob { context: PathBuf, glob: String, reason: String },
+    Traced { error: Box<CliError>, trace: Vec<TraceStep> },
     MainNotFound { name: String },
 
     InvalidUtf8 { context: PathBuf, err: string::FromUtf8Error },
@@ -975,6 +2689,8 @@ pub enum CliError {
     Json(serde_json::Error),
     Notify(notify::Error),
     Es6(es6::Error),
+    TsConfig(tsconfig::Error),
+    Pnp(pnp::Error),
     Lex(lex::Error),
     ParseStrLit(lex::ParseStrLitError),
     Box(Box<Any + Send + 'static>),
@@ -999,6 +2715,16 @@ impl From<es6::Error> for CliError {
         CliError::Es6(inner)
     }
 }
+impl From<tsconfig::Error> for CliError {
+    fn from(inner: tsconfig::Error) -> CliError {
+        CliError::TsConfig(inner)
+    }
+}
+impl From<pnp::Error> for CliError {
+    fn from(inner: pnp::Error) -> CliError {
+        CliError::Pnp(inner)
+    }
+}
 impl From<lex::Error> for CliError {
     fn from(inner: lex::Error) -> CliError {
         CliError::Lex(inner)
@@ -1048,8 +2774,17 @@ impl fmt::Display for CliError {
             CliError::BadUsage(ref arg) => {
                 write!(f, "{}", arg)
             }
-
-            CliError::RequireRoot { ref context, ref path } => {
+            CliError::InvalidEntryName(ref name) => {
+                write!(f, "invalid entry point name '{}': use <name>=<input>, where <name> isn't empty and has no slashes", name)
+            }
+            CliError::DuplicateEntryName(ref name) => {
+                write!(f, "more than one entry point is named '{}'", name)
+            }
+            CliError::InvalidGlobalName(ref name) => {
+                write!(f, "invalid global name '{}': use identifiers separated by dots, like MyLib or MyOrg.MyLib", name)
+            }
+
+            CliError::RequireRoot { ref context, ref path } => {
                 match *context {
                     None => {
                         write!(f,
@@ -1069,13 +2804,77 @@ impl fmt::Display for CliError {
             CliError::EmptyModuleName { ref context } => {
                 write!(f, "require('') in {}", context.display())
             }
-            CliError::ModuleNotFound { ref context, ref name } => {
+            CliError::ModuleNotFound { ref context, ref name, ref searched } => {
                 write!(f,
                     "module '{}' not found in {}",
                     name,
                     context.display(),
+                )?;
+                if !searched.is_empty() {
+                    write!(f, "; searched:")?;
+                    for dir in searched {
+                        write!(f, "\n    {}", dir.display())?;
+                    }
+                }
+                Ok(())
+            }
+            CliError::PackagePathNotExported { ref context, ref name, ref subpath, ref package } => {
+                write!(f,
+                    "module '{}' not found in {}: subpath '{}' is not exported by {}",
+                    name,
+                    context.display(),
+                    subpath,
+                    package.join("package.json").display(),
                 )
             }
+            CliError::PackageImportNotDefined { ref context, ref name, ref package } => {
+                match *package {
+                    None => {
+                        write!(f,
+                            "module '{}' not found in {}: no enclosing package.json",
+                            name,
+                            context.display(),
+                        )
+                    }
+                    Some(ref package) => {
+                        write!(f,
+                            "module '{}' not found in {}: '{}' is not defined by the `imports` field of {}",
+                            name,
+                            context.display(),
+                            name,
+                            package.join("package.json").display(),
+                        )
+                    }
+                }
+            }
+            CliError::SubstitutionCycle { ref context, ref names, ref package } => {
+                write!(f, "module substitution cycle in {}: {}", context.display(), names.join(" -> "))?;
+                match *package {
+                    Some(ref package) => write!(f, " in {}", package.join("package.json").display()),
+                    None => write!(f, " from --alias"),
+                }
+            }
+            CliError::CaseMismatch { ref context, ref path, ref actual } => {
+                write_case_mismatch(f, context, path, actual)
+            }
+            CliError::ContextTooLarge { ref context, ref request, limit } => {
+                write!(f,
+                    "{} in {} matches more than {} files; make it more specific or raise --max-context-files",
+                    request,
+                    context.display(),
+                    limit,
+                )
+            }
+            CliError::InvalidGlob { ref context, ref glob, ref reason } => {
+                write!(f, "invalid {} in {}: {}", glob, context.display(), reason)
+            }
+            CliError::Traced { ref error, ref trace } => {
+                write!(f, "{}", error)?;
+                for step in trace {
+                    write!(f, "\n  {}", step)?;
+                }
+                Ok(())
+            }
             CliError::MainNotFound { ref name } => {
                 write!(f, "main module '{}' not found", name)
             }
@@ -1096,6 +2895,12 @@ impl fmt::Display for CliError {
             CliError::Es6(ref inner) => {
                 write!(f, "{}", inner)
             }
+            CliError::TsConfig(ref inner) => {
+                write!(f, "{}", inner)
+            }
+            CliError::Pnp(ref inner) => {
+                write!(f, "{}", inner)
+            }
             CliError::Lex(ref inner) => {
                 write!(f, "{}", inner)
             }
@@ -1109,6 +2914,146 @@ impl fmt::Display for CliError {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceStep {
+    Resolve { context: PathBuf, name: String },
+    Substitute(ModuleSubstitution),
+    TsConfig(PathBuf),
+    ModulesDir(PathBuf),
+    Pnp { manifest: PathBuf, package: PathBuf },
+    Exports { package: PathBuf, subpath: String, resolution: ExportResolution },
+    Imports { package: PathBuf, resolution: ExportResolution },
+    PackageMain { package: PathBuf, field: String },
+    Candidate { path: PathBuf, substitution: PathSubstitution },
+    RealPath(PathBuf),
+}
+
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn write_resolution(f: &mut fmt::Formatter, resolution: &ExportResolution) -> fmt::Result {
+            match *resolution {
+                ExportResolution::Missing => write!(f, "not defined"),
+                ExportResolution::Blocked => write!(f, "blocked"),
+                ExportResolution::Path(ref target) => write!(f, "'{}'", target),
+            }
+        }
+        match *self {
+            TraceStep::Resolve { ref context, ref name } => {
+                write!(f, "resolve '{}' in {}", name, context.display())
+            }
+            TraceStep::Substitute(ModuleSubstitution::Normal) => {
+                write!(f, "  no substitution")
+            }
+            TraceStep::Substitute(ModuleSubstitution::Ignore) => {
+                write!(f, "  substitute an empty module")
+            }
+            TraceStep::Substitute(ModuleSubstitution::External) => {
+                write!(f, "  external")
+            }
+            TraceStep::Substitute(ModuleSubstitution::Replace { ref name, package: Some(ref package) }) => {
+                write!(f, "  substitute '{}' (browser field of {})", name, package.join("package.json").display())
+            }
+            TraceStep::Substitute(ModuleSubstitution::Replace { ref name, package: None }) => {
+                write!(f, "  substitute '{}' (--alias)", name)
+            }
+            TraceStep::Substitute(ModuleSubstitution::Polyfill(Some(ref name))) => {
+                write!(f, "  substitute polyfill '{}'", name)
+            }
+            TraceStep::Substitute(ModuleSubstitution::Polyfill(None)) => {
+                write!(f, "  no polyfill")
+            }
+            TraceStep::TsConfig(ref file) => {
+                write!(f, "  paths in {}", file.display())
+            }
+            TraceStep::ModulesDir(ref dir) => {
+                write!(f, "  look in {}", dir.display())
+            }
+            TraceStep::Pnp { ref manifest, ref package } => {
+                write!(f, "  look in {} (Plug'n'Play manifest {})", package.display(), manifest.display())
+            }
+            TraceStep::Exports { ref package, ref subpath, ref resolution } => {
+                write!(f, "  exports of {} for '{}': ", package.join("package.json").display(), subpath)?;
+                write_resolution(f, resolution)
+            }
+            TraceStep::Imports { ref package, ref resolution } => {
+                write!(f, "  imports of {}: ", package.join("package.json").display())?;
+                write_resolution(f, resolution)
+            }
+            TraceStep::PackageMain { ref package, ref field } => {
+                write!(f, "  '{}' field of {}", field, package.join("package.json").display())
+            }
+            TraceStep::Candidate { ref path, ref substitution } => {
+                write!(f, "  try {}: ", path.display())?;
+                match *substitution {
+                    PathSubstitution::Missing => write!(f, "not found"),
+                    PathSubstitution::Normal => write!(f, "found"),
+                    PathSubstitution::Ignore => write!(f, "ignored by browser field"),
+                    PathSubstitution::Replace(ref to) => write!(f, "replaced with '{}' by browser field", to.display()),
+                }
+            }
+            TraceStep::RealPath(ref path) => {
+                write!(f, "  follow symlinks to {}", path.display())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    NoPolyfill { name: String, polyfill: Option<String> },
+    CaseMismatch { context: PathBuf, path: PathBuf, actual: PathBuf },
+    Context { context: PathBuf, request: ContextRequest, files: usize },
+    UnsupportedContext { context: PathBuf, request: ContextRequest },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Warning::NoPolyfill { ref name, polyfill: Some(ref polyfill) } => {
+                write!(f, "using an empty module for core module '{}' because its polyfill '{}' is not installed", name, polyfill)
+            }
+            Warning::NoPolyfill { ref name, polyfill: None } => {
+                write!(f, "using an empty module for core module '{}' because it has no browser polyfill", name)
+            }
+            Warning::CaseMismatch { ref context, ref path, ref actual } => {
+                write_case_mismatch(f, context, path, actual)
+            }
+            Warning::Context { ref context, ref request, files: 0 } => {
+                write!(f,
+                    "no files match '{}' for a partially dynamic require() in {}",
+                    request,
+                    context.display(),
+                )
+            }
+            Warning::Context { ref context, ref request, files } => {
+                write!(f,
+                    "including {} file{} matching '{}' for a partially dynamic require() in {}, as many as --max-context-files allows",
+                    files,
+                    if files == 1 { "" } else { "s" },
+                    request,
+                    context.display(),
+                )
+            }
+            Warning::UnsupportedContext { ref context, ref request } => {
+                write!(f,
+                    "ignoring partially dynamic require() of '{}' in {}: only relative paths can be included",
+                    request,
+                    context.display(),
+                )
+            }
+        }
+    }
+}
+
+fn write_case_mismatch(f: &mut fmt::Formatter, context: &Path, path: &Path, actual: &Path) -> fmt::Result {
+    write!(f,
+        "{} in {} differs in case from {} on disk",
+        path.display(),
+        context.display(),
+        actual.display(),
+    )
+}
+
 fn main() {
     process::exit(match run() {
         Ok(_) => 0,
@@ -1131,9 +3076,6 @@ fn main() {
 trait PathBufExt {
     fn append_resolving<P: AsRef<Path> + ?Sized>(&mut self, more: &P);
     fn prepend_resolving<P: AsRef<Path> + ?Sized>(&mut self, base: &P);
-    fn clear(&mut self);
-    fn replace_with<P: AsRef<Path> + ?Sized>(&mut self, that: &P);
-    fn as_mut_vec(&mut self) -> &mut Vec<u8>;
 }
 impl PathBufExt for PathBuf {
     fn append_resolving<P: AsRef<Path> + ?Sized>(&mut self, more: &P) {
@@ -1160,17 +3102,6 @@ impl PathBufExt for PathBuf {
         mem::swap(self, &mut tmp);
         self.append_resolving(tmp.as_path());
     }
-    fn clear(&mut self) {
-        self.as_mut_vec().clear();
-    }
-    fn replace_with<P: AsRef<Path> + ?Sized>(&mut self, that: &P) {
-        self.clear();
-        self.push(that);
-    }
-
-    fn as_mut_vec(&mut self) -> &mut Vec<u8> {
-        unsafe { &mut *(self as *mut PathBuf as *mut Vec<u8>) }
-    }
 }
 
 trait PathExt {
@@ -1232,7 +3163,8 @@ impl Worker {
     fn new(init: WorkerInit) -> Self {
         Worker {
             tx: init.tx,
-            resolver: Resolver::new(init.input_options),
+            resolver: Resolver::with_cache(init.input_options, init.cache),
+            format: init.format,
             queue: init.queue,
             quit: init.quit,
         }
@@ -1242,11 +3174,17 @@ impl Worker {
         while let Some(work) = self.get_work() {
             let work_done = match work {
                 Work::Resolve { context, name } => {
-                    self.resolver.resolve(&context, &name)
-                    .map(|resolved| WorkDone::Resolve {
+                    let (result, trace) = self.resolver.resolve_traced(&context, &name);
+                    result.map(|resolved| WorkDone::Resolve {
                         context,
                         name,
                         resolved,
+                    }).map_err(|error| {
+                        if trace.is_empty() {
+                            error
+                        } else {
+                            CliError::Traced { error: Box::new(error), trace }
+                        }
                     })
                 }
                 Work::Include { module } => {
@@ -1257,6 +3195,9 @@ impl Worker {
                     })
                 }
             };
+            for warning in self.resolver.warnings.borrow_mut().drain(..) {
+                if self.tx.send(Ok(WorkDone::Warning(warning))).is_err() { return }
+            }
             if self.tx.send(work_done).is_err() { return }
         }
     }
@@ -1279,47 +3220,83 @@ impl Worker {
         let prefix;
         let suffix;
 
+        let mut dirs = Vec::new();
+        let mut dynamic_deps = FnvHashSet::default();
+        let mut exports = None;
+        let mut linkage = None;
+        let mut import_refs = None;
+
         let deps = {
             let path_string = module.to_string_lossy();
             // module.to_str().ok_or("<path with invalid utf-8>")
             let mut lexer = lex::Lexer::new(path_string.as_ref(), &source);
 
             let deps;
-            let ext = module.extension();
-            if matches!(ext, Some(s) if s == "mjs") {
-                let module = es6::module_to_cjs(&mut lexer, false)?;
-                // println!("{:#?}", module);
-                deps = module.deps;
-                prefix = module.source_prefix;
-                suffix = module.source_suffix;
-                new_source = Some(module.source);
-
-            } else if matches!(ext, Some(s) if s == "json") {
-                deps = FnvHashSet::default();
-                prefix = "module.exports =".to_owned();
-                suffix = String::new();
-
-            } else if self.resolver.input_options.es6_syntax_everywhere {
-                let module = es6::module_to_cjs(&mut lexer, true)?;
-                // println!("{:#?}", module);
-                deps = module.deps;
-                prefix = module.source_prefix;
-                suffix = module.source_suffix;
-                new_source = Some(module.source);
-
-            } else {
-                deps = cjs_parse_deps(&mut lexer)?;
-                prefix = String::new();
-                suffix = String::new();
+            let contexts;
+            let globs;
+            match self.resolver.module_kind(module)? {
+                kind @ ModuleKind::Esm |
+                kind @ ModuleKind::Mixed => {
+                    // an ES module bundle is strict mode code
+                    let module = es6::module_to_cjs(&mut lexer, kind == ModuleKind::Mixed, self.format == OutputFormat::Esm)?;
+                    // println!("{:#?}", module);
+                    deps = module.deps;
+                    dynamic_deps.extend(module.dynamic_deps.into_iter().map(Cow::into_owned));
+                    contexts = module.contexts;
+                    globs = module.globs;
+                    exports = module.exports;
+                    linkage = module.linkage.map(Box::new);
+                    prefix = module.source_prefix;
+                    suffix = module.source_suffix;
+                    if !module.import_refs.is_empty() {
+                        import_refs = Some(Box::new(module.import_refs));
+                    }
+                    new_source = Some(module.source);
+                }
+                ModuleKind::Json => {
+                    deps = FnvHashSet::default();
+                    contexts = FnvHashSet::default();
+                    globs = Vec::new();
+                    prefix = "module.exports =".to_owned();
+                    suffix = String::new();
+                }
+                ModuleKind::Cjs => {
+                    let cjs = cjs_parse_deps(&mut lexer)?;
+                    deps = cjs.deps;
+                    contexts = cjs.contexts;
+                    globs = cjs.globs;
+                    prefix = String::new();
+                    suffix = String::new();
+                }
             }
 
             if let Some(error) = lexer.take_error() {
                 return Err(From::from(error))
             }
 
-            deps.into_iter()
+            let mut deps = deps.into_iter()
                 .map(|s| s.into_owned())
-                .collect()
+                .collect::<FnvHashSet<_>>();
+            for request in &contexts {
+                deps.extend(self.resolver.context_names(module, request, &mut dirs)?);
+            }
+            // replace the last call first so that the others' ranges stay the same
+            for call in globs.iter().rev() {
+                let entries = self.resolver.glob_entries(module, &call.glob, &mut dirs)?;
+                deps.extend(entries.iter().map(|(_, name)| name.clone()));
+
+                let body = new_source.get_or_insert_with(|| source.clone());
+                let mut replacement = glob_replacement(&call.glob, &entries);
+                // keep the lines of the rest of the module where the source map says they are
+                for _ in 1..count_lines(&body[call.range.clone()]) {
+                    replacement.push('\n');
+                }
+                body.replace_range(call.range.clone(), &replacement);
+            }
+            // globs may require what's also imported with import()
+            dynamic_deps.retain(|dep| !deps.contains(dep));
+            deps.extend(dynamic_deps.iter().cloned());
+            deps.into_iter().collect()
         };
 
         // Convert hashbang #! to //
@@ -1337,15 +3314,21 @@ impl Worker {
                     body: source,
                     suffix,
                     original: None,
+                    import_refs,
                 },
                 Some(new_source) => Source {
                     prefix,
                     body: new_source,
                     suffix,
                     original: Some(source),
+                    import_refs,
                 }
             },
             deps,
+            dynamic_deps,
+            dirs,
+            exports,
+            linkage,
         })
     }
 
@@ -1368,7 +3351,15 @@ impl Worker {
 
 impl Resolver {
     fn new(input_options: InputOptions) -> Self {
+        let cache = Arc::new(PackageCache::new(&input_options));
+        Self::with_cache(input_options, cache)
+    }
+
+    fn with_cache(input_options: InputOptions, cache: Arc<PackageCache>) -> Self {
         Resolver {
+            cache,
+            extensions: input_options.resolve_extensions(),
+            trace: RefCell::new(if input_options.trace_resolve { Some(Vec::new()) } else { None }),
             input_options,
             ..Default::default()
         }
@@ -1383,14 +3374,55 @@ impl Resolver {
         let path = Path::new(name);
         dir.append_resolving(path);
         let needs_dir = Self::needs_dir(name, path);
-        self.resolve_path_or_module(None, dir, needs_dir, false)?.ok_or_else(|| {
+        let resolved = self.resolve_path_or_module(None, dir, needs_dir, false, &mut Vec::new())?.ok_or_else(|| {
             CliError::MainNotFound {
                 name: name.to_owned(),
             }
-        })
+        })?;
+        self.real_path(resolved)
     }
 
     fn resolve(&self, context: &Path, name: &str) -> Result<Resolved, CliError> {
+        let resolved = self.resolve_name(context, name, &mut Vec::new())?;
+        self.real_path(resolved)
+    }
+
+    /// Like `resolve`, but returns the steps that were taken along with the result, if tracing is on.
+    fn resolve_traced(&self, context: &Path, name: &str) -> (Result<Resolved, CliError>, Vec<TraceStep>) {
+        if let Some(ref mut trace) = *self.trace.borrow_mut() {
+            trace.clear();
+        }
+        let result = self.resolve(context, name);
+        let trace = self.trace.borrow_mut().as_mut().map_or_else(Vec::new, |trace| trace.split_off(0));
+        (result, trace)
+    }
+
+    fn trace<F: FnOnce() -> TraceStep>(&self, step: F) {
+        if let Some(ref mut trace) = *self.trace.borrow_mut() {
+            trace.push(step());
+        }
+    }
+
+    /// Follows symlinks in a resolved path unless --preserve-symlinks was given, so that a module reachable through several links is only included once.
+    fn real_path(&self, resolved: Resolved) -> Result<Resolved, CliError> {
+        match resolved {
+            Resolved::Normal(ref path) if !self.input_options.preserve_symlinks => {
+                let real_path = fs::canonicalize(path)?;
+                if real_path != *path {
+                    self.trace(|| TraceStep::RealPath(real_path.clone()));
+                }
+                Ok(Resolved::Normal(real_path))
+            }
+            resolved => Ok(resolved),
+        }
+    }
+
+    /// Resolves `name` without following symlinks. `substituted` holds the names that were replaced with another to get to `name`, so that substitution cycles are reported instead of recursing forever.
+    fn resolve_name(&self, context: &Path, name: &str, substituted: &mut Vec<String>) -> Result<Resolved, CliError> {
+        self.trace(|| TraceStep::Resolve {
+            context: context.to_owned(),
+            name: name.to_owned(),
+        });
         if name.is_empty() {
             return Err(CliError::EmptyModuleName {
                 context: context.to_owned(),
@@ -1401,10 +3433,11 @@ impl Resolver {
         let needs_dir = Self::needs_dir(name, path);
         if path.is_absolute() {
             Ok(
-                self.resolve_path_or_module(Some(context), path.to_owned(), needs_dir, false)?.ok_or_else(|| {
+                self.resolve_path_or_module(Some(context), path.to_owned(), needs_dir, false, substituted)?.ok_or_else(|| {
                     CliError::ModuleNotFound {
                         context: context.to_owned(),
                         name: name.to_owned(),
+                        searched: Vec::new(),
                     }
                 })?,
             )
@@ -1414,51 +3447,464 @@ impl Resolver {
             debug_assert!(did_pop);
             dir.append_resolving(path);
             Ok(
-                self.resolve_path_or_module(Some(context), dir, needs_dir, false)?.ok_or_else(|| {
+                self.resolve_path_or_module(Some(context), dir, needs_dir, false, substituted)?.ok_or_else(|| {
                     CliError::ModuleNotFound {
                         context: context.to_owned(),
                         name: name.to_owned(),
+                        searched: Vec::new(),
                     }
                 })?,
             )
+        } else if name.starts_with('#') {
+            self.resolve_package_imports(context, name, substituted)
         } else {
-            match self.module_substitution(context, name)? {
+            let substitution = self.module_substitution(context, name)?;
+            self.trace(|| TraceStep::Substitute(substitution.clone()));
+            match substitution {
                 ModuleSubstitution::Ignore => {
                     return Ok(Resolved::Ignore)
                 }
                 ModuleSubstitution::External => {
                     return Ok(Resolved::External)
                 }
-                ModuleSubstitution::Replace(new_name) => {
-                    // TODO: detect cycles
+                ModuleSubstitution::Replace { name: new_name, package } => {
                     // eprintln!("module replace {} => {}", name, &new_name);
-                    return self.resolve(context, &new_name)
+                    return self.resolve_substitute(context, name, &new_name, package, substituted)
+                }
+                ModuleSubstitution::Polyfill(polyfill) => {
+                    if let Some(ref polyfill) = polyfill {
+                        match self.resolve_node_module(context, polyfill, needs_dir, substituted) {
+                            Err(CliError::ModuleNotFound { .. }) => {}
+                            result => return result,
+                        }
+                    }
+                    self.warn(Warning::NoPolyfill {
+                        name: name.to_owned(),
+                        polyfill,
+                    });
+                    return Ok(Resolved::Ignore)
                 }
                 ModuleSubstitution::Normal => {}
             }
 
-            let mut suffix = PathBuf::from("node_modules");
-            for part in path.components() {
-                suffix.push(part);
+            if let Some(result) = self.resolve_tsconfig_paths(context, name, needs_dir, substituted)? {
+                return Ok(result)
             }
 
-            let mut dir = context.to_owned();
-            while dir.pop() {
-                match dir.file_name() {
-                    Some(s) if s == "node_modules" => continue,
-                    _ => {}
+            self.resolve_node_module(context, name, needs_dir, substituted)
+        }
+    }
+
+    /// Looks for `name` in node_modules directories and --modules-dir directories.
+    fn resolve_node_module(&self, context: &Path, name: &str, needs_dir: bool, substituted: &mut Vec<String>) -> Result<Resolved, CliError> {
+        if let Some(result) = self.resolve_pnp(context, name, needs_dir, substituted)? {
+            return Ok(result)
+        }
+
+        let suffix = Path::new(name).components().collect::<PathBuf>();
+        let (package_name, subpath) = Self::split_module_name(name);
+
+        let modules_dirs = self.modules_dirs(context);
+        for modules_dir in &modules_dirs {
+            self.trace(|| TraceStep::ModulesDir(modules_dir.clone()));
+            if let Some(result) = self.resolve_package_exports(context, name, modules_dir.join(package_name), subpath, substituted)? {
+                return Ok(result)
+            }
+            let new_path = modules_dir.join(&suffix);
+            if let Some(result) = self.resolve_path_or_module(Some(context), new_path, needs_dir, false, substituted)? {
+                return Ok(result)
+            }
+        }
+
+        Err(CliError::ModuleNotFound {
+            context: context.to_owned(),
+            name: name.to_owned(),
+            searched: modules_dirs,
+        })
+    }
+
+    /// Looks for `name` in the package that the nearest Yarn Plug'n'Play manifest maps it to from `context`, or returns `None` if there's no manifest or it doesn't cover `context`.
+    fn resolve_pnp(&self, context: &Path, name: &str, needs_dir: bool, substituted: &mut Vec<String>) -> Result<Option<Resolved>, CliError> {
+        let manifest = match context.parent() {
+            Some(dir) => self.cache.nearest_pnp_manifest(dir)?,
+            None => None,
+        };
+        let manifest = match manifest {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        };
+        let (package_name, subpath) = Self::split_module_name(name);
+        let package_dir = match manifest.resolve(context, package_name) {
+            Ok(Some(package_dir)) => package_dir,
+            Ok(None) => return Ok(None),
+            // core modules needn't be declared
+            Err(_) if CORE_MODULES.contains(&package_name) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        self.trace(|| TraceStep::Pnp {
+            manifest: manifest.file.clone(),
+            package: package_dir.clone(),
+        });
+
+        if let Some(result) = self.resolve_package_exports(context, name, package_dir.clone(), subpath, substituted)? {
+            return Ok(Some(result))
+        }
+        let suffix = Path::new(subpath.trim_start_matches('/')).components().collect::<PathBuf>();
+        if let Some(result) = self.resolve_path_or_module(Some(context), package_dir.join(suffix), needs_dir, false, substituted)? {
+            return Ok(Some(result))
+        }
+        Err(CliError::ModuleNotFound {
+            context: context.to_owned(),
+            name: name.to_owned(),
+            searched: vec![package_dir],
+        })
+    }
+
+    fn warn(&self, warning: Warning) {
+        self.warnings.borrow_mut().push(warning);
+    }
+
+    /// The module names that the partially dynamic `request` in `context` can refer to: for each file under its directory with one of --resolve-extensions, the file's path relative to `context`, the path without the extension if it's one of --resolve-extensions, and for index files, the directory's path, whichever of those match the request.
+    fn context_names(&self, context: &Path, request: &ContextRequest, dirs: &mut Vec<PathBuf>) -> Result<Vec<String>, CliError> {
+        let dir_name = match request.dir() {
+            Some(dir_name) => dir_name,
+            None => {
+                self.warn(Warning::UnsupportedContext {
+                    context: context.to_owned(),
+                    request: request.clone(),
+                });
+                return Ok(Vec::new())
+            }
+        };
+
+        let limit = self.input_options.max_context_files();
+        let mut names = Vec::new();
+        let mut files = 0;
+        self.walk_context_dir(context, dir_name, true, dirs, |name, file_name| {
+            // only files that could be required without a partially dynamic require()
+            let path = Path::new(file_name);
+            let (stem, ext) = match (path.file_stem(), path.extension()) {
+                (Some(stem), Some(ext)) if self.extensions.iter().any(|e| ext == e.as_str()) => (stem, ext),
+                _ => return Ok(()),
+            };
+            let mut candidates = vec![name.to_owned()];
+            let without_ext = &name[..name.len() - ext.len() - 1];
+            candidates.push(without_ext.to_owned());
+            if stem == "index" {
+                candidates.push(name[..name.len() - file_name.len() - 1].to_owned());
+            }
+            let len = names.len();
+            names.extend(candidates.into_iter().filter(|name| request.matches(name)));
+            if names.len() > len {
+                files += 1;
+                if files > limit {
+                    return Err(CliError::ContextTooLarge {
+                        context: context.to_owned(),
+                        request: format!("require('{}')", request),
+                        limit,
+                    })
+                }
+            }
+            Ok(())
+        })?;
+
+        // a request that matches nothing is probably a mistake, and one at the limit fails once another file matches
+        if files == 0 || files == limit {
+            self.warn(Warning::Context {
+                context: context.to_owned(),
+                request: request.clone(),
+                files,
+            });
+        }
+        Ok(names)
+    }
+
+    /// The files that `glob` in `context` includes, sorted, as pairs of the key that it maps each file to and the module name that refers to the file.
+    fn glob_entries(&self, context: &Path, glob: &Glob, dirs: &mut Vec<PathBuf>) -> Result<Vec<(String, String)>, CliError> {
+        let invalid = |reason: String| CliError::InvalidGlob {
+            context: context.to_owned(),
+            glob: glob.to_string(),
+            reason,
+        };
+        let limit = self.input_options.max_context_files();
+        let mut entries = FnvHashMap::default();
+        match *glob {
+            Glob::ImportMeta { ref patterns, .. } => {
+                let mut excludes = Vec::new();
+                for pattern in patterns.iter().filter_map(|pattern| pattern.strip_prefix('!')) {
+                    excludes.push(Glob::glob_regex(pattern).map_err(|err| invalid(err.to_string()))?);
                 }
-                let new_path = dir.join(&suffix);
-                if let Some(result) = self.resolve_path_or_module(Some(context), new_path, needs_dir, false)? {
-                    return Ok(result)
+                for pattern in patterns.iter().filter(|pattern| !pattern.starts_with('!')) {
+                    let dir_name = Glob::glob_dir(pattern).ok_or_else(|| {
+                        invalid(format!("'{}' isn't a relative path", pattern))
+                    })?;
+                    let regex = Glob::glob_regex(pattern).map_err(|err| invalid(err.to_string()))?;
+                    let recursive = pattern[dir_name.len()..].contains('/');
+                    self.walk_context_dir(context, dir_name, recursive, dirs, |name, _| {
+                        if regex.is_match(name) && !excludes.iter().any(|exclude| exclude.is_match(name)) {
+                            entries.insert(name.to_owned(), name.to_owned());
+                            if entries.len() > limit {
+                                return Err(CliError::ContextTooLarge {
+                                    context: context.to_owned(),
+                                    request: glob.to_string(),
+                                    limit,
+                                })
+                            }
+                        }
+                        Ok(())
+                    })?;
                 }
             }
+            Glob::RequireContext { ref dir, recursive, ref regexp, ref flags } => {
+                let mut dir_name = dir.clone();
+                if !dir_name.ends_with('/') {
+                    dir_name.push('/');
+                }
+                if !(dir_name.starts_with("./") || dir_name.starts_with("../")) {
+                    return Err(invalid(format!("'{}' isn't a relative path", dir)))
+                }
+                let regex = Glob::regex(regexp, flags).map_err(|err| invalid(err.to_string()))?;
+                self.walk_context_dir(context, &dir_name, recursive, dirs, |name, _| {
+                    let key = format!("./{}", &name[dir_name.len()..]);
+                    if regex.is_match(&key) {
+                        entries.insert(key, name.to_owned());
+                        if entries.len() > limit {
+                            return Err(CliError::ContextTooLarge {
+                                context: context.to_owned(),
+                                request: glob.to_string(),
+                                limit,
+                            })
+                        }
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+        let mut entries = entries.into_iter().collect::<Vec<_>>();
+        entries.sort();
+        Ok(entries)
+    }
 
-            Err(CliError::ModuleNotFound {
+    /// Calls `f` with the name of each file under the directory `dir_name` (relative to `context`, like './dir/' or '../'), like './dir/sub/file.js', and with its file name, and adds each directory it looks in to `dirs`, or the nearest one above it that exists. Only looks in subdirectories if `recursive` is true, and doesn't follow symlinks to them, which may form cycles.
+    fn walk_context_dir<F>(&self, context: &Path, dir_name: &str, recursive: bool, dirs: &mut Vec<PathBuf>, mut f: F) -> Result<(), CliError>
+    where F: FnMut(&str, &str) -> Result<(), CliError> {
+        let mut dir = context.parent().unwrap().to_owned();
+        dir.append_resolving(dir_name);
+        let mut stack = vec![(dir, dir_name.to_owned())];
+        while let Some((dir, dir_name)) = stack.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => {
+                    // watch for the directory being created
+                    dirs.extend(dir.ancestors().skip(1).find(|dir| dir.is_dir()).map(Path::to_owned));
+                    continue
+                }
+            };
+            dirs.push(dir);
+            for entry in entries {
+                let entry = entry?;
+                let file_name = entry.file_name();
+                let file_name = match file_name.to_str() {
+                    Some(file_name) => file_name,
+                    None => continue,
+                };
+                let name = format!("{}{}", dir_name, file_name);
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    if recursive {
+                        stack.push((entry.path(), name + "/"));
+                    }
+                } else if file_type.is_file() || entry.path().is_file() {
+                    f(&name, file_name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_substitute(&self, context: &Path, name: &str, new_name: &str, package: Option<PathBuf>, substituted: &mut Vec<String>) -> Result<Resolved, CliError> {
+        substituted.push(name.to_owned());
+        if substituted.iter().any(|n| n == new_name) {
+            let mut names = substituted.split_off(0);
+            names.push(new_name.to_owned());
+            return Err(CliError::SubstitutionCycle {
                 context: context.to_owned(),
-                name: name.to_owned(),
+                names,
+                package,
             })
         }
+        self.resolve_name(context, new_name, substituted)
+    }
+
+    /// The directories to look for a module name in from `context`: every node_modules directory above it, nearest first, then each --modules-dir.
+    fn modules_dirs(&self, context: &Path) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        let mut dir = context.to_owned();
+        while dir.pop() {
+            match dir.file_name() {
+                Some(s) if s == "node_modules" => continue,
+                _ => {}
+            }
+            dirs.push(dir.join("node_modules"));
+        }
+        dirs.extend(self.input_options.modules_dirs.iter().cloned());
+        dirs
+    }
+
+    fn split_module_name(name: &str) -> (&str, &str) {
+        let end = if name.starts_with('@') {
+            name.match_indices('/').nth(1)
+        } else {
+            name.match_indices('/').next()
+        }.map_or(name.len(), |(i, _)| i);
+        (&name[..end], &name[end..])
+    }
+
+    /// Whether `module` is in the ESM build of a package whose entry point is its "module" field, i.e., under the directory of that entry point.
+    fn in_module_build(&self, module: &Path) -> Result<bool, CliError> {
+        if !self.input_options.es6_syntax {
+            return Ok(false)
+        }
+        let info = match module.parent() {
+            Some(p) => self.cache.nearest_package_info(p.to_owned())?,
+            None => None,
+        };
+        Ok(info.is_some_and(|info| match info.mains.first() {
+            Some((field, main)) if field == "module" => {
+                let build = if main.is_dir() { Some(main.as_path()) } else { main.parent() };
+                build.is_some_and(|build| module.starts_with(build))
+            }
+            _ => false,
+        }))
+    }
+
+    /// How `module` is parsed, by its extension: .mjs files are always ESM, .cjs files are always CJS, and .json files are JSON. Files with any other extension (including those added with --resolve-extensions) are CJS unless --es-syntax-everywhere is given or they're in a package's ESM build.
+    fn module_kind(&self, module: &Path) -> Result<ModuleKind, CliError> {
+        Ok(match module.extension().and_then(|s| s.to_str()) {
+            Some("mjs") => ModuleKind::Esm,
+            Some("cjs") => ModuleKind::Cjs,
+            Some("json") => ModuleKind::Json,
+            _ => {
+                if self.input_options.es6_syntax_everywhere || self.in_module_build(module)? {
+                    ModuleKind::Mixed
+                } else {
+                    ModuleKind::Cjs
+                }
+            }
+        })
+    }
+
+    fn is_esm_context(&self, context: &Path) -> bool {
+        self.input_options.es6_syntax && match context.extension().and_then(|s| s.to_str()) {
+            Some("mjs") => true,
+            Some("cjs") | Some("json") => false,
+            _ => self.input_options.es6_syntax_everywhere,
+        }
+    }
+
+    fn conditions(&self, context: &Path) -> Vec<&str> {
+        let mut conditions = self.input_options.conditions
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        conditions.push(if self.input_options.for_browser { "browser" } else { "node" });
+        conditions.push(if self.is_esm_context(context) { "import" } else { "require" });
+        conditions
+    }
+
+    fn resolve_package_exports(&self, context: &Path, name: &str, mut package_dir: PathBuf, subpath: &str, substituted: &mut Vec<String>) -> Result<Option<Resolved>, CliError> {
+        let info = match self.cache.package_info(&mut package_dir)? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+        let exports = match info.exports {
+            Some(ref exports) => exports,
+            None => return Ok(None),
+        };
+
+        let subpath = format!(".{}", subpath);
+        let resolution = exports.resolve(&subpath, &self.conditions(context));
+        self.trace(|| TraceStep::Exports {
+            package: info.dir.clone(),
+            subpath: subpath.clone(),
+            resolution: resolution.clone(),
+        });
+        let target = match resolution {
+            ExportResolution::Path(target) => target,
+            ExportResolution::Missing |
+            ExportResolution::Blocked => {
+                return Err(CliError::PackagePathNotExported {
+                    context: context.to_owned(),
+                    name: name.to_owned(),
+                    subpath,
+                    package: package_dir,
+                })
+            }
+        };
+
+        self.resolve_package_target(context, name, &info, &target, substituted).map(Some)
+    }
+
+    fn resolve_package_imports(&self, context: &Path, name: &str, substituted: &mut Vec<String>) -> Result<Resolved, CliError> {
+        let info = match context.parent() {
+            Some(p) => self.cache.nearest_package_info(p.to_owned())?,
+            None => None,
+        };
+        let resolution = match info {
+            Some(ref info) => match info.imports {
+                Some(ref imports) => imports.resolve(name, &self.conditions(context)),
+                None => ExportResolution::Missing,
+            },
+            None => ExportResolution::Missing,
+        };
+        if let Some(ref info) = info {
+            self.trace(|| TraceStep::Imports {
+                package: info.dir.clone(),
+                resolution: resolution.clone(),
+            });
+        }
+        match resolution {
+            ExportResolution::Path(ref target) if target.starts_with("./") => {
+                self.resolve_package_target(context, name, info.as_ref().unwrap(), target, substituted)
+            }
+            ExportResolution::Path(ref target) => {
+                let package = info.as_ref().map(|info| info.dir.clone());
+                self.resolve_substitute(context, name, target, package, substituted)
+            }
+            ExportResolution::Missing |
+            ExportResolution::Blocked => {
+                Err(CliError::PackageImportNotDefined {
+                    context: context.to_owned(),
+                    name: name.to_owned(),
+                    package: info.map(|info| info.dir.clone()),
+                })
+            }
+        }
+    }
+
+    fn resolve_package_target(&self, context: &Path, name: &str, info: &PackageInfo, target: &str, substituted: &mut Vec<String>) -> Result<Resolved, CliError> {
+        // exports and imports targets are exact; no extension or index probing
+        let mut path = info.dir.clone();
+        path.append_resolving(target);
+        let substitution = if self.input_options.for_browser {
+            Self::check_path(Some(info), &path)
+        } else if path.is_file() {
+            PathSubstitution::Normal
+        } else {
+            PathSubstitution::Missing
+        };
+        match substitution {
+            PathSubstitution::Normal => Ok(Resolved::Normal(path)),
+            PathSubstitution::Ignore => Ok(Resolved::Ignore),
+            PathSubstitution::Replace(p) => self.resolve_path_replacement(&path, p, Some(info.dir.clone()), substituted),
+            PathSubstitution::Missing => {
+                Err(CliError::ModuleNotFound {
+                    context: context.to_owned(),
+                    name: name.to_owned(),
+                    searched: Vec::new(),
+                })
+            }
+        }
     }
 
     fn module_substitution(&self, context: &Path, name: &str) -> Result<ModuleSubstitution, CliError> {
@@ -1466,6 +3912,9 @@ impl Resolver {
         if self.input_options.external.contains(module_name) {
             return Ok(ModuleSubstitution::External)
         }
+        if let Some(new_name) = self.alias(name) {
+            return Ok(ModuleSubstitution::Replace { name: new_name, package: None })
+        }
         if self.input_options.for_browser {
             if let Some(p) = context.parent() {
                 if let Some(info) = self.cache.nearest_package_info(p.to_owned())? {
@@ -1476,17 +3925,85 @@ impl Resolver {
                         Some(&BrowserSubstitution::Replace(ref to)) => {
                             let mut new_name = to.to_string_lossy().into_owned();
                             new_name.push_str(&name[module_name.len()..]);
-                            return Ok(ModuleSubstitution::Replace(new_name))
+                            return Ok(ModuleSubstitution::Replace {
+                                name: new_name,
+                                package: Some(info.dir.clone()),
+                            })
                         }
                         None => {}
                     }
                 }
             }
         }
+        if self.input_options.polyfill_core && CORE_MODULES.contains(&module_name) {
+            let polyfill = match self.input_options.polyfills.iter().rev().find(|(from, _)| from == module_name) {
+                Some((_, Some(to))) => Some(&**to),
+                Some((_, None)) => return Ok(ModuleSubstitution::Ignore),
+                None => CORE_POLYFILLS.iter().find(|(from, _)| *from == module_name).map(|(_, to)| *to),
+            };
+            let rest = &name[module_name.len()..];
+            return Ok(ModuleSubstitution::Polyfill(polyfill.map(|polyfill| format!("{}{}", polyfill, rest))))
+        }
         Ok(ModuleSubstitution::Normal)
     }
 
-    fn resolve_path_or_module(&self, context: Option<&Path>, mut path: PathBuf, needs_dir: bool, package: bool) -> Result<Option<Resolved>, CliError> {
+    fn resolve_tsconfig_paths(&self, context: &Path, name: &str, needs_dir: bool, substituted: &mut Vec<String>) -> Result<Option<Resolved>, CliError> {
+        // tsconfig.json applies to the project, not its dependencies
+        if context.components().any(|c| c.as_os_str() == "node_modules") {
+            return Ok(None)
+        }
+        let config = match context.parent() {
+            Some(p) => self.tsconfigs.nearest_config(p.to_owned())?,
+            None => None,
+        };
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        self.trace(|| TraceStep::TsConfig(config.file.clone()));
+
+        if let Some((targets, star)) = match_subpath_key(&config.paths, name) {
+            for target in targets {
+                let target = match star {
+                    Some(star) => target.replacen('*', star, 1),
+                    None => target.clone(),
+                };
+                let mut path = config.paths_base().to_owned();
+                path.append_resolving(&target);
+                if let Some(result) = self.resolve_path_or_module(Some(context), path, needs_dir, false, substituted)? {
+                    return Ok(Some(result))
+                }
+            }
+        }
+        if let Some(ref base_url) = config.base_url {
+            let mut path = base_url.clone();
+            path.append_resolving(name);
+            if let Some(result) = self.resolve_path_or_module(Some(context), path, needs_dir, false, substituted)? {
+                return Ok(Some(result))
+            }
+        }
+        Ok(None)
+    }
+
+    fn alias(&self, name: &str) -> Option<String> {
+        self.input_options.aliases
+            .iter()
+            .filter(|(from, _)| {
+                if from.ends_with('/') {
+                    name.starts_with(&**from)
+                } else {
+                    name.starts_with(&**from) && matches!(name[from.len()..].chars().next(), None | Some('/'))
+                }
+            })
+            .max_by_key(|(from, _)| from.len())
+            .map(|(from, to)| {
+                let mut new_name = to.clone();
+                new_name.push_str(&name[from.len()..]);
+                new_name
+            })
+    }
+
+    fn resolve_path_or_module(&self, context: Option<&Path>, mut path: PathBuf, needs_dir: bool, package: bool, substituted: &mut Vec<String>) -> Result<Option<Resolved>, CliError> {
         let package_info = if self.input_options.for_browser {
             self.cache.nearest_package_info(path.clone())?
         } else {
@@ -1496,23 +4013,36 @@ impl Resolver {
         macro_rules! check_path {
             ( $package_info:ident, $path:ident ) => {
                 // eprintln!("check {}", $path.display());
-                if self.input_options.for_browser {
-                    match Self::check_path($package_info.as_ref().map(|x| x.as_ref()), &$path) {
-                        PathSubstitution::Normal => {
-                            // eprintln!("resolve {}", $path.display());
-                            return Ok(Some(Resolved::Normal($path)))
-                        }
-                        PathSubstitution::Ignore => {
-                            return Ok(Some(Resolved::Ignore))
-                        }
-                        PathSubstitution::Replace(p) => {
-                            // eprintln!("path replace {} => {}", $path.display(), p.display());
-                            return Ok(Some(Resolved::Normal(p)))
-                        }
-                        PathSubstitution::Missing => {}
+                let substitution = if self.input_options.for_browser {
+                    Self::check_path($package_info.as_ref().map(|x| x.as_ref()), &$path)
+                } else if $path.is_file() {
+                    PathSubstitution::Normal
+                } else {
+                    PathSubstitution::Missing
+                };
+                check_path!(@substitution substitution, $path);
+            };
+            ( @substitution $substitution:ident, $path:ident ) => {
+                let substitution = $substitution;
+                self.trace(|| TraceStep::Candidate {
+                    path: $path.clone(),
+                    substitution: substitution.clone(),
+                });
+                match substitution {
+                    PathSubstitution::Normal => {
+                        // eprintln!("resolve {}", $path.display());
+                        self.check_case(context, &$path)?;
+                        return Ok(Some(Resolved::Normal($path)))
                     }
-                } else if path.is_file() {
-                    return Ok(Some(Resolved::Normal(path)))
+                    PathSubstitution::Ignore => {
+                        return Ok(Some(Resolved::Ignore))
+                    }
+                    PathSubstitution::Replace(p) => {
+                        // eprintln!("path replace {} => {}", $path.display(), p.display());
+                        let package = package_info.as_ref().map(|info| info.dir.clone());
+                        return self.resolve_path_replacement(&$path, p, package, substituted).map(Some)
+                    }
+                    PathSubstitution::Missing => {}
                 }
             };
         }
@@ -1526,89 +4056,219 @@ impl Resolver {
                 path: path.clone(),
             })?.to_owned();
 
-            let mut new_file_name = file_name.to_owned();
-
-            if self.input_options.es6_syntax {
-                // <path>.mjs
-                new_file_name.push(".mjs");
+            for ext in &self.extensions {
+                // <path>.<ext>
+                let mut new_file_name = file_name.clone();
+                new_file_name.push(".");
+                new_file_name.push(ext);
                 path.set_file_name(&new_file_name);
                 check_path!(package_info, path);
-                new_file_name.clear();
-                new_file_name.push(&file_name);
             }
 
-            // <path>.js
-            new_file_name.push(".js");
-            path.set_file_name(&new_file_name);
-            check_path!(package_info, path);
-
-            // <path>.json
-            new_file_name.push("on"); // .js|on
-            path.set_file_name(&new_file_name);
-            check_path!(package_info, path);
-
             path.set_file_name(&file_name);
+        } else if self.in_same_package(context, package_info.as_ref())? {
+            // <path>/ matches a key without an extension, but only from inside the package as in webpack
+            if let Some(substitution) = Self::browser_substitution(package_info.as_ref().map(|x| x.as_ref()), &path) {
+                check_path!(@substitution substitution, path);
+            }
         }
 
         if !package {
             if let Some(info) = self.cache.package_info(&mut path)? {
-                if self.input_options.for_browser {
-                    match Self::check_path(Some(&*info), &path) {
-                        PathSubstitution::Replace(p) => {
-                            return Ok(Some(Resolved::Normal(p)))
-                        }
-                        _ => {}
+                for (field, main) in &info.mains {
+                    self.trace(|| TraceStep::PackageMain {
+                        package: info.dir.clone(),
+                        field: field.clone(),
+                    });
+                    if let Some(result) = self.resolve_path_or_module(context, main.clone(), false, true, substituted)? {
+                        return Ok(Some(result))
                     }
                 }
-                path.replace_with(&info.main);
-                return self.resolve_path_or_module(context, path, false, true);
+                return Ok(None)
             }
         }
 
-        if self.input_options.es6_syntax {
-            // <path>/index.mjs
-            path.push("index.mjs");
+        for ext in &self.extensions {
+            // <path>/index.<ext>
+            path.push(format!("index.{}", ext));
             check_path!(package_info, path);
             path.pop();
         }
 
-        // <path>/index.js
-        path.push("index.js");
-        check_path!(package_info, path);
-        path.pop();
-
-        // <path>/index.json
-        path.push("index.json");
-        check_path!(package_info, path);
-        // path.pop();
-
         Ok(None)
     }
 
-    fn check_path(package_info: Option<&PackageInfo>, path: &Path) -> PathSubstitution {
-        if let Some(package_info) = package_info {
-//            println!("check_path package_info: {:?} path: {:?}", package_info, path);
-            match package_info.browser_substitutions.0.get(path) {
-                Some(BrowserSubstitution::Ignore) => {
-                    return PathSubstitution::Ignore
+    /// Resolves the replacement for `path` in the `browser` field of the package.json in `package`, which is a module name if it isn't a path.
+    fn resolve_path_replacement(&self, path: &Path, replacement: PathBuf, package: Option<PathBuf>, substituted: &mut Vec<String>) -> Result<Resolved, CliError> {
+        if replacement == path {
+            // a file mapped to itself
+            return Ok(Resolved::Normal(replacement))
+        }
+        // replacement paths get the same extension and index lookup as a require()d path
+        self.resolve_substitute(path, &path.to_string_lossy(), &replacement.to_string_lossy(), package, substituted)
+    }
+
+    /// With --check-case, reports the components of `path` below `context`'s directory that differ in case from the names on disk.
+    fn check_case(&self, context: Option<&Path>, path: &Path) -> Result<(), CliError> {
+        let (check, context) = match (self.input_options.check_case, context) {
+            (Some(check), Some(context)) => (check, context),
+            _ => return Ok(()),
+        };
+        let mut actual = PathBuf::new();
+        let mut mismatch = false;
+        let mut below_context = false;
+        let mut context_components = context.parent().unwrap_or(context).components();
+        for c in path.components() {
+            // the directories the context is in were already checked, or weren't named by a require()
+            if !below_context && context_components.next() == Some(c) {
+                actual.push(c);
+                continue
+            }
+            below_context = true;
+            let name = c.as_os_str();
+            let actual_name = match self.cache.dir_entries(&actual) {
+                Some(ref entries) if !entries.contains(name) => {
+                    let lower = name.to_string_lossy().to_lowercase();
+                    entries.iter()
+                        .find(|entry| entry.to_string_lossy().to_lowercase() == lower)
+                        .cloned()
                 }
-                Some(BrowserSubstitution::Replace(ref replacement)) => {
-//                    println!(" -> replace with {:?}", replacement);
-                    return PathSubstitution::Replace(replacement.clone())
+                _ => None,
+            };
+            match actual_name {
+                Some(actual_name) => {
+                    mismatch = true;
+                    actual.push(actual_name);
                 }
-                None => {}
+                None => actual.push(name),
             }
         }
+        if !mismatch {
+            return Ok(())
+        }
+        match check {
+            CaseCheck::Warn => {
+                self.warn(Warning::CaseMismatch {
+                    context: context.to_owned(),
+                    path: path.to_owned(),
+                    actual,
+                });
+                Ok(())
+            }
+            CaseCheck::Error => Err(CliError::CaseMismatch {
+                context: context.to_owned(),
+                path: path.to_owned(),
+                actual,
+            }),
+        }
+    }
+
+    fn in_same_package(&self, context: Option<&Path>, package_info: Option<&Arc<PackageInfo>>) -> Result<bool, CliError> {
+        let (context, package_info) = match (context.and_then(Path::parent), package_info) {
+            (Some(context), Some(package_info)) => (context, package_info),
+            _ => return Ok(false),
+        };
+        Ok(self.cache.nearest_package_info(context.to_owned())?.is_some_and(|info| info.dir == package_info.dir))
+    }
+
+    fn check_path(package_info: Option<&PackageInfo>, path: &Path) -> PathSubstitution {
+        if let Some(substitution) = Self::browser_substitution(package_info, path) {
+            return substitution
+        }
         if path.is_file() {
             PathSubstitution::Normal
         } else {
             PathSubstitution::Missing
         }
     }
+
+    fn browser_substitution(package_info: Option<&PackageInfo>, path: &Path) -> Option<PathSubstitution> {
+//        println!("check_path package_info: {:?} path: {:?}", package_info, path);
+        match package_info?.browser_substitutions.0.get(path)? {
+            BrowserSubstitution::Ignore => Some(PathSubstitution::Ignore),
+            BrowserSubstitution::Replace(replacement) => Some(PathSubstitution::Replace(replacement.clone())),
+        }
+    }
 }
 
 impl PackageCache {
-    fn nearest_package_info(&self, mut dir: PathBuf) -> Result<Option<Rc<PackageInfo>>, CliError> {
+    pub fn new(input_options: &InputOptions) -> Self {
+        PackageCache {
+            main_fields: input_options.main_fields(),
+            extensions: input_options.resolve_extensions(),
+            ..Default::default()
+        }
+    }
+
+    /// The real paths of the package.json files that have been read.
+    pub fn files(&self) -> FnvHashSet<PathBuf> {
+        self.files.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Forgets the package.json file or PnP manifest whose real path is `file`, so that it's read again.
+    pub fn invalidate(&self, file: &Path) {
+        let mut files = self.files.lock().unwrap();
+        let mut pkgs = self.pkgs.write().unwrap();
+        let mut pnp = self.pnp.write().unwrap();
+        files.retain(|path, real_path| {
+            if real_path == file {
+                if let Some(dir) = path.parent() {
+                    pkgs.remove(dir);
+                }
+                pnp.retain(|_, manifest| !matches!(manifest, Some(manifest) if manifest.file == *path));
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Forgets the entries of `dir`, and that it had no package.json or PnP manifest, so that they're read again.
+    pub fn invalidate_dir(&self, dir: &Path) {
+        self.dirs.write().unwrap().remove(dir);
+        let mut pkgs = self.pkgs.write().unwrap();
+        if matches!(pkgs.get(dir), Some(None)) {
+            pkgs.remove(dir);
+        }
+        // the directories below `dir` use a manifest created in it rather than one further up
+        self.pnp.write().unwrap().retain(|path, manifest| !path.starts_with(dir) || manifest.as_ref().is_some_and(|manifest| manifest.file.starts_with(dir)));
+    }
+
+    fn dir_entries(&self, dir: &Path) -> Option<Arc<FnvHashSet<OsString>>> {
+        if let Some(entries) = self.dirs.read().unwrap().get(dir) {
+            return entries.clone()
+        }
+        let entries = fs::read_dir(dir).ok().map(|entries| {
+            Arc::new(entries.filter_map(|entry| entry.ok()).map(|entry| entry.file_name()).collect())
+        });
+        self.dirs.write().unwrap().entry(dir.to_owned()).or_insert(entries).clone()
+    }
+
+    /// The Yarn Plug'n'Play manifest in `dir` or the nearest directory above it.
+    fn nearest_pnp_manifest(&self, dir: &Path) -> Result<Option<Arc<pnp::Manifest>>, CliError> {
+        if let Some(manifest) = self.pnp.read().unwrap().get(dir) {
+            return Ok(manifest.clone())
+        }
+
+        let mut manifest = None;
+        for name in pnp::MANIFEST_NAMES {
+            let file = dir.join(name);
+            if file.is_file() {
+                let real_path = fs::canonicalize(&file).unwrap_or_else(|_| file.clone());
+                self.files.lock().unwrap().insert(file.clone(), real_path);
+                manifest = Some(Arc::new(pnp::load(&file)?));
+                break
+            }
+        }
+        if manifest.is_none() {
+            if let Some(parent) = dir.parent() {
+                manifest = self.nearest_pnp_manifest(parent)?;
+            }
+        }
+        Ok(self.pnp.write().unwrap().entry(dir.to_owned()).or_insert(manifest).clone())
+    }
+
+    fn nearest_package_info(&self, mut dir: PathBuf) -> Result<Option<Arc<PackageInfo>>, CliError> {
         loop {
             if !matches!(dir.file_name(), Some(s) if s == "node_modules") {
                 if let Some(info) = self.package_info(&mut dir)? {
@@ -1618,71 +4278,185 @@ impl PackageCache {
             if !dir.pop() { return Ok(None) }
         }
     }
-    fn package_info(&self, dir: &mut PathBuf) -> Result<Option<Rc<PackageInfo>>, CliError> {
-        let mut pkgs = self.pkgs.borrow_mut();
-        Ok(pkgs.entry(dir.clone()).or_insert_with(|| {
-            dir.push("package.json");
-            if let Ok(file) = fs::File::open(&dir) {
-                let buf_reader = io::BufReader::new(file);
-                if let Ok(mut info) = serde_json::from_reader::<_, PackageInfo>(buf_reader) {
-                    dir.pop();
-                    info.set_base(&dir);
-                    // eprintln!("info {} {:?}", dir.display(), info);
-                    Some(Rc::new(info))
-                } else {
-                    dir.pop();
-                    None
-                }
-            } else {
-                dir.pop();
-                None
-            }
-        }).as_ref().cloned())
+    fn package_info(&self, dir: &mut PathBuf) -> Result<Option<Arc<PackageInfo>>, CliError> {
+        if let Some(info) = self.pkgs.read().unwrap().get(dir.as_path()) {
+            return Ok(info.clone())
+        }
+
+        dir.push("package.json");
+        let info = if let Ok(file) = fs::File::open(&dir) {
+            let real_path = fs::canonicalize(&dir).unwrap_or_else(|_| dir.clone());
+            self.files.lock().unwrap().insert(dir.clone(), real_path);
+            let buf_reader = io::BufReader::new(file);
+            let mut de = serde_json::Deserializer::from_reader(buf_reader);
+            let seed = PackageInfoSeed { main_fields: &self.main_fields };
+            seed.deserialize(&mut de).and_then(|info| de.end().map(|_| info)).ok()
+        } else {
+            None
+        };
+        dir.pop();
+
+        let info = info.map(|mut info| {
+            info.set_base(dir, &self.extensions);
+            // eprintln!("info {} {:?}", dir.display(), info);
+            Arc::new(info)
+        });
+        // another worker may have read it first
+        Ok(self.pkgs.write().unwrap().entry(dir.clone()).or_insert(info).clone())
     }
 }
 
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
 struct PackageInfo {
-    main: PathBuf,
+    dir: PathBuf,
+    name: Option<String>,
+    version: Option<String>,
+    mains: Vec<(String, PathBuf)>,
     browser_substitutions: BrowserSubstitutionMap,
+    exports: Option<PackageExports>,
+    imports: Option<PackageImports>,
 }
 impl PackageInfo {
-    fn set_base(&mut self, base: &Path) {
-        self.main.prepend_resolving(base);
+    /// Makes the paths in the package relative to `base`, its directory. Browser field keys also match the files they resolve to with `extensions` or as directories, as in Browserify.
+    fn set_base(&mut self, base: &Path, extensions: &[String]) {
+        self.dir = base.to_owned();
+        for (_, main) in &mut self.mains {
+            main.prepend_resolving(base);
+        }
         let substs = mem::replace(&mut self.browser_substitutions, Default::default());
-        self.browser_substitutions.0.extend(substs.0.into_iter()
-            .map(|(mut from, mut to)| {
-                if from.is_explicitly_relative() {
-                    from.prepend_resolving(base);
-                }
-                match to {
-                    BrowserSubstitution::Ignore => {}
-                    BrowserSubstitution::Replace(ref mut path) => {
-                        if path.is_explicitly_relative() {
-                            path.prepend_resolving(base);
-                        }
+        let mut resolved = Vec::new();
+        for (from, mut to) in substs.0 {
+            if let BrowserSubstitution::Replace(ref mut path) = to {
+                let is_dir = ends_with_slash(path);
+                if path.is_explicitly_relative() {
+                    path.prepend_resolving(base);
+                    if is_dir {
+                        // keep the trailing slash so that only the directory matches
+                        path.push("");
                     }
+                } else if is_dir && !path.is_absolute() {
+                    // but a module name is looked up without it
+                    *path = path.components().collect();
                 }
-                (from, to)
-            }));
+            }
+            // keys for directories never match, as in webpack
+            if !ends_with_slash(&from) && !from.is_absolute() {
+                // bare keys are module names as well as paths relative to the package
+                let mut file = base.to_owned();
+                file.append_resolving(&from);
+                if let Some(resolved_file) = resolve_browser_key(&file, extensions) {
+                    resolved.push((resolved_file, to.clone()));
+                }
+                self.browser_substitutions.0.insert(file, to.clone());
+            }
+            if !from.is_explicitly_relative() {
+                self.browser_substitutions.0.insert(from, to);
+            }
+        }
+        // a key for the file itself comes first
+        for (file, to) in resolved {
+            self.browser_substitutions.0.entry(file).or_insert(to);
+        }
     }
 }
+/// The file that the browser field key `file` refers to, if it isn't that file itself: `file` with one of `extensions`, or its directory's index file.
+fn resolve_browser_key(file: &Path, extensions: &[String]) -> Option<PathBuf> {
+    if file.is_file() {
+        return None
+    }
+    let file_name = file.file_name()?;
+    let with_extension = extensions.iter().map(|ext| {
+        let mut new_file_name = file_name.to_owned();
+        new_file_name.push(".");
+        new_file_name.push(ext);
+        file.with_file_name(new_file_name)
+    });
+    let index = extensions.iter().map(|ext| file.join(format!("index.{}", ext)));
+    with_extension.chain(index).find(|path| path.is_file())
+}
+fn ends_with_slash(path: &Path) -> bool {
+    path.as_os_str().to_string_lossy().ends_with('/')
+}
 impl<'de> Deserialize<'de> for PackageInfo {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        #[derive(Debug, Default, PartialEq, Eq, Deserialize)]
-        #[serde(default)]
-        struct RawPackageInfo {
-            #[serde(deserialize_with = "from_str_or_none")]
-            main: Option<PathBuf>,
-            browser: BrowserField,
+        PackageInfoSeed { main_fields: &["main".to_owned()] }.deserialize(deserializer)
+    }
+}
+
+/// Deserializes a `PackageInfo`, reading the entry point from `main_fields` in priority order.
+#[derive(Debug, Clone, Copy)]
+struct PackageInfoSeed<'a> {
+    main_fields: &'a [String],
+}
+impl<'a, 'de> DeserializeSeed<'de> for PackageInfoSeed<'a> {
+    type Value = PackageInfo;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+#[derive(Deserialize)]
+struct MainField(#[serde(deserialize_with = "from_str_or_none")] Option<PathBuf>);
+#[derive(Deserialize)]
+struct StringField(#[serde(deserialize_with = "from_str_or_none")] Option<String>);
+
+impl<'a, 'de> Visitor<'de> for PackageInfoSeed<'a> {
+    type Value = PackageInfo;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "package.json object")
+    }
+    fn visit_map<A: de::MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let mut fields = FnvHashMap::default();
+        let mut name = None;
+        let mut version = None;
+        let mut browser = BrowserField::Empty;
+        let mut exports = None;
+        let mut imports = None;
+        while let Some(key) = access.next_key::<String>()? {
+            match &*key {
+                "browser" => browser = access.next_value()?,
+                "name" => name = access.next_value::<StringField>()?.0,
+                "version" => version = access.next_value::<StringField>()?.0,
+                "exports" => exports = access.next_value::<Option<ExportTarget>>()?,
+                "imports" => imports = access.next_value::<Option<ExportTarget>>()?,
+                _ if self.main_fields.contains(&key) => {
+                    if let MainField(Some(path)) = access.next_value()? {
+                        fields.insert(key, path);
+                    } else {
+                        fields.remove(&key);
+                    }
+                }
+                _ => {
+                    access.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+
+        let mut mains = Vec::new();
+        for field in self.main_fields {
+            let path = match (&**field, &browser) {
+                ("browser", BrowserField::Main(path)) => path.clone(),
+                _ => match fields.remove(field) {
+                    Some(path) => path,
+                    None => continue,
+                },
+            };
+            mains.push((field.clone(), path));
+        }
+        if mains.is_empty() {
+            mains.push(("main".to_owned(), PathBuf::from("./index")));
         }
 
-        let info = RawPackageInfo::deserialize(deserializer)?;
-        let main = info.main.unwrap_or(PathBuf::from("./index"));
-        let browser_substitutions = info.browser.to_map(&main);
         Ok(PackageInfo {
-            main,
-            browser_substitutions,
+            dir: PathBuf::new(),
+            name,
+            version,
+            mains,
+            browser_substitutions: browser.to_map(),
+            exports: exports.map(PackageExports::from_target),
+            imports: imports.map(PackageImports::from_target),
         })
     }
 }
@@ -1761,34 +4535,15 @@ enum BrowserField {
     Complex(BrowserSubstitutionMap),
 }
 impl BrowserField {
-    fn to_map(self, main: &Path) -> BrowserSubstitutionMap {
+    fn to_map(self) -> BrowserSubstitutionMap {
         match self {
-            BrowserField::Empty => {
+            // a browser field that is only a path is the "browser" main field
+            BrowserField::Empty |
+            BrowserField::Main(_) => {
                 Default::default()
             }
-            BrowserField::Main(mut to) => {
-                Self::make_explicitly_relative(&mut to);
-                BrowserSubstitutionMap(map! {
-                    Path::new(".").to_owned() => BrowserSubstitution::Replace(to),
-                })
-            }
-            BrowserField::Complex(mut map) => {
-                for (_, sub) in &mut map.0 {
-                    match sub {
-                        BrowserSubstitution::Ignore => {},
-                        BrowserSubstitution::Replace(ref mut p) => {
-                            Self::make_explicitly_relative(p);
-                        }
-                    }
-                }
-                map
-            }
-        }
-    }
-
-    fn make_explicitly_relative(p: &mut PathBuf) {
-        if !p.is_explicitly_relative() {
-            p.prepend_resolving(Path::new("."));
+            // replacements that aren't explicitly relative are module names
+            BrowserField::Complex(map) => map,
         }
     }
 }
@@ -1820,6 +4575,182 @@ impl<'de> Deserialize<'de> for BrowserField {
     }
 }
 
+/// A target in the `exports` field of package.json.
+///
+/// https://nodejs.org/api/packages.html#conditional-exports
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum ExportTarget {
+    Null,
+    Path(String),
+    Alternatives(Vec<ExportTarget>),
+    Conditions(Vec<(String, ExportTarget)>),
+}
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ExportResolution {
+    Missing,
+    Blocked,
+    Path(String),
+}
+impl ExportTarget {
+    fn resolve(&self, star: Option<&str>, conditions: &[&str], allow_bare: bool) -> ExportResolution {
+        match *self {
+            ExportTarget::Null => ExportResolution::Blocked,
+            ExportTarget::Path(ref target) => {
+                if !target.starts_with("./") {
+                    // only `imports` may map to other packages
+                    if !allow_bare || target.starts_with("../") || target.starts_with('/') {
+                        return ExportResolution::Missing
+                    }
+                } else if target[2..].split('/').any(|part| part == ".." || part == "." || part == "node_modules") {
+                    return ExportResolution::Missing
+                }
+                match star {
+                    Some(star) => ExportResolution::Path(target.replace('*', star)),
+                    None => ExportResolution::Path(target.clone()),
+                }
+            }
+            ExportTarget::Alternatives(ref targets) => {
+                let mut result = ExportResolution::Missing;
+                for target in targets {
+                    match target.resolve(star, conditions, allow_bare) {
+                        ExportResolution::Path(path) => return ExportResolution::Path(path),
+                        ExportResolution::Blocked => result = ExportResolution::Blocked,
+                        ExportResolution::Missing => {}
+                    }
+                }
+                result
+            }
+            ExportTarget::Conditions(ref conds) => {
+                for (cond, target) in conds {
+                    if cond == "default" || conditions.contains(&cond.as_str()) {
+                        match target.resolve(star, conditions, allow_bare) {
+                            ExportResolution::Missing => {}
+                            result => return result,
+                        }
+                    }
+                }
+                ExportResolution::Missing
+            }
+        }
+    }
+}
+impl<'de> Deserialize<'de> for ExportTarget {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ExportTargetVisitor;
+
+        impl<'de> Visitor<'de> for ExportTargetVisitor {
+            type Value = ExportTarget;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "anything at all")
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(ExportTarget::Path(v.to_owned()))
+            }
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+                let mut targets = Vec::new();
+                while let Some(target) = access.next_element()? {
+                    targets.push(target);
+                }
+                Ok(ExportTarget::Alternatives(targets))
+            }
+            fn visit_map<A: de::MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+                // order matters here, so we can't deserialize into a map
+                let mut conds = Vec::new();
+                while let Some(entry) = access.next_entry()? {
+                    conds.push(entry);
+                }
+                Ok(ExportTarget::Conditions(conds))
+            }
+
+            visit_unconditionally!('de ExportTarget::Null, bool i64 i128 u64 u128 f64 bytes none some unit newtype_struct enum);
+        }
+
+        deserializer.deserialize_any(ExportTargetVisitor)
+    }
+}
+
+/// The `exports` field of package.json, normalized to a list of subpath keys like `.` and `./feature/*`.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+struct PackageExports(Vec<(String, ExportTarget)>);
+impl PackageExports {
+    fn from_target(target: ExportTarget) -> Self {
+        match target {
+            ExportTarget::Conditions(conds) => {
+                if conds.iter().any(|(key, _)| key.starts_with('.')) {
+                    PackageExports(conds.into_iter()
+                        .filter(|(key, _)| key.starts_with('.'))
+                        .collect())
+                } else {
+                    PackageExports(vec![(".".to_owned(), ExportTarget::Conditions(conds))])
+                }
+            }
+            target => PackageExports(vec![(".".to_owned(), target)]),
+        }
+    }
+
+    fn resolve(&self, subpath: &str, conditions: &[&str]) -> ExportResolution {
+        match match_subpath_key(&self.0, subpath) {
+            Some((target, star)) => target.resolve(star, conditions, false),
+            None => ExportResolution::Missing,
+        }
+    }
+}
+
+/// The `imports` field of package.json: private `#`-prefixed specifiers that are only visible within the package.
+///
+/// https://nodejs.org/api/packages.html#subpath-imports
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+struct PackageImports(Vec<(String, ExportTarget)>);
+impl PackageImports {
+    fn from_target(target: ExportTarget) -> Self {
+        match target {
+            ExportTarget::Conditions(conds) => {
+                PackageImports(conds.into_iter()
+                    .filter(|(key, _)| key.starts_with('#'))
+                    .collect())
+            }
+            _ => PackageImports::default(),
+        }
+    }
+
+    fn resolve(&self, name: &str, conditions: &[&str]) -> ExportResolution {
+        match match_subpath_key(&self.0, name) {
+            Some((target, star)) => target.resolve(star, conditions, true),
+            None => ExportResolution::Missing,
+        }
+    }
+}
+
+/// Finds the entry for `subpath`, preferring an exact key and then the `*` pattern with the longest prefix.
+fn match_subpath_key<'a, 'b, T>(map: &'a [(String, T)], subpath: &'b str) -> Option<(&'a T, Option<&'b str>)> {
+    if let Some((_, target)) = map.iter().find(|(key, _)| !key.contains('*') && key == subpath) {
+        return Some((target, None))
+    }
+    let mut best: Option<(&str, &T, &str)> = None;
+    for (key, target) in map {
+        let star = match key.find('*') {
+            Some(star) => star,
+            None => continue,
+        };
+        let (prefix, suffix) = (&key[..star], &key[star + 1..]);
+        if subpath.len() < key.len() || !subpath.starts_with(prefix) || !subpath.ends_with(suffix) {
+            continue
+        }
+        let better = match best {
+            None => true,
+            Some((best_key, _, _)) => {
+                let best_prefix = best_key.find('*').unwrap();
+                star > best_prefix || star == best_prefix && key.len() > best_key.len()
+            }
+        };
+        if better {
+            best = Some((key, target, &subpath[star..subpath.len() - suffix.len()]));
+        }
+    }
+    best.map(|(_, target, star)| (target, Some(star)))
+}
+
 fn from_str_or_none<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
 where for<'a> T: From<&'a str> + Deserialize<'de>, D: Deserializer<'de> {
     struct FromStrOrNone<T>(PhantomData<T>);
@@ -1,9 +1,3 @@
-use std::{env, iter};
-
-pub fn args() -> Expand<iter::Skip<env::Args>> {
-    expand(env::args().skip(1))
-}
-
 pub fn expand<I: IntoIterator<Item = String>>(args: I) -> Expand<I::IntoIter> {
     Expand {
         arg: None,
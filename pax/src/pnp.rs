@@ -0,0 +1,289 @@
+use std::{fmt, fs, io};
+use std::path::{Component, Path, PathBuf};
+use esparse::lex::{self, Tt};
+use fnv::{FnvHashMap, FnvHashSet};
+use regex::Regex;
+use serde_json;
+
+use super::PathBufExt;
+
+/// Manifest file names, in the order they're looked for in each directory.
+///
+/// With `pnpEnableInlining: false`, Yarn writes the data to .pnp.data.json and leaves it out of .pnp.cjs.
+pub const MANIFEST_NAMES: &[&str] = &[".pnp.data.json", ".pnp.cjs"];
+
+/// A package in the dependency tree of a Yarn Plug'n'Play manifest; the top-level package has neither a name nor a reference.
+///
+/// https://yarnpkg.com/advanced/pnp-spec
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct Locator {
+    pub name: Option<String>,
+    pub reference: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Package {
+    location: PathBuf,
+    /// The locator of each dependency, or `None` for a peer dependency that the parent doesn't provide.
+    dependencies: FnvHashMap<String, Option<Locator>>,
+}
+
+#[derive(Debug)]
+pub struct Manifest {
+    pub file: PathBuf,
+    dir: PathBuf,
+    packages: FnvHashMap<Locator, Package>,
+    /// Each package's location, most nested first.
+    locations: Vec<(PathBuf, Locator)>,
+    top_level_fallback: bool,
+    fallback_exclusions: FnvHashSet<Locator>,
+    fallback_pool: FnvHashMap<String, Option<Locator>>,
+    ignore_pattern: Option<Regex>,
+}
+
+impl Manifest {
+    /// Finds the directory of the package that `name` refers to in the file `issuer`, or `None` if the manifest doesn't cover `issuer`.
+    pub fn resolve(&self, issuer: &Path, name: &str) -> Result<Option<PathBuf>> {
+        if let Some(ref pattern) = self.ignore_pattern {
+            if let Ok(relative) = issuer.strip_prefix(&self.dir) {
+                if pattern.is_match(&relative.to_string_lossy()) {
+                    return Ok(None)
+                }
+            }
+        }
+        let issuer = match self.locations.iter().find(|(location, _)| issuer.starts_with(location)) {
+            Some((_, locator)) => locator,
+            None => return Ok(None),
+        };
+
+        let mut dependency = self.packages[issuer].dependencies.get(name);
+        if dependency.is_none() && self.top_level_fallback && !self.fallback_exclusions.contains(issuer) {
+            dependency = self.packages.get(&Locator::default())
+                .and_then(|top_level| top_level.dependencies.get(name))
+                .or_else(|| self.fallback_pool.get(name));
+        }
+        let locator = match dependency {
+            Some(Some(locator)) => locator,
+            Some(None) => return Err(self.error(ErrorKind::MissingPeer { issuer: issuer.clone(), name: name.to_owned() })),
+            None => return Err(self.error(ErrorKind::NotDeclared { issuer: issuer.clone(), name: name.to_owned() })),
+        };
+        let package = self.packages.get(locator).ok_or_else(|| {
+            self.error(ErrorKind::MissingPackage(locator.clone()))
+        })?;
+        if package.location.components().any(|c| matches!(c, Component::Normal(s) if s.to_string_lossy().ends_with(".zip"))) {
+            return Err(self.error(ErrorKind::Zip { locator: locator.clone(), location: package.location.clone() }))
+        }
+        Ok(Some(package.location.clone()))
+    }
+
+    fn error(&self, kind: ErrorKind) -> Error {
+        Error::new(&self.file, kind)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawManifest {
+    #[serde(default)]
+    enable_top_level_fallback: bool,
+    #[serde(default)]
+    fallback_exclusion_list: Vec<(String, Vec<String>)>,
+    #[serde(default)]
+    fallback_pool: Vec<(String, Option<RawReference>)>,
+    #[serde(default)]
+    ignore_pattern_data: Option<String>,
+    package_registry_data: Vec<(Option<String>, Vec<RawPackageEntry>)>,
+}
+
+/// A package's reference and data, under its name in packageRegistryData.
+type RawPackageEntry = (Option<String>, RawPackage);
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawPackage {
+    package_location: String,
+    #[serde(default)]
+    package_dependencies: Vec<(String, Option<RawReference>)>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawReference {
+    Reference(String),
+    /// A dependency installed under another name: `[name, reference]`.
+    Alias(String, String),
+}
+
+impl RawReference {
+    fn into_locator(self, name: &str) -> Locator {
+        let (name, reference) = match self {
+            RawReference::Reference(reference) => (name.to_owned(), reference),
+            RawReference::Alias(name, reference) => (name, reference),
+        };
+        Locator {
+            name: Some(name),
+            reference: Some(reference),
+        }
+    }
+}
+
+fn dependencies(raw: Vec<(String, Option<RawReference>)>) -> FnvHashMap<String, Option<Locator>> {
+    raw.into_iter()
+        .map(|(name, reference)| {
+            let locator = reference.map(|reference| reference.into_locator(&name));
+            (name, locator)
+        })
+        .collect()
+}
+
+pub fn load(path: &Path) -> Result<Manifest> {
+    let source = fs::read_to_string(path).map_err(|err| Error::new(path, ErrorKind::Io(err)))?;
+    let raw: RawManifest = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&source)
+    } else {
+        serde_json::from_str(&runtime_state(&source).ok_or_else(|| Error::new(path, ErrorKind::NoRuntimeState))?)
+    }.map_err(|err| Error::new(path, ErrorKind::Json(err)))?;
+    let dir = path.parent().unwrap();
+
+    let mut packages = FnvHashMap::default();
+    let mut locations = Vec::new();
+    for (name, references) in raw.package_registry_data {
+        for (reference, package) in references {
+            let locator = Locator {
+                name: name.clone(),
+                reference,
+            };
+            let mut location = dir.to_owned();
+            location.append_resolving(&package.package_location);
+            locations.push((location.clone(), locator.clone()));
+            packages.insert(locator, Package {
+                location,
+                dependencies: dependencies(package.package_dependencies),
+            });
+        }
+    }
+    locations.sort_by_key(|(location, _)| std::cmp::Reverse(location.components().count()));
+
+    let ignore_pattern = match raw.ignore_pattern_data {
+        Some(pattern) => Some(Regex::new(&pattern).map_err(|err| Error::new(path, ErrorKind::IgnorePattern(err)))?),
+        None => None,
+    };
+    Ok(Manifest {
+        file: path.to_owned(),
+        dir: dir.to_owned(),
+        packages,
+        locations,
+        top_level_fallback: raw.enable_top_level_fallback,
+        fallback_exclusions: raw.fallback_exclusion_list.into_iter()
+            .flat_map(|(name, references)| references.into_iter().map(move |reference| Locator {
+                name: Some(name.clone()),
+                reference: Some(reference),
+            }))
+            .collect(),
+        fallback_pool: dependencies(raw.fallback_pool),
+        ignore_pattern,
+    })
+}
+
+/// Extracts the JSON string that .pnp.cjs assigns to `RAW_RUNTIME_STATE`.
+fn runtime_state(source: &str) -> Option<String> {
+    let mut lex = lex::Lexer::new_unnamed(source);
+    let mut found = false;
+    loop {
+        match lex.advance().tt {
+            Tt::Id("RAW_RUNTIME_STATE") => found = true,
+            Tt::StrLitSgl(s) |
+            Tt::StrLitDbl(s) if found => return lex::str_lit_value(s).ok().map(|s| s.into_owned()),
+            Tt::Eof => return None,
+            _ => {}
+        }
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub struct Error {
+    pub path: PathBuf,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Io(io::Error),
+    Json(serde_json::Error),
+    IgnorePattern(::regex::Error),
+    NoRuntimeState,
+    NotDeclared { issuer: Locator, name: String },
+    MissingPeer { issuer: Locator, name: String },
+    MissingPackage(Locator),
+    Zip { locator: Locator, location: PathBuf },
+}
+
+impl Error {
+    fn new(path: &Path, kind: ErrorKind) -> Self {
+        Error {
+            path: path.to_owned(),
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "in {}: {}", self.path.display(), self.kind)
+    }
+}
+
+impl fmt::Display for Locator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.name, &self.reference) {
+            (Some(name), Some(reference)) => write!(f, "{}@{}", name, reference),
+            (Some(name), None) => write!(f, "{}", name),
+            _ => write!(f, "the top-level package"),
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::Io(ref inner) => {
+                write!(f, "{}", inner)
+            }
+            ErrorKind::Json(ref inner) => {
+                write!(f, "{}", inner)
+            }
+            ErrorKind::IgnorePattern(ref inner) => {
+                write!(f, "ignorePatternData: {}", inner)
+            }
+            ErrorKind::NoRuntimeState => {
+                write!(f, "no RAW_RUNTIME_STATE; the data may be in .pnp.data.json")
+            }
+            ErrorKind::NotDeclared { ref issuer, ref name } => {
+                write!(f, "{} requires '{}', which isn't in its dependencies", issuer, name)
+            }
+            ErrorKind::MissingPeer { ref issuer, ref name } => {
+                write!(f, "{} requires its peer dependency '{}', which its parent doesn't provide", issuer, name)
+            }
+            ErrorKind::MissingPackage(ref locator) => {
+                write!(f, "{} is not in packageRegistryData", locator)
+            }
+            ErrorKind::Zip { ref locator, ref location } => {
+                write!(f, "{} is in a zip archive at {}; unplug it with `yarn unplug`", locator, location.display())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_runtime_state() {
+        assert_eq!(runtime_state("const RAW_RUNTIME_STATE =\n'{\"a\": \"b\\'c\"}';"), Some(r#"{"a": "b'c"}"#.to_owned()));
+        assert_eq!(runtime_state(r#"const x = "y"; const RAW_RUNTIME_STATE = "{}";"#), Some("{}".to_owned()));
+        assert_eq!(runtime_state(r#"const x = "y";"#), None);
+    }
+}
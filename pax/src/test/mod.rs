@@ -10,6 +10,22 @@ use std::path::Path;
 use walkdir::WalkDir;
 use super::*;
 
+macro_rules! map {
+    {} => {
+        Default::default()
+    };
+    { $($key:expr => $value:expr,)+ } => {
+        {
+            let mut map = FnvHashMap::default();
+            $(map.insert($key, $value);)+
+            map
+        }
+    };
+    { $($key:expr => $value:expr),+ } => {
+        map!{$($key => $value,)+}
+    };
+}
+
 #[test]
 fn test_count_lines() {
     assert_eq!(count_lines(""), 1);
@@ -106,31 +122,75 @@ fn test_deserialize_package_info() {
     assert_matches!(parse("100"), Err(_));
     assert_matches!(parse("[1, 2, 3]"), Err(_));
     assert_eq!(parse(r#"{}"#).unwrap(), PackageInfo {
-        main: PathBuf::from("./index"),
+        mains: vec![("main".to_owned(), PathBuf::from("./index"))],
         browser_substitutions: BrowserSubstitutionMap(map!{}),
+        ..PackageInfo::default()
     });
     assert_eq!(parse(r#"{"browser": null}"#).unwrap(), PackageInfo {
-        main: PathBuf::from("./index"),
+        mains: vec![("main".to_owned(), PathBuf::from("./index"))],
         browser_substitutions: BrowserSubstitutionMap(map!{}),
+        ..PackageInfo::default()
     });
     assert_eq!(parse(r#"{"browser": "simple"}"#).unwrap(), PackageInfo {
-        main: PathBuf::from("./index"),
-        browser_substitutions: BrowserSubstitutionMap(map!{
-            PathBuf::from("./index") => BrowserSubstitution::Replace(PathBuf::from("./simple")),
-        }),
+        mains: vec![("main".to_owned(), PathBuf::from("./index"))],
+        browser_substitutions: BrowserSubstitutionMap(map!{}),
+        ..PackageInfo::default()
     });
     assert_eq!(parse(r#"{"browser": {}}"#).unwrap(), PackageInfo {
-        main: PathBuf::from("./index"),
+        mains: vec![("main".to_owned(), PathBuf::from("./index"))],
         browser_substitutions: BrowserSubstitutionMap(map!{}),
+        ..PackageInfo::default()
+    });
+    assert_eq!(parse(r#"{"name": "pkg", "version": "1.2.3"}"#).unwrap(), PackageInfo {
+        name: Some("pkg".to_owned()),
+        version: Some("1.2.3".to_owned()),
+        mains: vec![("main".to_owned(), PathBuf::from("./index"))],
+        ..PackageInfo::default()
+    });
+    assert_eq!(parse(r#"{"name": null, "version": 1}"#).unwrap(), PackageInfo {
+        mains: vec![("main".to_owned(), PathBuf::from("./index"))],
+        ..PackageInfo::default()
     });
     assert_eq!(parse(r#"{"browser": {"mod": false}}"#).unwrap(), PackageInfo {
-        main: PathBuf::from("./index"),
+        mains: vec![("main".to_owned(), PathBuf::from("./index"))],
         browser_substitutions: BrowserSubstitutionMap(map!{
             PathBuf::from("mod") => BrowserSubstitution::Ignore,
         }),
+        ..PackageInfo::default()
     });
 }
 
+#[test]
+fn test_deserialize_main_fields() {
+    fn parse(s: &str, main_fields: &[&str]) -> PackageInfo {
+        let main_fields = main_fields.iter().map(|&f| f.to_owned()).collect::<Vec<_>>();
+        PackageInfoSeed { main_fields: &main_fields }.deserialize(&mut serde_json::Deserializer::from_str(s)).unwrap()
+    }
+    let fields = &["module", "browser", "main"];
+    assert_eq!(parse(r#"{}"#, fields).mains, vec![
+        ("main".to_owned(), PathBuf::from("./index")),
+    ]);
+    assert_eq!(parse(r#"{"main": "a", "browser": "b", "module": "c"}"#, fields).mains, vec![
+        ("module".to_owned(), PathBuf::from("c")),
+        ("browser".to_owned(), PathBuf::from("b")),
+        ("main".to_owned(), PathBuf::from("a")),
+    ]);
+    assert_eq!(parse(r#"{"main": "a", "browser": {"./a": "./b"}, "module": null}"#, fields).mains, vec![
+        ("main".to_owned(), PathBuf::from("a")),
+    ]);
+    assert_eq!(parse(r#"{"main": "a", "browser": "b", "module": "c"}"#, &["browser", "main"]).mains, vec![
+        ("browser".to_owned(), PathBuf::from("b")),
+        ("main".to_owned(), PathBuf::from("a")),
+    ]);
+    assert_eq!(parse(r#"{"main": "a", "browser": "b", "module": "c"}"#, &["main"]).mains, vec![
+        ("main".to_owned(), PathBuf::from("a")),
+    ]);
+    assert_eq!(parse(r#"{"main": "a", "jsnext:main": "d"}"#, &["jsnext:main", "main"]).mains, vec![
+        ("jsnext:main".to_owned(), PathBuf::from("d")),
+        ("main".to_owned(), PathBuf::from("a")),
+    ]);
+}
+
 fn fixture_path() -> PathBuf {
     // let mut path = PathBuf::from(file!());
     // path.append_resolving("../../../fixtures");
@@ -139,12 +199,14 @@ fn fixture_path() -> PathBuf {
     path
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum Resolution<'a> {
     Y(&'a str),
     Ignore,
     External,
     Fail,
+    /// Resolves like Browserify, which matches the files that browser field keys resolve to, where webpack only matches keys as written.
+    NotWebpack(&'a Resolution<'a>),
 }
 use self::Resolution::*;
 
@@ -163,21 +225,23 @@ fn test_resolve_path_or_module() {
         let resolver = Resolver::new(input_options.clone());
         let expected = to_path.map(Resolved::Normal);
         // resolves with an empty cache...
-        assert_eq!(resolver.resolve_path_or_module(None, from_path.clone(), false, false).unwrap(), expected);
+        assert_eq!(resolver.resolve_path_or_module(None, from_path.clone(), false, false, &mut Vec::new()).unwrap(), expected);
         // ...and with everything cached
-        assert_eq!(resolver.resolve_path_or_module(None, from_path, false, false).unwrap(), expected);
+        assert_eq!(resolver.resolve_path_or_module(None, from_path, false, false, &mut Vec::new()).unwrap(), expected);
     }
     let cjs = InputOptions {
         for_browser: false,
         es6_syntax: false,
         es6_syntax_everywhere: false,
         external: Default::default(),
+        ..InputOptions::default()
     };
     let esm = InputOptions {
         for_browser: false,
         es6_syntax: true,
         es6_syntax_everywhere: false,
         external: Default::default(),
+        ..InputOptions::default()
     };
     path_resolves("resolve/named-noext",
              Some("resolve/named-noext"), &cjs);
@@ -268,8 +332,12 @@ fn test_resolve_path_or_module() {
 
 fn assert_resolves(context: &str, from: &str, to: Resolution, input_options: &InputOptions) {
 //    eprintln!("--- assert_resolves {:?} {:?} {:?}\n{:?}", context, from, to, input_options);
+    if let NotWebpack(to) = to {
+        return assert_resolves(context, from, *to, input_options)
+    }
     let base_path = fixture_path();
     let expected = match to {
+        NotWebpack(_) => unreachable!(),
         Y(to) => {
             let mut to_path = base_path.clone();
             to_path.append_resolving(to);
@@ -311,12 +379,14 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
         es6_syntax: false,
         es6_syntax_everywhere: false,
         external: Default::default(),
+        ..InputOptions::default()
     };
     let esm = InputOptions {
         for_browser: false,
         es6_syntax: true,
         es6_syntax_everywhere: false,
         external: Default::default(),
+        ..InputOptions::default()
     };
 
     // relative paths
@@ -1220,6 +1290,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
         es6_syntax: false,
         es6_syntax_everywhere: false,
         external: Default::default(),
+        ..InputOptions::default()
     };
 
     let ctx = "resolve/hypothetical.js";
@@ -1326,6 +1397,11 @@ fn test_resolve_consistency() {
             }
         "#).to_vec() };
         for (from, to) in cases {
+            let to = match *to {
+                NotWebpack(_) if target == Target::Webpack => continue,
+                NotWebpack(to) => to,
+                ref to => to,
+            };
             let from_path = Path::new(from);
             let from = if from_path.is_absolute() {
                 let suffix = from_path.strip_prefix(fixture_path()).expect("absolute path outside of fixtures");
@@ -1364,6 +1440,7 @@ fn test_resolve_consistency() {
                     }
                 },
                 External => unimplemented!(),
+                NotWebpack(_) => unreachable!(),
                 Fail => match target {
                     Target::Browserify => {
                         writeln!(b, "n(require({from}), {from})", from=from).unwrap();
@@ -1520,12 +1597,14 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
         es6_syntax: true,
         es6_syntax_everywhere: false,
         external: Default::default(),
+        ..InputOptions::default()
     };
     let br = InputOptions {
         for_browser: true,
         es6_syntax: true,
         es6_syntax_everywhere: false,
         external: Default::default(),
+        ..InputOptions::default()
     };
 
     let ctx = "browser/hypothetical.js";
@@ -1699,6 +1778,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
                   Y("browser/alternate-files-main-rel/main-browser.js"), &br);
     assert_resolves(ctx,  "./alternate-files-main-rel/main-default.js",
                   Y("browser/alternate-files-main-rel/main-browser.js"), &br);
+    // a bare replacement is a module name, even with a file of that name next to package.json
     assert_resolves(ctx,  "./alternate-files-main-bare",
                   Y("browser/alternate-files-main-bare/node_modules/main-browser.js"), &br);
     assert_resolves(ctx,  "./alternate-files-main-bare/main-default",
@@ -2045,7 +2125,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-rel-ext-default",
                   Y("browser/alternate-files/file-from-rel-noext-to-rel-ext-browser.js"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-rel-ext-default.js",
-                  Y("browser/alternate-files/file-from-rel-noext-to-rel-ext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/file-from-rel-noext-to-rel-ext-browser.js")), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-rel-ext-default.json",
                   Y("browser/alternate-files/file-from-rel-noext-to-rel-ext-default.json"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-slash-to-rel-ext-default",
@@ -2067,7 +2147,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-rel-noext-default",
                   Y("browser/alternate-files/file-from-rel-noext-to-rel-noext-browser.js"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-rel-noext-default.js",
-                  Y("browser/alternate-files/file-from-rel-noext-to-rel-noext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/file-from-rel-noext-to-rel-noext-browser.js")), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-rel-noext-default.json",
                   Y("browser/alternate-files/file-from-rel-noext-to-rel-noext-default.json"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-slash-to-rel-noext-default",
@@ -2095,7 +2175,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-rel-slash-default",
                   Y("browser/alternate-files/file-from-rel-noext-to-rel-slash-browser/index.js"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-rel-slash-default.js",
-                  Y("browser/alternate-files/file-from-rel-noext-to-rel-slash-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/file-from-rel-noext-to-rel-slash-browser/index.js")), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-rel-slash-default.json",
                   Y("browser/alternate-files/file-from-rel-noext-to-rel-slash-default.json"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-rel-slash-default/",
@@ -2121,7 +2201,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-rel-ext-default",
                   Y("browser/alternate-files/file-from-bare-noext-to-rel-ext-browser.js"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-rel-ext-default.js",
-                  Y("browser/alternate-files/file-from-bare-noext-to-rel-ext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/file-from-bare-noext-to-rel-ext-browser.js")), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-rel-ext-default.json",
                   Y("browser/alternate-files/file-from-bare-noext-to-rel-ext-default.json"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-slash-to-rel-ext-default",
@@ -2143,7 +2223,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-rel-noext-default",
                   Y("browser/alternate-files/file-from-bare-noext-to-rel-noext-browser.js"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-rel-noext-default.js",
-                  Y("browser/alternate-files/file-from-bare-noext-to-rel-noext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/file-from-bare-noext-to-rel-noext-browser.js")), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-rel-noext-default.json",
                   Y("browser/alternate-files/file-from-bare-noext-to-rel-noext-default.json"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-slash-to-rel-noext-default",
@@ -2171,7 +2251,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-rel-slash-default",
                   Y("browser/alternate-files/file-from-bare-noext-to-rel-slash-browser/index.js"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-rel-slash-default.js",
-                  Y("browser/alternate-files/file-from-bare-noext-to-rel-slash-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/file-from-bare-noext-to-rel-slash-browser/index.js")), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-rel-slash-default.json",
                   Y("browser/alternate-files/file-from-bare-noext-to-rel-slash-default.json"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-rel-slash-default/",
@@ -2197,7 +2277,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-bare-ext-default",
                   Y("browser/alternate-files/node_modules/file-from-rel-noext-to-bare-ext-browser.js"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-bare-ext-default.js",
-                  Y("browser/alternate-files/file-from-rel-noext-to-bare-ext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/node_modules/file-from-rel-noext-to-bare-ext-browser.js")), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-bare-ext-default.json",
                   Y("browser/alternate-files/file-from-rel-noext-to-bare-ext-default.json"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-slash-to-bare-ext-default",
@@ -2219,7 +2299,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-bare-noext-default",
                   Y("browser/alternate-files/node_modules/file-from-rel-noext-to-bare-noext-browser.js"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-bare-noext-default.js",
-                  Y("browser/alternate-files/file-from-rel-noext-to-bare-noext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/node_modules/file-from-rel-noext-to-bare-noext-browser.js")), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-bare-noext-default.json",
                   Y("browser/alternate-files/file-from-rel-noext-to-bare-noext-default.json"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-slash-to-bare-noext-default",
@@ -2247,7 +2327,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-bare-slash-default",
                   Y("browser/alternate-files/node_modules/file-from-rel-noext-to-bare-slash-browser.js"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-bare-slash-default.js",
-                  Y("browser/alternate-files/file-from-rel-noext-to-bare-slash-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/node_modules/file-from-rel-noext-to-bare-slash-browser.js")), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-bare-slash-default.json",
                   Y("browser/alternate-files/file-from-rel-noext-to-bare-slash-default.json"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-rel-noext-to-bare-slash-default/",
@@ -2273,7 +2353,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-bare-ext-default",
                   Y("browser/alternate-files/node_modules/file-from-bare-noext-to-bare-ext-browser.js"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-bare-ext-default.js",
-                  Y("browser/alternate-files/file-from-bare-noext-to-bare-ext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/node_modules/file-from-bare-noext-to-bare-ext-browser.js")), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-bare-ext-default.json",
                   Y("browser/alternate-files/file-from-bare-noext-to-bare-ext-default.json"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-slash-to-bare-ext-default",
@@ -2295,7 +2375,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-bare-noext-default",
                   Y("browser/alternate-files/node_modules/file-from-bare-noext-to-bare-noext-browser.js"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-bare-noext-default.js",
-                  Y("browser/alternate-files/file-from-bare-noext-to-bare-noext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/node_modules/file-from-bare-noext-to-bare-noext-browser.js")), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-bare-noext-default.json",
                   Y("browser/alternate-files/file-from-bare-noext-to-bare-noext-default.json"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-slash-to-bare-noext-default",
@@ -2323,7 +2403,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-bare-slash-default",
                   Y("browser/alternate-files/node_modules/file-from-bare-noext-to-bare-slash-browser.js"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-bare-slash-default.js",
-                  Y("browser/alternate-files/file-from-bare-noext-to-bare-slash-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/node_modules/file-from-bare-noext-to-bare-slash-browser.js")), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-bare-slash-default.json",
                   Y("browser/alternate-files/file-from-bare-noext-to-bare-slash-default.json"), &br);
     assert_resolves(ctx,  "./alternate-files/file-from-bare-noext-to-bare-slash-default/",
@@ -2656,7 +2736,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                  "./file-from-rel-noext-to-rel-ext-default",
                   Y("browser/alternate-files/file-from-rel-noext-to-rel-ext-browser.js"), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-rel-ext-default.js",
-                  Y("browser/alternate-files/file-from-rel-noext-to-rel-ext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/file-from-rel-noext-to-rel-ext-browser.js")), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-rel-ext-default.json",
                   Y("browser/alternate-files/file-from-rel-noext-to-rel-ext-default.json"), &br);
     assert_resolves(ctx,                  "./file-from-rel-slash-to-rel-ext-default",
@@ -2678,7 +2758,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                  "./file-from-rel-noext-to-rel-noext-default",
                   Y("browser/alternate-files/file-from-rel-noext-to-rel-noext-browser.js"), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-rel-noext-default.js",
-                  Y("browser/alternate-files/file-from-rel-noext-to-rel-noext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/file-from-rel-noext-to-rel-noext-browser.js")), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-rel-noext-default.json",
                   Y("browser/alternate-files/file-from-rel-noext-to-rel-noext-default.json"), &br);
     assert_resolves(ctx,                  "./file-from-rel-slash-to-rel-noext-default",
@@ -2706,7 +2786,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                  "./file-from-rel-noext-to-rel-slash-default",
                   Y("browser/alternate-files/file-from-rel-noext-to-rel-slash-browser/index.js"), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-rel-slash-default.js",
-                  Y("browser/alternate-files/file-from-rel-noext-to-rel-slash-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/file-from-rel-noext-to-rel-slash-browser/index.js")), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-rel-slash-default.json",
                   Y("browser/alternate-files/file-from-rel-noext-to-rel-slash-default.json"), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-rel-slash-default/",
@@ -2732,7 +2812,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                  "./file-from-bare-noext-to-rel-ext-default",
                   Y("browser/alternate-files/file-from-bare-noext-to-rel-ext-browser.js"), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-rel-ext-default.js",
-                  Y("browser/alternate-files/file-from-bare-noext-to-rel-ext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/file-from-bare-noext-to-rel-ext-browser.js")), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-rel-ext-default.json",
                   Y("browser/alternate-files/file-from-bare-noext-to-rel-ext-default.json"), &br);
     assert_resolves(ctx,                  "./file-from-bare-slash-to-rel-ext-default",
@@ -2754,7 +2834,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                  "./file-from-bare-noext-to-rel-noext-default",
                   Y("browser/alternate-files/file-from-bare-noext-to-rel-noext-browser.js"), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-rel-noext-default.js",
-                  Y("browser/alternate-files/file-from-bare-noext-to-rel-noext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/file-from-bare-noext-to-rel-noext-browser.js")), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-rel-noext-default.json",
                   Y("browser/alternate-files/file-from-bare-noext-to-rel-noext-default.json"), &br);
     assert_resolves(ctx,                  "./file-from-bare-slash-to-rel-noext-default",
@@ -2782,7 +2862,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                  "./file-from-bare-noext-to-rel-slash-default",
                   Y("browser/alternate-files/file-from-bare-noext-to-rel-slash-browser/index.js"), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-rel-slash-default.js",
-                  Y("browser/alternate-files/file-from-bare-noext-to-rel-slash-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/file-from-bare-noext-to-rel-slash-browser/index.js")), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-rel-slash-default.json",
                   Y("browser/alternate-files/file-from-bare-noext-to-rel-slash-default.json"), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-rel-slash-default/",
@@ -2808,7 +2888,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                  "./file-from-rel-noext-to-bare-ext-default",
                   Y("browser/alternate-files/node_modules/file-from-rel-noext-to-bare-ext-browser.js"), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-bare-ext-default.js",
-                  Y("browser/alternate-files/file-from-rel-noext-to-bare-ext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/node_modules/file-from-rel-noext-to-bare-ext-browser.js")), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-bare-ext-default.json",
                   Y("browser/alternate-files/file-from-rel-noext-to-bare-ext-default.json"), &br);
     assert_resolves(ctx,                  "./file-from-rel-slash-to-bare-ext-default",
@@ -2830,7 +2910,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                  "./file-from-rel-noext-to-bare-noext-default",
                   Y("browser/alternate-files/node_modules/file-from-rel-noext-to-bare-noext-browser.js"), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-bare-noext-default.js",
-                  Y("browser/alternate-files/file-from-rel-noext-to-bare-noext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/node_modules/file-from-rel-noext-to-bare-noext-browser.js")), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-bare-noext-default.json",
                   Y("browser/alternate-files/file-from-rel-noext-to-bare-noext-default.json"), &br);
     assert_resolves(ctx,                  "./file-from-rel-slash-to-bare-noext-default",
@@ -2858,7 +2938,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                  "./file-from-rel-noext-to-bare-slash-default",
                   Y("browser/alternate-files/node_modules/file-from-rel-noext-to-bare-slash-browser.js"), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-bare-slash-default.js",
-                  Y("browser/alternate-files/file-from-rel-noext-to-bare-slash-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/node_modules/file-from-rel-noext-to-bare-slash-browser.js")), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-bare-slash-default.json",
                   Y("browser/alternate-files/file-from-rel-noext-to-bare-slash-default.json"), &br);
     assert_resolves(ctx,                  "./file-from-rel-noext-to-bare-slash-default/",
@@ -2884,7 +2964,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                  "./file-from-bare-noext-to-bare-ext-default",
                   Y("browser/alternate-files/node_modules/file-from-bare-noext-to-bare-ext-browser.js"), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-bare-ext-default.js",
-                  Y("browser/alternate-files/file-from-bare-noext-to-bare-ext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/node_modules/file-from-bare-noext-to-bare-ext-browser.js")), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-bare-ext-default.json",
                   Y("browser/alternate-files/file-from-bare-noext-to-bare-ext-default.json"), &br);
     assert_resolves(ctx,                  "./file-from-bare-slash-to-bare-ext-default",
@@ -2906,7 +2986,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                  "./file-from-bare-noext-to-bare-noext-default",
                   Y("browser/alternate-files/node_modules/file-from-bare-noext-to-bare-noext-browser.js"), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-bare-noext-default.js",
-                  Y("browser/alternate-files/file-from-bare-noext-to-bare-noext-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/node_modules/file-from-bare-noext-to-bare-noext-browser.js")), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-bare-noext-default.json",
                   Y("browser/alternate-files/file-from-bare-noext-to-bare-noext-default.json"), &br);
     assert_resolves(ctx,                  "./file-from-bare-slash-to-bare-noext-default",
@@ -2934,7 +3014,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                  "./file-from-bare-noext-to-bare-slash-default",
                   Y("browser/alternate-files/node_modules/file-from-bare-noext-to-bare-slash-browser.js"), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-bare-slash-default.js",
-                  Y("browser/alternate-files/file-from-bare-noext-to-bare-slash-default.js"), &br);
+                  NotWebpack(&Y("browser/alternate-files/node_modules/file-from-bare-noext-to-bare-slash-browser.js")), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-bare-slash-default.json",
                   Y("browser/alternate-files/file-from-bare-noext-to-bare-slash-default.json"), &br);
     assert_resolves(ctx,                  "./file-from-bare-noext-to-bare-slash-default/",
@@ -3267,7 +3347,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-rel-ext-default",
                   Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-rel-ext-browser.js"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-rel-ext-default.js",
-                  Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-rel-ext-default.js"), &br);
+                  NotWebpack(&Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-rel-ext-browser.js")), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-rel-ext-default.json",
                   Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-rel-ext-default.json"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-slash-to-rel-ext-default",
@@ -3289,7 +3369,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-rel-noext-default",
                   Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-rel-noext-browser.js"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-rel-noext-default.js",
-                  Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-rel-noext-default.js"), &br);
+                  NotWebpack(&Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-rel-noext-browser.js")), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-rel-noext-default.json",
                   Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-rel-noext-default.json"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-slash-to-rel-noext-default",
@@ -3317,7 +3397,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-rel-slash-default",
                   Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-rel-slash-browser/index.js"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-rel-slash-default.js",
-                  Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-rel-slash-default.js"), &br);
+                  NotWebpack(&Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-rel-slash-browser/index.js")), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-rel-slash-default.json",
                   Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-rel-slash-default.json"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-rel-slash-default/",
@@ -3343,7 +3423,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-rel-ext-default",
                   Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-rel-ext-browser.js"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-rel-ext-default.js",
-                  Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-rel-ext-default.js"), &br);
+                  NotWebpack(&Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-rel-ext-browser.js")), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-rel-ext-default.json",
                   Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-rel-ext-default.json"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-slash-to-rel-ext-default",
@@ -3365,7 +3445,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-rel-noext-default",
                   Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-rel-noext-browser.js"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-rel-noext-default.js",
-                  Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-rel-noext-default.js"), &br);
+                  NotWebpack(&Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-rel-noext-browser.js")), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-rel-noext-default.json",
                   Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-rel-noext-default.json"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-slash-to-rel-noext-default",
@@ -3393,7 +3473,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-rel-slash-default",
                   Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-rel-slash-browser/index.js"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-rel-slash-default.js",
-                  Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-rel-slash-default.js"), &br);
+                  NotWebpack(&Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-rel-slash-browser/index.js")), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-rel-slash-default.json",
                   Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-rel-slash-default.json"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-rel-slash-default/",
@@ -3419,7 +3499,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-bare-ext-default",
                   Y("browser/node_modules/n-alternate-files/node_modules/file-from-rel-noext-to-bare-ext-browser.js"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-bare-ext-default.js",
-                  Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-bare-ext-default.js"), &br);
+                  NotWebpack(&Y("browser/node_modules/n-alternate-files/node_modules/file-from-rel-noext-to-bare-ext-browser.js")), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-bare-ext-default.json",
                   Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-bare-ext-default.json"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-slash-to-bare-ext-default",
@@ -3441,7 +3521,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-bare-noext-default",
                   Y("browser/node_modules/n-alternate-files/node_modules/file-from-rel-noext-to-bare-noext-browser.js"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-bare-noext-default.js",
-                  Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-bare-noext-default.js"), &br);
+                  NotWebpack(&Y("browser/node_modules/n-alternate-files/node_modules/file-from-rel-noext-to-bare-noext-browser.js")), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-bare-noext-default.json",
                   Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-bare-noext-default.json"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-slash-to-bare-noext-default",
@@ -3469,7 +3549,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-bare-slash-default",
                   Y("browser/node_modules/n-alternate-files/node_modules/file-from-rel-noext-to-bare-slash-browser.js"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-bare-slash-default.js",
-                  Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-bare-slash-default.js"), &br);
+                  NotWebpack(&Y("browser/node_modules/n-alternate-files/node_modules/file-from-rel-noext-to-bare-slash-browser.js")), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-bare-slash-default.json",
                   Y("browser/node_modules/n-alternate-files/file-from-rel-noext-to-bare-slash-default.json"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-rel-noext-to-bare-slash-default/",
@@ -3495,7 +3575,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-bare-ext-default",
                   Y("browser/node_modules/n-alternate-files/node_modules/file-from-bare-noext-to-bare-ext-browser.js"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-bare-ext-default.js",
-                  Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-bare-ext-default.js"), &br);
+                  NotWebpack(&Y("browser/node_modules/n-alternate-files/node_modules/file-from-bare-noext-to-bare-ext-browser.js")), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-bare-ext-default.json",
                   Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-bare-ext-default.json"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-slash-to-bare-ext-default",
@@ -3517,7 +3597,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-bare-noext-default",
                   Y("browser/node_modules/n-alternate-files/node_modules/file-from-bare-noext-to-bare-noext-browser.js"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-bare-noext-default.js",
-                  Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-bare-noext-default.js"), &br);
+                  NotWebpack(&Y("browser/node_modules/n-alternate-files/node_modules/file-from-bare-noext-to-bare-noext-browser.js")), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-bare-noext-default.json",
                   Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-bare-noext-default.json"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-slash-to-bare-noext-default",
@@ -3545,7 +3625,7 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-bare-slash-default",
                   Y("browser/node_modules/n-alternate-files/node_modules/file-from-bare-noext-to-bare-slash-browser.js"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-bare-slash-default.js",
-                  Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-bare-slash-default.js"), &br);
+                  NotWebpack(&Y("browser/node_modules/n-alternate-files/node_modules/file-from-bare-noext-to-bare-slash-browser.js")), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-bare-slash-default.json",
                   Y("browser/node_modules/n-alternate-files/file-from-bare-noext-to-bare-slash-default.json"), &br);
     assert_resolves(ctx,                 "n-alternate-files/file-from-bare-noext-to-bare-slash-default/",
@@ -3682,14 +3762,56 @@ where F: FnMut(&str, &str, Resolution<'static>, &InputOptions) {
                   Y("browser/ignore-files/file-rel-ext.js"), &no);
     assert_resolves(ctx,  "./ignore-files/file-bare-noext", Ignore, &br);
     assert_resolves(ctx,  "./ignore-files/file-bare-noext.js",
-                  Y("browser/ignore-files/file-bare-noext.js"), &br);
+                  NotWebpack(&Ignore), &br);
     assert_resolves(ctx,  "./ignore-files/file-bare-ext", Ignore, &br);
     assert_resolves(ctx,  "./ignore-files/file-bare-ext.js", Ignore, &br);
     assert_resolves(ctx,  "./ignore-files/file-rel-noext", Ignore, &br);
     assert_resolves(ctx,  "./ignore-files/file-rel-noext.js",
-                  Y("browser/ignore-files/file-rel-noext.js"), &br);
+                  NotWebpack(&Ignore), &br);
     assert_resolves(ctx,  "./ignore-files/file-rel-ext", Ignore, &br);
     assert_resolves(ctx,  "./ignore-files/file-rel-ext.js", Ignore, &br);
+
+    // keys match with or without an extension, and directory keys match the directory's index file
+    let ctx = "browser/normalized-keys/hypothetical.js";
+    assert_resolves(ctx, "./lib/node",
+                  Y("browser/normalized-keys/lib/node.js"), &no);
+    assert_resolves(ctx, "./lib",
+                  Y("browser/normalized-keys/lib/index.js"), &no);
+    assert_resolves(ctx, "./util",
+                  Y("browser/normalized-keys/util.js"), &no);
+    assert_resolves(ctx, "./dir",
+                  Y("browser/normalized-keys/dir.js"), &no);
+    assert_resolves(ctx, "./lib/node",
+                  Y("browser/normalized-keys/lib/browser.js"), &br);
+    assert_resolves(ctx, "./lib/node.js",
+                  Y("browser/normalized-keys/lib/browser.js"), &br);
+    assert_resolves(ctx, "./lib",
+                  Y("browser/normalized-keys/lib/index-browser.js"), &br);
+    assert_resolves(ctx, "./lib/",
+                  Y("browser/normalized-keys/lib/index-browser.js"), &br);
+    assert_resolves(ctx, "./lib/index",
+                  Y("browser/normalized-keys/lib/index-browser.js"), &br);
+    assert_resolves(ctx, "./lib/index.js",
+                  Y("browser/normalized-keys/lib/index-browser.js"), &br);
+    assert_resolves(ctx, "./util",
+                  Y("browser/normalized-keys/util-browser.js"), &br);
+    // a key without an extension matches the file it resolves to
+    assert_resolves(ctx, "./util.js",
+                  NotWebpack(&Y("browser/normalized-keys/util-browser.js")), &br);
+    assert_resolves(ctx, "./same",
+                  Y("browser/normalized-keys/same.js"), &br);
+    assert_resolves(ctx, "./dir",
+                  Y("browser/normalized-keys/dir-browser/index.js"), &br);
+
+    let ctx = "browser/hypothetical.js";
+    assert_resolves(ctx, "./normalized-keys/lib/node",
+                  Y("browser/normalized-keys/lib/browser.js"), &br);
+    assert_resolves(ctx, "./normalized-keys/lib",
+                  Y("browser/normalized-keys/lib/index-browser.js"), &br);
+    assert_resolves(ctx, "./normalized-keys/util",
+                  Y("browser/normalized-keys/util-browser.js"), &br);
+    assert_resolves(ctx, "./normalized-keys/util.js",
+                  NotWebpack(&Y("browser/normalized-keys/util-browser.js")), &br);
 }
 
 #[test]
@@ -3702,12 +3824,14 @@ fn test_external() {
             "external".to_owned(),
             "external-only-module".to_owned(),
         ].into_iter().collect(),
+        ..InputOptions::default()
     };
     let non = InputOptions {
         for_browser: false,
         es6_syntax: false,
         es6_syntax_everywhere: false,
         external: Default::default(),
+        ..InputOptions::default()
     };
 
     let ctx = "resolve/hypothetical.js";
@@ -3739,6 +3863,1140 @@ fn test_external() {
         Y("resolve/node_modules/external/subdir/index.js"), &non);
 }
 
+#[test]
+fn test_deserialize_exports() {
+    let parse = |s| PackageExports::from_target(serde_json::from_str::<ExportTarget>(s).unwrap());
+    assert_eq!(parse(r#""./main.js""#), PackageExports(vec![
+        (".".to_owned(), ExportTarget::Path("./main.js".to_owned())),
+    ]));
+    assert_eq!(parse(r#"{"require": "./main.js", "import": "./main.mjs"}"#), PackageExports(vec![
+        (".".to_owned(), ExportTarget::Conditions(vec![
+            ("require".to_owned(), ExportTarget::Path("./main.js".to_owned())),
+            ("import".to_owned(), ExportTarget::Path("./main.mjs".to_owned())),
+        ])),
+    ]));
+    assert_eq!(parse(r#"{"./b": ["./b.js", null], "./a": false}"#), PackageExports(vec![
+        ("./b".to_owned(), ExportTarget::Alternatives(vec![
+            ExportTarget::Path("./b.js".to_owned()),
+            ExportTarget::Null,
+        ])),
+        ("./a".to_owned(), ExportTarget::Null),
+    ]));
+}
+
+#[test]
+fn test_exports() {
+    let cjs = InputOptions::default();
+    let esm = InputOptions {
+        es6_syntax: true,
+        ..InputOptions::default()
+    };
+    let br = InputOptions {
+        for_browser: true,
+        ..InputOptions::default()
+    };
+    let dev = InputOptions {
+        conditions: vec!["development".to_owned()].into_iter().collect(),
+        ..InputOptions::default()
+    };
+    let br_dev = InputOptions {
+        for_browser: true,
+        conditions: vec!["development".to_owned()].into_iter().collect(),
+        ..InputOptions::default()
+    };
+
+    let ctx = "resolve/hypothetical.js";
+    let mctx = "resolve/hypothetical.mjs";
+    assert_resolves(ctx, "exports-string",
+        Y("resolve/node_modules/exports-string/lib/main.js"), &cjs);
+    assert_resolves(ctx, "exports-string/lib/main.js", Fail, &cjs);
+    assert_resolves(ctx, "exports-string/index.js", Fail, &cjs);
+    assert_resolves(ctx, "exports-string/package.json", Fail, &cjs);
+
+    assert_resolves(ctx, "exports-conditions",
+        Y("resolve/node_modules/exports-conditions/default.js"), &cjs);
+    assert_resolves(ctx, "exports-conditions",
+        Y("resolve/node_modules/exports-conditions/default.js"), &esm);
+    assert_resolves(mctx, "exports-conditions",
+        Y("resolve/node_modules/exports-conditions/esm.mjs"), &esm);
+    assert_resolves(ctx, "exports-conditions",
+        Y("resolve/node_modules/exports-conditions/browser.js"), &br);
+    assert_resolves(ctx, "exports-conditions",
+        Y("resolve/node_modules/exports-conditions/dev.js"), &dev);
+    assert_resolves(ctx, "exports-conditions",
+        Y("resolve/node_modules/exports-conditions/browser.dev.js"), &br_dev);
+
+    assert_resolves(ctx, "exports-conditions/feature",
+        Y("resolve/node_modules/exports-conditions/lib/feature.js"), &cjs);
+    assert_resolves(ctx, "exports-conditions/feature.js", Fail, &cjs);
+    assert_resolves(ctx, "exports-conditions/fallback",
+        Y("resolve/node_modules/exports-conditions/lib/feature.js"), &cjs);
+    assert_resolves(ctx, "exports-conditions/lib/util",
+        Y("resolve/node_modules/exports-conditions/lib/util.js"), &cjs);
+    assert_resolves(ctx, "exports-conditions/lib/missing", Fail, &cjs);
+    assert_resolves(ctx, "exports-conditions/lib/private/secret", Fail, &cjs);
+    assert_resolves(ctx, "exports-conditions/lib/special/a.js",
+        Y("resolve/node_modules/exports-conditions/special/a.js"), &cjs);
+    assert_resolves(ctx, "exports-conditions/escape/package", Fail, &cjs);
+    assert_resolves(ctx, "exports-conditions/nope", Fail, &cjs);
+
+    assert_resolves(ctx, "@user/exports/sub",
+        Y("resolve/node_modules/@user/exports/sub.js"), &cjs);
+    assert_resolves(ctx, "@user/exports", Fail, &cjs);
+    assert_resolves(ctx, "@user/scoped",
+        Y("resolve/node_modules/@user/scoped/index.js"), &cjs);
+}
+
+#[test]
+fn test_imports() {
+    let cjs = InputOptions::default();
+    let br = InputOptions {
+        for_browser: true,
+        ..InputOptions::default()
+    };
+
+    let ctx = "resolve/imports/src/hypothetical.js";
+    assert_resolves(ctx, "#utils/x",
+        Y("resolve/imports/src/utils/x.js"), &cjs);
+    assert_resolves(ctx, "#utils/missing", Fail, &cjs);
+    assert_resolves(ctx, "#config",
+        Y("resolve/imports/src/config.js"), &cjs);
+    assert_resolves(ctx, "#config",
+        Y("resolve/imports/src/config.browser.js"), &br);
+    assert_resolves(ctx, "#external",
+        Y("resolve/node_modules/external/index.js"), &cjs);
+    assert_resolves(ctx, "#blocked", Fail, &cjs);
+    assert_resolves(ctx, "#not-a-hash", Fail, &cjs);
+    assert_resolves(ctx, "#nope", Fail, &cjs);
+
+    let ctx = "resolve/hypothetical.js";
+    assert_resolves(ctx, "#utils/x", Fail, &cjs);
+}
+
+#[test]
+fn test_main_fields() {
+    let cjs = InputOptions::default();
+    let esm = InputOptions {
+        es6_syntax: true,
+        ..InputOptions::default()
+    };
+    let br = InputOptions {
+        for_browser: true,
+        ..InputOptions::default()
+    };
+    let br_esm = InputOptions {
+        for_browser: true,
+        es6_syntax: true,
+        ..InputOptions::default()
+    };
+    let custom = InputOptions {
+        for_browser: true,
+        es6_syntax: true,
+        main_fields: vec!["module".to_owned(), "browser".to_owned(), "main".to_owned()],
+        ..InputOptions::default()
+    };
+    let main_only = InputOptions {
+        for_browser: true,
+        es6_syntax: true,
+        main_fields: vec!["main".to_owned()],
+        ..InputOptions::default()
+    };
+
+    let ctx = "resolve/hypothetical.js";
+    assert_resolves(ctx, "main-fields",
+        Y("resolve/node_modules/main-fields/main.js"), &cjs);
+    assert_resolves(ctx, "main-fields",
+        Y("resolve/node_modules/main-fields/module.js"), &esm);
+    assert_resolves(ctx, "main-fields",
+        Y("resolve/node_modules/main-fields/browser.js"), &br);
+    assert_resolves(ctx, "main-fields",
+        Y("resolve/node_modules/main-fields/browser.js"), &br_esm);
+    assert_resolves(ctx, "main-fields",
+        Y("resolve/node_modules/main-fields/module.js"), &custom);
+    assert_resolves(ctx, "main-fields",
+        Y("resolve/node_modules/main-fields/main.js"), &main_only);
+
+    // falls through fields whose files are missing
+    assert_resolves(ctx, "main-fields-missing",
+        Y("resolve/node_modules/main-fields-missing/main.js"), &esm);
+
+    let resolver = Resolver::new(esm.clone());
+    let mut path = fixture_path();
+    path.append_resolving("resolve/node_modules/main-fields/module.js");
+    assert!(resolver.in_module_build(&path).unwrap());
+    // only the directory of the ESM entry point is the ESM build
+    let mut builds = fixture_path();
+    builds.append_resolving("resolve/node_modules/main-fields-builds/dist");
+    assert!(resolver.in_module_build(&builds.join("esm/index.js")).unwrap());
+    assert!(!resolver.in_module_build(&builds.join("cjs/index.js")).unwrap());
+    let resolver = Resolver::new(cjs.clone());
+    assert!(!resolver.in_module_build(&path).unwrap());
+}
+
+#[test]
+fn test_alias() {
+    let mut src = fixture_path();
+    src.append_resolving("resolve/dir-js");
+    let mut named = fixture_path();
+    named.append_resolving("resolve/named-js.js");
+    let aliases = vec![
+        ("aliased".to_owned(), "external".to_owned()),
+        ("aliased/subdir".to_owned(), "n-dir-js".to_owned()),
+        ("@app/".to_owned(), format!("{}/", src.display())),
+        ("abs-alias".to_owned(), named.to_string_lossy().into_owned()),
+    ];
+    let cjs = InputOptions {
+        aliases: aliases.clone(),
+        ..InputOptions::default()
+    };
+    let br = InputOptions {
+        for_browser: true,
+        aliases: aliases.clone(),
+        ..InputOptions::default()
+    };
+    let ext = InputOptions {
+        external: vec!["aliased".to_owned()].into_iter().collect(),
+        aliases: aliases.clone(),
+        ..InputOptions::default()
+    };
+
+    let ctx = "resolve/hypothetical.js";
+    for input_options in &[&cjs, &br] {
+        assert_resolves(ctx, "aliased",
+            Y("resolve/node_modules/external/index.js"), input_options);
+        assert_resolves(ctx, "aliased/file",
+            Y("resolve/node_modules/external/file.js"), input_options);
+        assert_resolves(ctx, "aliased/subdir",
+            Y("resolve/node_modules/n-dir-js/index.js"), input_options);
+        assert_resolves(ctx, "aliasedx", Fail, input_options);
+        assert_resolves(ctx, "@app/index",
+            Y("resolve/dir-js/index.js"), input_options);
+        assert_resolves(ctx, "@app/index.js",
+            Y("resolve/dir-js/index.js"), input_options);
+        assert_resolves(ctx, "@app", Fail, input_options);
+        assert_resolves(ctx, "abs-alias",
+            Y("resolve/named-js.js"), input_options);
+        assert_resolves(ctx, "./aliased", Fail, input_options);
+    }
+    assert_resolves(ctx, "aliased", External, &ext);
+}
+
+#[test]
+fn test_tsconfig_paths() {
+    let cjs = InputOptions::default();
+
+    let ctx = "tsconfig/project/src/hypothetical.js";
+    assert_resolves(ctx, "@lib/x",
+        Y("tsconfig/project/src/lib/x.js"), &cjs);
+    assert_resolves(ctx, "@lib/x.js",
+        Y("tsconfig/project/src/lib/x.js"), &cjs);
+    assert_resolves(ctx, "@lib/missing", Fail, &cjs);
+    assert_resolves(ctx, "exact",
+        Y("tsconfig/project/src/lib/exact.js"), &cjs);
+    assert_resolves(ctx, "@multi/y",
+        Y("tsconfig/project/src/other/y.js"), &cjs);
+    assert_resolves(ctx, "top",
+        Y("tsconfig/project/src/top.js"), &cjs);
+    assert_resolves(ctx, "lib/x",
+        Y("tsconfig/project/src/lib/x.js"), &cjs);
+    assert_resolves(ctx, "dep",
+        Y("tsconfig/project/node_modules/dep/index.js"), &cjs);
+
+    // dependencies don't use the project's config
+    let ctx = "tsconfig/project/node_modules/dep/hypothetical.js";
+    assert_resolves(ctx, "top", Fail, &cjs);
+
+    let ctx = "tsconfig/shared/src/hypothetical.js";
+    assert_resolves(ctx, "~/z",
+        Y("tsconfig/shared/src/z.js"), &cjs);
+
+    let mut ctx = fixture_path();
+    ctx.append_resolving("tsconfig/cycle/hypothetical.js");
+    assert_matches!(Resolver::new(cjs).resolve(&ctx, "anything"), Err(CliError::TsConfig(tsconfig::Error {
+        kind: tsconfig::ErrorKind::ExtendsCycle(_),
+        ..
+    })));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    // the fixture tree has no symlinks, since the consistency tests copy it
+    let dir = tempfile::tempdir().unwrap();
+    let base = fs::canonicalize(dir.path()).unwrap();
+    fs::create_dir_all(base.join("project/node_modules")).unwrap();
+    for (file, source) in &[
+        ("project/main.js", "require('pkg'); require('./linked')\n"),
+        ("store/node_modules/pkg/index.js", "module.exports = require('peer')\n"),
+        ("store/node_modules/peer/index.js", "module.exports = 'peer'\n"),
+    ] {
+        fs::create_dir_all(base.join(file).parent().unwrap()).unwrap();
+        fs::write(base.join(file), source).unwrap();
+    }
+    symlink("../../store/node_modules/pkg", base.join("project/node_modules/pkg")).unwrap();
+    symlink("../store/node_modules/pkg/index.js", base.join("project/linked.js")).unwrap();
+
+    let cjs = Resolver::new(InputOptions::default());
+    let preserve = Resolver::new(InputOptions {
+        preserve_symlinks: true,
+        ..InputOptions::default()
+    });
+    let resolves = |resolver: &Resolver, context: &str, name: &str| match resolver.resolve(&base.join(context), name) {
+        Ok(Resolved::Normal(path)) => Some(path),
+        _ => None,
+    };
+
+    let ctx = "project/main.js";
+    assert_eq!(resolves(&cjs, ctx, "pkg"), Some(base.join("store/node_modules/pkg/index.js")));
+    assert_eq!(resolves(&cjs, ctx, "./linked"), Some(base.join("store/node_modules/pkg/index.js")));
+    assert_eq!(resolves(&preserve, ctx, "pkg"), Some(base.join("project/node_modules/pkg/index.js")));
+    assert_eq!(resolves(&preserve, ctx, "./linked"), Some(base.join("project/linked.js")));
+
+    // dependencies are looked up from where the package really is
+    assert_eq!(resolves(&cjs, "store/node_modules/pkg/index.js", "peer"), Some(base.join("store/node_modules/peer/index.js")));
+    assert_eq!(resolves(&preserve, "project/node_modules/pkg/index.js", "peer"), None);
+}
+
+#[test]
+fn test_resolve_extensions() {
+    let cjs = InputOptions::default();
+    let jsx = InputOptions {
+        resolve_extensions: vec!["jsx".to_owned(), "js".to_owned(), "cjs".to_owned()],
+        ..InputOptions::default()
+    };
+
+    let ctx = "extensions/hypothetical.js";
+    assert_resolves(ctx, "./component", Fail, &cjs);
+    assert_resolves(ctx, "./common", Fail, &cjs);
+    assert_resolves(ctx, "./dir-jsx", Fail, &cjs);
+    assert_resolves(ctx, "./both",
+        Y("extensions/both.js"), &cjs);
+
+    assert_resolves(ctx, "./component",
+        Y("extensions/component.jsx"), &jsx);
+    assert_resolves(ctx, "./common",
+        Y("extensions/common.cjs"), &jsx);
+    assert_resolves(ctx, "./dir-jsx",
+        Y("extensions/dir-jsx/index.jsx"), &jsx);
+    assert_resolves(ctx, "./both",
+        Y("extensions/both.jsx"), &jsx);
+    assert_resolves(ctx, "./both.js",
+        Y("extensions/both.js"), &jsx);
+    assert_resolves(ctx, "../resolve/named-json", Fail, &jsx);
+}
+
+#[test]
+fn test_module_kind() {
+    let esm = InputOptions {
+        es6_syntax: true,
+        ..InputOptions::default()
+    };
+    let everywhere = InputOptions {
+        es6_syntax: true,
+        es6_syntax_everywhere: true,
+        ..InputOptions::default()
+    };
+    let resolver = Resolver::new(esm);
+    assert_eq!(resolver.module_kind(Path::new("/a.mjs")).unwrap(), ModuleKind::Esm);
+    assert_eq!(resolver.module_kind(Path::new("/a.cjs")).unwrap(), ModuleKind::Cjs);
+    assert_eq!(resolver.module_kind(Path::new("/a.json")).unwrap(), ModuleKind::Json);
+    assert_eq!(resolver.module_kind(Path::new("/a.js")).unwrap(), ModuleKind::Cjs);
+    assert_eq!(resolver.module_kind(Path::new("/a.jsx")).unwrap(), ModuleKind::Cjs);
+    let resolver = Resolver::new(everywhere);
+    assert_eq!(resolver.module_kind(Path::new("/a.mjs")).unwrap(), ModuleKind::Esm);
+    assert_eq!(resolver.module_kind(Path::new("/a.cjs")).unwrap(), ModuleKind::Cjs);
+    assert_eq!(resolver.module_kind(Path::new("/a.js")).unwrap(), ModuleKind::Mixed);
+    assert_eq!(resolver.module_kind(Path::new("/a.jsx")).unwrap(), ModuleKind::Mixed);
+    assert!(!resolver.is_esm_context(Path::new("/a.cjs")));
+    assert!(resolver.is_esm_context(Path::new("/a.jsx")));
+}
+
+#[test]
+fn test_modules_dirs() {
+    let mut lib = fixture_path();
+    lib.append_resolving("modules-dirs/lib");
+    let mut lib2 = fixture_path();
+    lib2.append_resolving("modules-dirs/lib2");
+    let cjs = InputOptions::default();
+    let dirs = InputOptions {
+        modules_dirs: vec![lib.clone(), lib2.clone()],
+        ..InputOptions::default()
+    };
+
+    let ctx = "modules-dirs/app/hypothetical.js";
+    assert_resolves(ctx, "shared", Fail, &cjs);
+    assert_resolves(ctx, "shared",
+        Y("modules-dirs/lib/shared/index.js"), &dirs);
+    assert_resolves(ctx, "only2",
+        Y("modules-dirs/lib2/only2.js"), &dirs);
+    assert_resolves(ctx, "local",
+        Y("modules-dirs/app/node_modules/local/index.js"), &dirs);
+
+    let mut ctx = fixture_path();
+    ctx.append_resolving("modules-dirs/app/hypothetical.js");
+    match Resolver::new(dirs).resolve(&ctx, "missing") {
+        Err(CliError::ModuleNotFound { searched, .. }) => {
+            assert_eq!(searched.first(), Some(&ctx.parent().unwrap().join("node_modules")));
+            assert_eq!(&searched[searched.len() - 2..], &[lib, lib2]);
+        }
+        result => panic!("expected ModuleNotFound, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_substitution_cycles() {
+    let br = InputOptions {
+        for_browser: true,
+        ..InputOptions::default()
+    };
+    let alias = InputOptions {
+        aliases: vec![
+            ("x".to_owned(), "y".to_owned()),
+            ("y".to_owned(), "x".to_owned()),
+        ],
+        ..InputOptions::default()
+    };
+    let mut ctx = fixture_path();
+    ctx.append_resolving("cycles/hypothetical.js");
+    let mut package = fixture_path();
+    package.append_resolving("cycles");
+
+    let cycle = |input_options: &InputOptions, name: &str| {
+        match Resolver::new(input_options.clone()).resolve(&ctx, name) {
+            Err(CliError::SubstitutionCycle { names, package, .. }) => (names, package),
+            result => panic!("expected SubstitutionCycle, got {:?}", result),
+        }
+    };
+    assert_eq!(cycle(&br, "a"), (vec!["a".to_owned(), "b".to_owned(), "a".to_owned()], Some(package.clone())));
+    assert_eq!(cycle(&br, "b/file"), (vec!["b/file".to_owned(), "a/file".to_owned(), "b/file".to_owned()], Some(package.clone())));
+    assert_eq!(cycle(&br, "self"), (vec!["self".to_owned(), "self".to_owned()], Some(package.clone())));
+    assert_eq!(cycle(&br, "#i"), (vec!["#i".to_owned(), "j".to_owned(), "#i".to_owned()], Some(package.clone())));
+    assert_eq!(cycle(&alias, "x"), (vec!["x".to_owned(), "y".to_owned(), "x".to_owned()], None));
+
+    // through a path that the browser field replaces with a module name
+    let mut ctx = package.join("paths");
+    let a = ctx.join("a.js");
+    ctx.push("hypothetical.js");
+    match Resolver::new(br.clone()).resolve(&ctx, "./a.js") {
+        Err(CliError::SubstitutionCycle { names, package: Some(p), .. }) => {
+            assert_eq!(names, vec![a.display().to_string(), "b".to_owned(), a.display().to_string()]);
+            assert_eq!(p, package.join("paths"));
+        }
+        result => panic!("expected SubstitutionCycle, got {:?}", result),
+    }
+
+    let error = CliError::SubstitutionCycle {
+        context: PathBuf::from("/x/index.js"),
+        names: vec!["a".to_owned(), "b".to_owned(), "a".to_owned()],
+        package: Some(PathBuf::from("/x/node_modules/y")),
+    };
+    assert_eq!(error.to_string(), "module substitution cycle in /x/index.js: a -> b -> a in /x/node_modules/y/package.json");
+}
+
+#[test]
+fn test_polyfill_core() {
+    let br = InputOptions {
+        for_browser: true,
+        ..InputOptions::default()
+    };
+    let poly = InputOptions {
+        for_browser: true,
+        polyfill_core: true,
+        ..InputOptions::default()
+    };
+    let custom = InputOptions {
+        for_browser: true,
+        polyfill_core: true,
+        polyfills: vec![
+            ("fs".to_owned(), Some("my-fs".to_owned())),
+            ("zlib".to_owned(), None),
+        ],
+        ..InputOptions::default()
+    };
+
+    let ctx = "polyfills/hypothetical.js";
+    assert_resolves(ctx, "events",
+        Y("polyfills/node_modules/events/index.js"), &br);
+    assert_resolves(ctx, "path", Fail, &br);
+
+    assert_resolves(ctx, "events",
+        Y("polyfills/node_modules/events/index.js"), &poly);
+    assert_resolves(ctx, "path",
+        Y("polyfills/node_modules/path-browserify/path.js"), &poly);
+    assert_resolves(ctx, "path/posix",
+        Y("polyfills/node_modules/path-browserify/posix.js"), &poly);
+    assert_resolves(ctx, "fs", Ignore, &poly);
+    assert_resolves(ctx, "crypto", Ignore, &poly);
+    // the browser field comes first
+    assert_resolves(ctx, "os", Ignore, &poly);
+
+    assert_resolves(ctx, "fs",
+        Y("polyfills/node_modules/my-fs/index.js"), &custom);
+    assert_resolves(ctx, "zlib", Ignore, &custom);
+
+    let mut ctx = fixture_path();
+    ctx.append_resolving("polyfills/hypothetical.js");
+    let resolver = Resolver::new(custom);
+    resolver.resolve(&ctx, "fs").unwrap();
+    resolver.resolve(&ctx, "zlib").unwrap();
+    resolver.resolve(&ctx, "net").unwrap();
+    resolver.resolve(&ctx, "crypto").unwrap();
+    assert_eq!(*resolver.warnings.borrow(), vec![
+        Warning::NoPolyfill { name: "net".to_owned(), polyfill: None },
+        Warning::NoPolyfill { name: "crypto".to_owned(), polyfill: Some("crypto-browserify".to_owned()) },
+    ]);
+}
+
+#[test]
+fn test_trace_resolve() {
+    let base = fixture_path();
+    let ctx = base.join("resolve/hypothetical.js");
+    let resolver = Resolver::new(InputOptions {
+        aliases: vec![("aliased".to_owned(), "n-dir-js".to_owned())],
+        trace_resolve: true,
+        ..InputOptions::default()
+    });
+    let (result, trace) = resolver.resolve_traced(&ctx, "aliased");
+    let found = base.join("resolve/node_modules/n-dir-js/index.js");
+    assert_eq!(result.unwrap(), Resolved::Normal(found.clone()));
+    assert_eq!(trace.first(), Some(&TraceStep::Resolve { context: ctx.clone(), name: "aliased".to_owned() }));
+    assert!(trace.contains(&TraceStep::Substitute(ModuleSubstitution::Replace { name: "n-dir-js".to_owned(), package: None })));
+    assert!(trace.contains(&TraceStep::ModulesDir(base.join("resolve/node_modules"))));
+    assert!(trace.contains(&TraceStep::Candidate {
+        path: base.join("resolve/node_modules/n-dir-js.js"),
+        substitution: PathSubstitution::Missing,
+    }));
+    assert_eq!(trace.last(), Some(&TraceStep::Candidate { path: found, substitution: PathSubstitution::Normal }));
+
+    // each resolution starts a new trace
+    let (_, trace) = resolver.resolve_traced(&ctx, "./named-js");
+    assert_eq!(trace.len(), 3);
+
+    let (result, trace) = resolver.resolve_traced(&ctx, "missing");
+    assert_matches!(result, Err(CliError::ModuleNotFound { .. }));
+    assert!(!trace.is_empty());
+
+    let (_, trace) = Resolver::new(InputOptions::default()).resolve_traced(&ctx, "./named-js");
+    assert!(trace.is_empty());
+}
+
+#[test]
+fn test_check_case() {
+    let base = fixture_path();
+    let ctx = base.join("case/main.js");
+    let button = base.join("case/components/button.js");
+    let wrong = base.join("case/Components/Button.js");
+    let resolver = |check_case| Resolver::new(InputOptions {
+        check_case,
+        ..InputOptions::default()
+    });
+
+    assert_eq!(resolver(Some(CaseCheck::Error)).resolve(&ctx, "./components/button").unwrap(), Resolved::Normal(button.clone()));
+    assert_matches!(resolver(Some(CaseCheck::Error)).check_case(Some(&ctx), &button), Ok(()));
+    // without --check-case, or for the main module
+    assert_matches!(resolver(None).check_case(Some(&ctx), &wrong), Ok(()));
+    assert_matches!(resolver(Some(CaseCheck::Error)).check_case(None, &wrong), Ok(()));
+
+    let err = resolver(Some(CaseCheck::Error)).check_case(Some(&ctx), &wrong).unwrap_err();
+    assert_matches!(err, CliError::CaseMismatch { ref path, ref actual, .. } if *path == wrong && *actual == button);
+
+    let warner = resolver(Some(CaseCheck::Warn));
+    assert_matches!(warner.check_case(Some(&ctx), &wrong), Ok(()));
+    assert_eq!(*warner.warnings.borrow(), vec![Warning::CaseMismatch {
+        context: ctx.clone(),
+        path: wrong.clone(),
+        actual: button.clone(),
+    }]);
+
+    // only the directories that the context is in aren't checked
+    let inner_ctx = base.join("case/components/hypothetical.js");
+    assert_matches!(resolver(Some(CaseCheck::Error)).check_case(Some(&inner_ctx), &base.join("case/components/Button.js")), Err(CliError::CaseMismatch { .. }));
+    assert_matches!(resolver(Some(CaseCheck::Error)).check_case(Some(&inner_ctx), &base.join("case/Components/button.js")), Err(CliError::CaseMismatch { .. }));
+    assert_matches!(resolver(Some(CaseCheck::Error)).check_case(Some(&inner_ctx), &base.join("case/main.js")), Ok(()));
+}
+
+#[test]
+fn test_dedupe() {
+    let base = fixture_path().join("dedupe");
+    let entry_point = base.join("main.js");
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("bundle.js");
+    let output = output.to_str().unwrap();
+    let lib = |p: &str| base.join("node_modules").join(p).join("node_modules/lib");
+
+    let modules = bundle(&entry_point, InputOptions::default(), output, &SourceMapOutput::Suppressed).unwrap().modules;
+    assert_eq!(modules.len(), 10);
+
+    let Bundled { modules, deduped } = bundle(&entry_point, InputOptions {
+        dedupe: true,
+        ..InputOptions::default()
+    }, output, &SourceMapOutput::Suppressed).unwrap();
+    assert_eq!(modules.len(), 8);
+    assert_eq!(deduped.0, 2);
+    for p in &["a", "c"] {
+        assert!(modules.contains_key(&lib(p).join("index.js")));
+        assert!(modules.contains_key(&lib(p).join("util.js")));
+    }
+    // b's copy of lib 1.0.0 is the same as a's
+    assert!(!modules.contains_key(&lib("b").join("index.js")));
+    assert!(!modules.contains_key(&lib("b").join("util.js")));
+    let b = &modules[&base.join("node_modules/b/index.js")];
+    assert_eq!(b.deps["lib"], Resolved::Normal(lib("a").join("index.js")));
+
+    let bundled = fs::read_to_string(output).unwrap();
+    assert!(!bundled.contains("node_modules/b/node_modules"));
+
+    // a's and b's copies of lib 1.0.0 require different versions of helper
+    let base = fixture_path().join("dedupe-nested");
+    let Bundled { modules, deduped } = bundle(&base.join("main.js"), InputOptions {
+        dedupe: true,
+        ..InputOptions::default()
+    }, output, &SourceMapOutput::Suppressed).unwrap();
+    assert_eq!(modules.len(), 7);
+    assert_eq!(deduped, (0, 0));
+}
+
+#[test]
+fn test_require_resolve() {
+    let base = fixture_path().join("require-resolve");
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("bundle.js");
+    let output = output.to_str().unwrap();
+
+    for &(entry, dep, es6_syntax, es6_syntax_everywhere) in &[
+        ("main.js", "./dep", false, false),
+        ("mixed.js", "./dep", true, true),
+        ("module.mjs", "./dep.js", true, false),
+    ] {
+        let entry_point = base.join(entry);
+        let modules = bundle(&entry_point, InputOptions {
+            es6_syntax,
+            es6_syntax_everywhere,
+            ..InputOptions::default()
+        }, output, &SourceMapOutput::Suppressed).unwrap().modules;
+        assert_eq!(modules[&entry_point].deps[dep], Resolved::Normal(base.join("dep.js")));
+
+        let result = process::Command::new("node")
+            .arg(output)
+            .output()
+            .expect("failed to run node");
+        if !result.status.success() {
+            io::stderr().write_all(&result.stderr).unwrap();
+            panic!("require.resolve() in {} didn't return the bundled module's filename", entry);
+        }
+    }
+}
+
+#[test]
+fn test_context_modules() {
+    let base = fixture_path().join("context");
+    let entry_point = base.join("main.js");
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("bundle.js");
+    let output = output.to_str().unwrap();
+
+    let resolver = Resolver::new(InputOptions::default());
+    let request = |parts: &[&str]| ContextRequest {
+        parts: parts.iter().map(|&s| s.to_owned()).collect(),
+    };
+    let mut dirs = Vec::new();
+    let mut names = resolver.context_names(&entry_point, &request(&["./locales/", ".json"]), &mut dirs).unwrap();
+    names.sort();
+    assert_eq!(names, vec!["./locales/en.json", "./locales/fr.json"]);
+    let mut names = resolver.context_names(&entry_point, &request(&["./pages/", ""]), &mut dirs).unwrap();
+    names.sort();
+    assert_eq!(names, vec!["./pages/about", "./pages/about/index", "./pages/about/index.js", "./pages/home", "./pages/home.js"]);
+    assert_eq!(resolver.context_names(&entry_point, &request(&["lodash/", ""]), &mut dirs).unwrap(), Vec::<String>::new());
+    // only files with one of --resolve-extensions
+    assert_eq!(resolver.context_names(&entry_point, &request(&["./pages/", ".txt"]), &mut dirs).unwrap(), Vec::<String>::new());
+    dirs.sort();
+    dirs.dedup();
+    assert_eq!(dirs, vec![base.join("locales"), base.join("pages"), base.join("pages/about")]);
+    // warnings only for requests that match nothing or hit the limit
+    assert_eq!(*resolver.warnings.borrow(), vec![
+        Warning::UnsupportedContext { context: entry_point.clone(), request: request(&["lodash/", ""]) },
+        Warning::Context { context: entry_point.clone(), request: request(&["./pages/", ".txt"]), files: 0 },
+    ]);
+    let resolver = Resolver::new(InputOptions {
+        max_context_files: Some(2),
+        ..InputOptions::default()
+    });
+    resolver.context_names(&entry_point, &request(&["./pages/", ""]), &mut dirs).unwrap();
+    assert_eq!(*resolver.warnings.borrow(), vec![
+        Warning::Context { context: entry_point.clone(), request: request(&["./pages/", ""]), files: 2 },
+    ]);
+
+    for &es6_syntax_everywhere in &[false, true] {
+        let modules = bundle(&entry_point, InputOptions {
+            es6_syntax: es6_syntax_everywhere,
+            es6_syntax_everywhere,
+            ..InputOptions::default()
+        }, output, &SourceMapOutput::Suppressed).unwrap().modules;
+        assert_eq!(modules.len(), 5);
+        assert_eq!(modules[&entry_point].deps["./pages/about"], Resolved::Normal(base.join("pages/about/index.js")));
+
+        let result = process::Command::new("node")
+            .arg(output)
+            .output()
+            .expect("failed to run node");
+        if !result.status.success() {
+            io::stderr().write_all(&result.stderr).unwrap();
+            panic!("partially dynamic require() failed at runtime");
+        }
+    }
+
+    let err = bundle(&entry_point, InputOptions {
+        max_context_files: Some(1),
+        ..InputOptions::default()
+    }, output, &SourceMapOutput::Suppressed).unwrap_err();
+    assert_matches!(err, CliError::ContextTooLarge { limit: 1, .. });
+}
+
+#[test]
+fn test_globs() {
+    let base = fixture_path().join("glob");
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("bundle.js");
+    let output = output.to_str().unwrap();
+
+    for entry in &["esm.mjs", "cjs.js"] {
+        let entry_point = base.join(entry);
+        let modules = bundle(&entry_point, InputOptions {
+            es6_syntax: true,
+            ..InputOptions::default()
+        }, output, &SourceMapOutput::Suppressed).unwrap().modules;
+        assert_eq!(modules.len(), 5);
+        let module = &modules[&entry_point];
+        assert!(module.dirs.contains(&base.join("routes")));
+        assert!(module.dirs.contains(&base.join("routes/admin")));
+        // the source map still lines up
+        let original = fs::read_to_string(&entry_point).unwrap();
+        assert_eq!(count_lines(&module.source.body), count_lines(&original));
+
+        let result = process::Command::new("node")
+            .arg(output)
+            .output()
+            .expect("failed to run node");
+        if !result.status.success() {
+            io::stderr().write_all(&result.stderr).unwrap();
+            panic!("globs in {} failed at runtime", entry);
+        }
+    }
+
+    let err = bundle(&base.join("cjs.js"), InputOptions {
+        max_context_files: Some(3),
+        ..InputOptions::default()
+    }, output, &SourceMapOutput::Suppressed).unwrap_err();
+    assert_matches!(err, CliError::ContextTooLarge { ref request, limit: 3, .. } if request == r"require.context('./routes', true, /\.js$/)");
+
+    // a directory that doesn't exist is watched through the nearest one above it that does
+    let resolver = Resolver::new(InputOptions::default());
+    let mut dirs = Vec::new();
+    let glob = Glob::ImportMeta { patterns: vec!["./missing/sub/*.js".to_owned()], eager: false };
+    assert_eq!(resolver.glob_entries(&base.join("esm.mjs"), &glob, &mut dirs).unwrap(), vec![]);
+    assert_eq!(dirs, vec![base.clone()]);
+}
+
+#[test]
+fn test_code_splitting() {
+    let base = fixture_path().join("split");
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("bundle.js");
+    let map_output = SourceMapOutput::File(dir.path().join("bundle.js.map"), &output);
+    let modules = bundle(&base.join("main.mjs"), InputOptions {
+        es6_syntax: true,
+        ..InputOptions::default()
+    }, output.to_str().unwrap(), &map_output).unwrap().modules;
+    assert_eq!(modules.len(), 6);
+    let main = &modules[&base.join("main.mjs")];
+    assert_eq!(main.dynamic_deps, ["./about.mjs", "./contact.mjs"].iter().map(|s| s.to_string()).collect());
+
+    let bundle_js = fs::read_to_string(&output).unwrap();
+    assert!(bundle_js.contains(&format!("Pax.chunks = {{{:?}: [\"bundle.about.js\", \"bundle.about~contact.js\"]", "about.mjs")));
+    for (chunk, files) in &[
+        ("bundle.about.js", &["about.mjs", "format.js"][..]),
+        ("bundle.about~contact.js", &["common.mjs"][..]),
+        ("bundle.contact.js", &["contact.mjs"][..]),
+    ] {
+        let chunk_js = fs::read_to_string(dir.path().join(chunk)).unwrap();
+        for file in *files {
+            assert!(chunk_js.contains(&format!("Pax.files[{:?}]", file)));
+            assert!(!bundle_js.contains(&format!("Pax.files[{:?}]", file)));
+        }
+        assert!(chunk_js.ends_with(&format!("//# sourceMappingURL={}.map\n", chunk)));
+        let map: serde_json::Value = serde_json::from_reader(fs::File::open(dir.path().join(format!("{}.map", chunk))).unwrap()).unwrap();
+        assert_eq!(map["sources"].as_array().unwrap().len(), files.len());
+    }
+
+    let result = process::Command::new("node")
+        .arg(&output)
+        .output()
+        .expect("failed to run node");
+    if !result.status.success() {
+        io::stderr().write_all(&result.stderr).unwrap();
+        panic!("chunks failed to load");
+    }
+    assert_eq!(String::from_utf8_lossy(&result.stdout), "ok\n");
+}
+
+#[test]
+fn test_entries() {
+    let base = fixture_path().join("entries");
+    let dir = tempfile::tempdir().unwrap();
+    let entries = vec![
+        ("home".to_owned(), base.join("home.js")),
+        ("admin".to_owned(), base.join("admin.mjs")),
+    ];
+    let modules = bundle_entries(&entries, InputOptions {
+        es6_syntax: true,
+        ..InputOptions::default()
+    }, dir.path(), &SourceMapOutput::File(PathBuf::new(), Path::new(""))).unwrap().modules;
+    assert_eq!(modules.len(), 7);
+
+    for (file, modules) in &[
+        ("home.js", &["greeting.js", "home.js"][..]),
+        ("admin.js", &["admin.mjs"][..]),
+        // settings.mjs imports util.js, which home.js requires, so admin.js may need it too
+        ("shared.js", &["lib/format.js", "lib/util.js", "node_modules/vendor/index.js"][..]),
+        ("settings.js", &["settings.mjs"][..]),
+    ] {
+        let js = fs::read_to_string(dir.path().join(file)).unwrap();
+        let files = js.matches("\n  Pax.files[").count();
+        assert_eq!(files, modules.len(), "{} has the wrong modules", file);
+        for module in *modules {
+            assert!(js.contains(&format!("Pax.files[{:?}]", module)), "{} isn't in {}", module, file);
+        }
+        assert!(dir.path().join(format!("{}.map", file)).exists());
+    }
+
+    for (file, expected) in &[("home.js", "vendor home 2\n"), ("admin.js", "vendor admin 4\n")] {
+        let result = process::Command::new("node")
+            .arg(dir.path().join(file))
+            .output()
+            .expect("failed to run node");
+        if !result.status.success() {
+            io::stderr().write_all(&result.stderr).unwrap();
+            panic!("{} failed at runtime", file);
+        }
+        assert_eq!(String::from_utf8_lossy(&result.stdout), *expected);
+    }
+
+    // ES modules are named .mjs, so that node runs them as such
+    let dir = tempfile::tempdir().unwrap();
+    let input_options = InputOptions {
+        es6_syntax: true,
+        ..InputOptions::default()
+    };
+    let cache = Arc::new(PackageCache::new(&input_options));
+    bundle_entries_with_cache(&entries, input_options, &cache, dir.path(), &SourceMapOutput::Suppressed, &OutputOptions { format: OutputFormat::Esm, ..OutputOptions::default() }).unwrap();
+    for file in &["shared.mjs", "settings.mjs"] {
+        assert!(dir.path().join(file).exists(), "{} wasn't written", file);
+    }
+    for (file, expected) in &[("home.mjs", "vendor home 2\n"), ("admin.mjs", "vendor admin 4\n")] {
+        let result = process::Command::new("node")
+            .arg(dir.path().join(file))
+            .output()
+            .expect("failed to run node");
+        if !result.status.success() {
+            io::stderr().write_all(&result.stderr).unwrap();
+            panic!("{} failed at runtime", file);
+        }
+        assert_eq!(String::from_utf8_lossy(&result.stdout), *expected);
+    }
+
+    // an entry point that another entry requires is in the shared chunk, but still runs on its own
+    let dir = tempfile::tempdir().unwrap();
+    let entries = vec![
+        ("a".to_owned(), base.join("required/a.js")),
+        ("b".to_owned(), base.join("required/b.js")),
+    ];
+    bundle_entries(&entries, InputOptions::default(), dir.path(), &SourceMapOutput::Suppressed).unwrap();
+    let shared_js = fs::read_to_string(dir.path().join("shared.js")).unwrap();
+    assert!(shared_js.contains("Pax.files[\"b.js\"]"));
+    for (file, expected) in &[("a.js", "b runs\na sees b\n"), ("b.js", "b runs\n")] {
+        let result = process::Command::new("node")
+            .arg(dir.path().join(file))
+            .output()
+            .expect("failed to run node");
+        if !result.status.success() {
+            io::stderr().write_all(&result.stderr).unwrap();
+            panic!("{} failed at runtime", file);
+        }
+        assert_eq!(String::from_utf8_lossy(&result.stdout), *expected);
+    }
+}
+
+#[test]
+fn test_esm_output() {
+    let base = fixture_path().join("esm-output");
+    let dir = tempfile::tempdir().unwrap();
+    let input_options = InputOptions {
+        es6_syntax: true,
+        external: ["fs", "path"].iter().map(|s| s.to_string()).collect(),
+        ..InputOptions::default()
+    };
+    let cache = Arc::new(PackageCache::new(&input_options));
+    let output = dir.path().join("bundle.mjs");
+    let map_output = SourceMapOutput::File(dir.path().join("bundle.mjs.map"), &output);
+    bundle_with_cache(&base.join("main.mjs"), input_options.clone(), &cache, output.to_str().unwrap(), &map_output, &OutputOptions { format: OutputFormat::Esm, ..OutputOptions::default() }).unwrap();
+    let cjs_output = dir.path().join("cjs.mjs");
+    bundle_with_cache(&base.join("cjs.js"), input_options.clone(), &cache, cjs_output.to_str().unwrap(), &SourceMapOutput::Suppressed, &OutputOptions { format: OutputFormat::Esm, ..OutputOptions::default() }).unwrap();
+
+    let bundle_js = fs::read_to_string(&output).unwrap();
+    assert!(bundle_js.starts_with("import * as __pax_external0 from \"fs\"\nimport * as __pax_external1 from \"path\"\nlet __pax_main\n"));
+    assert!(!bundle_js.contains("module.exports = Pax.main"));
+    let chunk_js = fs::read_to_string(dir.path().join("bundle.lazy.mjs")).unwrap();
+    assert!(chunk_js.contains("export default __pax_chunk\n"));
+    // the first module starts three lines further down than in a script, after the imports
+    let cjs_map_output = SourceMapOutput::File(dir.path().join("bundle.js.map"), &output);
+    bundle_with_cache(&base.join("main.mjs"), input_options.clone(), &cache, dir.path().join("bundle.js").to_str().unwrap(), &cjs_map_output, &OutputOptions::default()).unwrap();
+    let map: serde_json::Value = serde_json::from_reader(fs::File::open(dir.path().join("bundle.mjs.map")).unwrap()).unwrap();
+    let cjs_map: serde_json::Value = serde_json::from_reader(fs::File::open(dir.path().join("bundle.js.map")).unwrap()).unwrap();
+    let first_mapping = |map: &serde_json::Value| map["mappings"].as_str().unwrap().find(|c| c != ';').unwrap();
+    assert_eq!(first_mapping(&map), first_mapping(&cjs_map) + 3);
+
+    let result = process::Command::new("node")
+        .current_dir(dir.path())
+        .arg("--input-type=module")
+        .arg("-e")
+        .arg("\
+            import greet, * as bundle from './bundle.mjs'
+            import cjs from './cjs.mjs'
+            const lazy = await bundle.loadLazy()
+            console.log(greet('you'), bundle.answer, bundle.extra, bundle.canRead, lazy.value, cjs.cjs)
+            console.log(Object.keys(bundle).sort().join(' '))
+        ")
+        .output()
+        .expect("failed to run node");
+    if !result.status.success() {
+        io::stderr().write_all(&result.stderr).unwrap();
+        panic!("ES module bundle failed to load");
+    }
+    assert_eq!(String::from_utf8_lossy(&result.stdout), "hi you 42 extra true value true\nanswer canRead default extra loadLazy\n");
+
+    // imports read the bindings whenever they're used, like in the CommonJS format
+    for (entry, expected) in &[("cycle/a.mjs", "b sees a\n"), ("live/main.mjs", "count 1\n")] {
+        let output = dir.path().join(entry.replace('/', "-"));
+        bundle_with_cache(&base.join(entry), input_options.clone(), &cache, output.to_str().unwrap(), &SourceMapOutput::Suppressed, &OutputOptions { format: OutputFormat::Esm, ..OutputOptions::default() }).unwrap();
+        let result = process::Command::new("node")
+            .arg(&output)
+            .output()
+            .expect("failed to run node");
+        if !result.status.success() {
+            io::stderr().write_all(&result.stderr).unwrap();
+            panic!("{} failed at runtime", entry);
+        }
+        assert_eq!(String::from_utf8_lossy(&result.stdout), *expected, "{}", entry);
+    }
+}
+
+#[test]
+fn test_scope_hoist() {
+    let base = fixture_path().join("hoist");
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("bundle.js");
+    let map_output = SourceMapOutput::File(dir.path().join("bundle.js.map"), &output);
+    let input_options = InputOptions {
+        es6_syntax: true,
+        ..InputOptions::default()
+    };
+    let cache = Arc::new(PackageCache::new(&input_options));
+    bundle_with_cache(&base.join("main.mjs"), input_options.clone(), &cache, output.to_str().unwrap(), &map_output, &OutputOptions { scope_hoist: true, ..OutputOptions::default() }).unwrap();
+
+    let bundle_js = fs::read_to_string(&output).unwrap();
+    // shared.mjs is required by legacy.js, so it keeps its function
+    for (file, wrapped) in &[("main.mjs", true), ("counter.mjs", false), ("shapes.mjs", false), ("shared.mjs", true), ("legacy.js", true)] {
+        assert_eq!(bundle_js.contains(&format!("Pax.files[{:?}]", file)), *wrapped, "{}", file);
+    }
+    assert!(bundle_js.contains("const value$1 = 'shapes'\n"));
+    assert!(bundle_js.contains("const result = {value: value$3, count, shapes: Object.keys(shapes).join(',')}\n"));
+    let map: serde_json::Value = serde_json::from_reader(fs::File::open(dir.path().join("bundle.js.map")).unwrap()).unwrap();
+    assert_eq!(map["sources"].as_array().unwrap().len(), 5);
+
+    let result = process::Command::new("node")
+        .current_dir(dir.path())
+        .arg("-e")
+        .arg("const main = require('./bundle.js'); console.log(Object.keys(main).join(' '), main.squareArea(4))")
+        .output()
+        .expect("failed to run node");
+    if !result.status.success() {
+        io::stderr().write_all(&result.stderr).unwrap();
+        panic!("hoisted bundle failed at runtime");
+    }
+    assert_eq!(String::from_utf8_lossy(&result.stdout), "shapes counter main 2 label,area 9 legacy shared shared\nsquareArea value 16\n");
+
+    // imports from modules that aren't hoisted stay live, except where the name is declared again
+    let base = fixture_path().join("hoist-live");
+    let output = dir.path().join("live.js");
+    bundle_with_cache(&base.join("main.mjs"), input_options, &cache, output.to_str().unwrap(), &SourceMapOutput::Suppressed, &OutputOptions { scope_hoist: true, ..OutputOptions::default() }).unwrap();
+    let bundle_js = fs::read_to_string(&output).unwrap();
+    assert!(!bundle_js.contains("Pax.files[\"next.mjs\"]"));
+    assert!(bundle_js.contains("const count$1 = __pax_import0.count\n"));
+    let result = process::Command::new("node")
+        .arg(&output)
+        .output()
+        .expect("failed to run node");
+    if !result.status.success() {
+        io::stderr().write_all(&result.stderr).unwrap();
+        panic!("hoisted bundle failed at runtime");
+    }
+    assert_eq!(String::from_utf8_lossy(&result.stdout), "legacy count 1 next 1\n");
+}
+
+#[test]
+fn test_umd_output() {
+    let base = fixture_path().join("umd");
+    let dir = tempfile::tempdir().unwrap();
+    let input_options = InputOptions {
+        es6_syntax: true,
+        ..InputOptions::default()
+    };
+    let cache = Arc::new(PackageCache::new(&input_options));
+    for (file, format) in &[
+        ("umd.js", OutputFormat::Umd { global_name: Some("MyOrg.Widget".to_owned()) }),
+        ("iife.js", OutputFormat::Iife { global_name: Some("Widget".to_owned()) }),
+        ("anonymous.js", OutputFormat::Iife { global_name: None }),
+    ] {
+        let output = dir.path().join(file);
+        bundle_with_cache(&base.join("index.mjs"), input_options.clone(), &cache, output.to_str().unwrap(), &SourceMapOutput::Suppressed, &OutputOptions { format: format.clone(), ..OutputOptions::default() }).unwrap();
+    }
+    assert!(fs::read_to_string(dir.path().join("umd.js")).unwrap().contains("global.MyOrg = global.MyOrg || {}, global.MyOrg.Widget = exports"));
+    assert!(!fs::read_to_string(dir.path().join("anonymous.js")).unwrap().contains("Pax.expose"));
+
+    // each script runs in a fresh global scope, with `define` for AMD
+    let result = process::Command::new("node")
+        .current_dir(dir.path())
+        .arg("-e")
+        .arg("\
+            const vm = require('vm'), fs = require('fs')
+            const run = (file, context) => (vm.runInNewContext(fs.readFileSync(file, 'utf8'), context), context)
+            const umd = require('./umd.js')
+            console.log(umd.name, umd.default())
+            console.log(run('umd.js', {}).MyOrg.Widget.default())
+            const amdDefine = (deps, factory) => console.log(deps.length, factory().name)
+            amdDefine.amd = {}
+            run('umd.js', {define: amdDefine})
+            console.log(Object.keys(require('./iife.js')).length, run('iife.js', {}).Widget.name)
+            console.log(Object.keys(run('anonymous.js', {})).length)
+        ")
+        .output()
+        .expect("failed to run node");
+    if !result.status.success() {
+        io::stderr().write_all(&result.stderr).unwrap();
+        panic!("UMD bundle failed to load");
+    }
+    assert_eq!(String::from_utf8_lossy(&result.stdout), "widget hello widget\nhello widget\n0 widget\n0 widget\n0\n");
+}
+
+#[test]
+fn test_project_root() {
+    let base = fixture_path().join("root");
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(PackageCache::new(&InputOptions::default()));
+    for (file, root, expected) in &[
+        // the package root by default
+        ("bundle.js", None, "src/index.js src src/lib/util.js\nsrc/lib/util.js src/lib\nnode_modules/dep\n"),
+        ("src.js", Some(base.join("src")), "index.js . lib/util.js\nlib/util.js lib\n../node_modules/dep\n"),
+    ] {
+        let output = dir.path().join(file);
+        bundle_with_cache(&base.join("src/index.js"), InputOptions::default(), &cache, output.to_str().unwrap(), &SourceMapOutput::Suppressed, &OutputOptions {
+            root: root.clone(),
+            ..OutputOptions::default()
+        }).unwrap();
+        let bundle_js = fs::read_to_string(&output).unwrap();
+        assert!(!bundle_js.contains(base.to_str().unwrap()));
+        // no function is named after an absolute path
+        assert!(!bundle_js.contains("file_$2f"));
+
+        let result = process::Command::new("node")
+            .arg(&output)
+            .output()
+            .expect("failed to run node");
+        if !result.status.success() {
+            io::stderr().write_all(&result.stderr).unwrap();
+            panic!("{} failed at runtime", file);
+        }
+        assert_eq!(String::from_utf8_lossy(&result.stdout), *expected);
+    }
+}
+
+#[test]
+fn test_pnp() {
+    let base = fixture_path().join("pnp");
+    let unplugged = |p: &str| base.join(".yarn/unplugged").join(p);
+    let left_pad = unplugged("left-pad-npm-1.3.0-4a2b1c/node_modules/left-pad");
+    let app = base.join("packages/app/index.js");
+    let resolver = Resolver::new(InputOptions::default());
+
+    assert_eq!(resolver.resolve(&base.join("main.js"), "left-pad").unwrap(), Resolved::Normal(left_pad.join("lib/index.js")));
+    // aliases
+    assert_eq!(resolver.resolve(&app, "pad").unwrap(), Resolved::Normal(left_pad.join("lib/index.js")));
+    assert_eq!(resolver.resolve(&app, "pad/lib/pad").unwrap(), Resolved::Normal(left_pad.join("lib/pad.js")));
+    // the fallback pool
+    assert_eq!(resolver.resolve(&left_pad.join("lib/index.js"), "dep").unwrap(), Resolved::Normal(unplugged("dep-npm-1.0.0-9f8e7d/node_modules/dep/index.js")));
+    // ignorePatternData
+    assert_eq!(resolver.resolve(&base.join("ignored/index.js"), "dep").unwrap(), Resolved::Normal(base.join("ignored/node_modules/dep/index.js")));
+
+    assert_matches!(resolver.resolve(&app, "dep"), Err(CliError::Pnp(pnp::Error { kind: pnp::ErrorKind::NotDeclared { ref name, .. }, .. })) if name == "dep");
+    assert_matches!(resolver.resolve(&app, "left-pad"), Err(CliError::Pnp(pnp::Error { kind: pnp::ErrorKind::NotDeclared { .. }, .. })));
+    assert_matches!(resolver.resolve(&unplugged("react-dom-npm-1.0.0-1a2b3c/node_modules/react-dom/index.js"), "react"), Err(CliError::Pnp(pnp::Error { kind: pnp::ErrorKind::MissingPeer { ref name, .. }, .. })) if name == "react");
+    assert_matches!(resolver.resolve(&app, "zipped"), Err(CliError::Pnp(pnp::Error { kind: pnp::ErrorKind::Zip { .. }, .. })));
+    // core modules needn't be declared
+    assert_matches!(resolver.resolve(&app, "fs"), Err(CliError::ModuleNotFound { .. }));
+
+    // .pnp.cjs
+    let cjs = base.join("cjs");
+    assert_eq!(resolver.resolve(&cjs.join("main.js"), "util-pkg").unwrap(), Resolved::Normal(cjs.join(".yarn/unplugged/util-npm-1.0.0-5e6f7a/node_modules/util-pkg/index.js")));
+}
+
+#[test]
+fn test_package_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = fs::canonicalize(dir.path()).unwrap();
+    let package_json = dir.join("package.json");
+    fs::write(&package_json, r#"{"main": "a.js"}"#).unwrap();
+    fs::write(dir.join("a.js"), "").unwrap();
+    fs::write(dir.join("b.js"), "").unwrap();
+
+    let input_options = InputOptions::default();
+    let cache = Arc::new(PackageCache::new(&input_options));
+    let resolver = Resolver::with_cache(input_options.clone(), cache.clone());
+    let other = Resolver::with_cache(input_options.clone(), cache.clone());
+    let ctx = dir.join("hypothetical.js");
+    assert_eq!(resolver.resolve(&ctx, "./").unwrap(), Resolved::Normal(dir.join("a.js")));
+    assert_eq!(cache.files(), vec![package_json.clone()].into_iter().collect());
+
+    // other resolvers with the same cache don't read package.json again...
+    fs::write(&package_json, r#"{"main": "b.js"}"#).unwrap();
+    assert_eq!(other.resolve(&ctx, "./").unwrap(), Resolved::Normal(dir.join("a.js")));
+
+    // ...until it's invalidated
+    cache.invalidate(&dir.join("unrelated.json"));
+    assert_eq!(other.resolve(&ctx, "./").unwrap(), Resolved::Normal(dir.join("a.js")));
+    cache.invalidate(&package_json);
+    assert!(cache.files().is_empty());
+    assert_eq!(resolver.resolve(&ctx, "./").unwrap(), Resolved::Normal(dir.join("b.js")));
+    assert_eq!(other.resolve(&ctx, "./").unwrap(), Resolved::Normal(dir.join("b.js")));
+
+    // a package.json that didn't exist is looked for again once the watcher reports a change in its directory
+    let sub = dir.join("sub");
+    fs::create_dir(&sub).unwrap();
+    fs::write(sub.join("index.js"), "").unwrap();
+    fs::write(sub.join("c.js"), "").unwrap();
+    assert_eq!(resolver.resolve(&ctx, "./sub").unwrap(), Resolved::Normal(sub.join("index.js")));
+    fs::write(sub.join("package.json"), r#"{"main": "c.js"}"#).unwrap();
+    assert_eq!(resolver.resolve(&ctx, "./sub").unwrap(), Resolved::Normal(sub.join("index.js")));
+    cache.invalidate_dir(&dir);
+    assert_eq!(resolver.resolve(&ctx, "./sub").unwrap(), Resolved::Normal(sub.join("index.js")));
+    cache.invalidate_dir(&sub);
+    assert_eq!(resolver.resolve(&ctx, "./sub").unwrap(), Resolved::Normal(sub.join("c.js")));
+    // but the package.json files that were read stay cached
+    fs::write(&package_json, r#"{"main": "a.js"}"#).unwrap();
+    assert_eq!(resolver.resolve(&ctx, "./").unwrap(), Resolved::Normal(dir.join("b.js")));
+}
+
 fn npm_install(dir: &Path) {
     let node_modules = dir.join("node_modules");
     if node_modules.is_dir() { return }
@@ -3829,6 +5087,7 @@ cfg_if! {
                                     body: include_str!("itt.js").to_owned(),
                                     suffix: "}()".to_owned(),
                                     original: None,
+                                    import_refs: None,
                                 },
                                 deps: {
                                     let mut deps = FnvHashMap::new();
@@ -3840,21 +5099,31 @@ cfg_if! {
                                     ));
                                     deps
                                 },
+                                dynamic_deps: FnvHashSet::default(),
+                                dirs: Vec::new(),
+                                exports: None,
+                                linkage: None,
                             },
                         );
                     }
                     modules
                 },
-                entry_point: Path::new("examples/es6-everywhere-simple/index.js"),
+                entry_points: &[PathBuf::from("examples/es6-everywhere-simple/index.js")],
                 map_output: &SourceMapOutput::Inline,
+                split: Split::default(),
+                format: &OutputFormat::Cjs,
+                root: PathBuf::new(),
+                scopes: Vec::new(),
+                merged: FnvHashSet::default(),
             };
 
             let mut out = Vec::new();
             b.iter(|| {
                 out.clear();
-                writer.write_map_to(&mut out).unwrap();
+                writer.write_map_to(&mut out, Part::Bundle(0)).unwrap();
             });
             b.bytes = out.len() as u64;
         }
     }
 }
+
@@ -0,0 +1,302 @@
+use std::{fmt, fs, io};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use fnv::FnvHashMap;
+use serde_json;
+
+use super::{PathBufExt, PathExt};
+
+/// Config file names, in the order they're looked for in each directory.
+pub const CONFIG_NAMES: &[&str] = &["tsconfig.json", "jsconfig.json"];
+
+/// The module resolution options of a tsconfig.json or jsconfig.json, with its `extends` chain applied.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct TsConfig {
+    pub file: PathBuf,
+    pub base_url: Option<PathBuf>,
+    pub paths: Vec<(String, Vec<String>)>,
+    /// The directory of the config that specified `paths`, which they're relative to when there is no `baseUrl`.
+    pub paths_dir: PathBuf,
+}
+
+impl TsConfig {
+    /// The directory that targets in `paths` are relative to.
+    pub fn paths_base(&self) -> &Path {
+        self.base_url.as_ref().unwrap_or(&self.paths_dir)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TsConfigCache {
+    configs: RefCell<FnvHashMap<PathBuf, Option<Rc<TsConfig>>>>,
+}
+
+impl TsConfigCache {
+    pub fn nearest_config(&self, mut dir: PathBuf) -> Result<Option<Rc<TsConfig>>> {
+        loop {
+            if let Some(config) = self.config(&dir)? {
+                return Ok(Some(config))
+            }
+            if !dir.pop() { return Ok(None) }
+        }
+    }
+
+    pub fn config(&self, dir: &Path) -> Result<Option<Rc<TsConfig>>> {
+        if let Some(config) = self.configs.borrow().get(dir) {
+            return Ok(config.clone())
+        }
+        let config = match CONFIG_NAMES.iter().map(|name| dir.join(name)).find(|path| path.is_file()) {
+            Some(path) => Some(Rc::new(load(&path, &mut Vec::new())?)),
+            None => None,
+        };
+        self.configs.borrow_mut().insert(dir.to_owned(), config.clone());
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RawConfig {
+    extends: Extends,
+    #[serde(rename = "compilerOptions")]
+    compiler_options: RawCompilerOptions,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct RawCompilerOptions {
+    base_url: Option<String>,
+    paths: Option<FnvHashMap<String, Vec<String>>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(untagged)]
+enum Extends {
+    #[default]
+    None,
+    One(String),
+    Many(Vec<String>),
+}
+
+fn load(path: &Path, stack: &mut Vec<PathBuf>) -> Result<TsConfig> {
+    if stack.iter().any(|p| p == path) {
+        stack.push(path.to_owned());
+        return Err(Error::new(path, ErrorKind::ExtendsCycle(stack.clone())))
+    }
+    stack.push(path.to_owned());
+
+    let source = fs::read_to_string(path).map_err(|err| Error::new(path, ErrorKind::Io(err)))?;
+    let raw: RawConfig = serde_json::from_str(&strip_comments(&source))
+        .map_err(|err| Error::new(path, ErrorKind::Json(err)))?;
+    let dir = path.parent().unwrap();
+
+    let mut config = TsConfig::default();
+    let extends = match raw.extends {
+        Extends::None => vec![],
+        Extends::One(name) => vec![name],
+        Extends::Many(names) => names,
+    };
+    // later configs in the list override earlier ones
+    for name in extends {
+        let base_path = resolve_extends(dir, &name).ok_or_else(|| {
+            Error::new(path, ErrorKind::ExtendsNotFound(name.clone()))
+        })?;
+        let base = load(&base_path, stack)?;
+        if base.base_url.is_some() {
+            config.base_url = base.base_url;
+        }
+        if !base.paths.is_empty() {
+            config.paths = base.paths;
+            config.paths_dir = base.paths_dir;
+        }
+    }
+    stack.pop();
+
+    config.file = path.to_owned();
+    if let Some(base_url) = raw.compiler_options.base_url {
+        let mut base_dir = dir.to_owned();
+        base_dir.append_resolving(&base_url);
+        config.base_url = Some(base_dir);
+    }
+    if let Some(paths) = raw.compiler_options.paths {
+        let mut paths = paths.into_iter().collect::<Vec<_>>();
+        paths.sort();
+        config.paths = paths;
+        config.paths_dir = dir.to_owned();
+    }
+    Ok(config)
+}
+
+fn resolve_extends(dir: &Path, name: &str) -> Option<PathBuf> {
+    fn config_file(mut path: PathBuf) -> Option<PathBuf> {
+        if path.is_file() {
+            return Some(path)
+        }
+        if path.is_dir() {
+            path.push("tsconfig.json");
+            return if path.is_file() { Some(path) } else { None }
+        }
+        let mut file_name = path.file_name()?.to_owned();
+        file_name.push(".json");
+        path.set_file_name(file_name);
+        if path.is_file() { Some(path) } else { None }
+    }
+
+    let path = Path::new(name);
+    if path.is_absolute() || path.is_explicitly_relative() {
+        let mut file = dir.to_owned();
+        file.append_resolving(path);
+        return config_file(file)
+    }
+    let mut dir = dir.to_owned();
+    loop {
+        if !matches!(dir.file_name(), Some(s) if s == "node_modules") {
+            let mut file = dir.join("node_modules");
+            file.append_resolving(path);
+            if let Some(file) = config_file(file) {
+                return Some(file)
+            }
+        }
+        if !dir.pop() { return None }
+    }
+}
+
+/// Blanks out comments and trailing commas, which tsconfig.json allows but JSON doesn't.
+fn strip_comments(source: &str) -> String {
+    let bytes = source.as_bytes();
+    let mut result = bytes.to_vec();
+    let mut last_comma = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                last_comma = None;
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    result[i] = b' ';
+                    i += 1;
+                }
+                continue
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let start = i;
+                i += 2;
+                while i + 1 < bytes.len() && !(bytes[i] == b'*' && bytes[i + 1] == b'/') {
+                    i += 1;
+                }
+                i = (i + 2).min(bytes.len());
+                for b in &mut result[start..i] {
+                    if *b != b'\n' {
+                        *b = b' ';
+                    }
+                }
+                continue
+            }
+            b',' => last_comma = Some(i),
+            b'}' | b']' => {
+                if let Some(comma) = last_comma.take() {
+                    result[comma] = b' ';
+                }
+            }
+            b' ' | b'\t' | b'\r' | b'\n' => {}
+            _ => last_comma = None,
+        }
+        i += 1;
+    }
+    // only ASCII bytes outside of strings were replaced with ASCII spaces
+    String::from_utf8(result).unwrap()
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub struct Error {
+    pub path: PathBuf,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Io(io::Error),
+    Json(serde_json::Error),
+    ExtendsNotFound(String),
+    ExtendsCycle(Vec<PathBuf>),
+}
+
+impl Error {
+    fn new(path: &Path, kind: ErrorKind) -> Self {
+        Error {
+            path: path.to_owned(),
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "in {}: {}", self.path.display(), self.kind)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::Io(ref inner) => {
+                write!(f, "{}", inner)
+            }
+            ErrorKind::Json(ref inner) => {
+                write!(f, "{}", inner)
+            }
+            ErrorKind::ExtendsNotFound(ref name) => {
+                write!(f, "config '{}' in `extends` not found", name)
+            }
+            ErrorKind::ExtendsCycle(ref stack) => {
+                write!(f, "`extends` cycle: ")?;
+                for (i, path) in stack.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " -> ")?;
+                    }
+                    write!(f, "{}", path.display())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strip_comments() {
+        assert_eq!(strip_comments(r#"{}"#), r#"{}"#);
+        assert_eq!(strip_comments(r#"{"a": 1} // x"#), r#"{"a": 1}     "#);
+        assert_eq!(strip_comments("{\n  // x\n  \"a\": 1\n}"), "{\n      \n  \"a\": 1\n}");
+        assert_eq!(strip_comments(r#"{/* x */"a": 1}"#), r#"{       "a": 1}"#);
+        assert_eq!(strip_comments("/* x\ny */{}"), "    \n    {}");
+        assert_eq!(strip_comments(r#"{/**/"a": 1}"#), r#"{    "a": 1}"#);
+        assert_eq!(strip_comments(r#"{"a": "//", "b": "/*"}"#), r#"{"a": "//", "b": "/*"}"#);
+        assert_eq!(strip_comments(r#"{"a": "\"//"}"#), r#"{"a": "\"//"}"#);
+        assert_eq!(strip_comments(r#"{"a": [1, 2,], "b": 3,}"#), r#"{"a": [1, 2 ], "b": 3 }"#);
+        assert_eq!(strip_comments("{\"a\": 1, // x\n}"), "{\"a\": 1      \n}");
+        assert_eq!(strip_comments(r#"{"a": ",}"}"#), r#"{"a": ",}"}"#);
+    }
+
+    #[test]
+    fn test_deserialize_extends() {
+        let parse = |s| serde_json::from_str::<RawConfig>(s).unwrap().extends;
+        assert_matches!(parse(r#"{}"#), Extends::None);
+        assert_matches!(parse(r#"{"extends": "./base"}"#), Extends::One(ref s) if s == "./base");
+        assert_matches!(parse(r#"{"extends": ["./a", "b"]}"#), Extends::Many(ref v) if v.len() == 2);
+    }
+}